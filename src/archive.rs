@@ -1,17 +1,170 @@
 //! Archive creation for various formats.
 
+use crate::checksum::{FileHasher, HashingReader, SumsFile};
+use crate::incremental;
+use crate::owner::Ownership;
+use crate::parallel::ParallelEncoder;
+use crate::sparse::{self, Region, RegionReader};
 use crate::walk::{EntryType, FileEntry};
+use crate::{ChecksumAlgorithm, TarFormat, ZipMethod, ZipSymlinks};
 
 use anyhow::Result;
+use brotli::CompressorWriter as BrotliEncoder;
 use bzip2::write::BzEncoder;
-use flate2::write::GzEncoder;
+use colored::Colorize;
+use flate2::write::{DeflateEncoder, GzEncoder};
 use flate2::Compression as GzCompression;
 use indicatif::{ProgressBar, ProgressStyle};
-use std::fs::File;
-use std::io::{self, Write};
+use liblzma::write::XzEncoder;
+use lz4::EncoderBuilder as Lz4EncoderBuilder;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
 use time::OffsetDateTime;
+use zip::write::FileOptions;
+use zip::{AesMode, CompressionMethod};
 use zstd::stream::write::Encoder as ZstdEncoder;
 
+/// Compression tuning for the compressed formats.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompressionOptions {
+    /// Compression level; `None` uses the format's default
+    pub level: Option<i32>,
+    /// Enable zstd long-distance matching
+    pub zstd_long: bool,
+    /// zstd window size as a power of two (e.g., 27 = 128 MiB)
+    pub zstd_window_log: Option<u32>,
+    /// Worker threads for gzip, bzip2 and zstd; `None` compresses on one thread
+    pub threads: Option<u32>,
+}
+
+impl CompressionOptions {
+    /// Returns the level as unsigned, falling back to `default`.
+    fn level_or(&self, default: u32) -> u32 {
+        self.level
+            .and_then(|l| u32::try_from(l).ok())
+            .unwrap_or(default)
+    }
+
+    /// Returns the worker count when more than one thread was requested.
+    fn parallel_threads(&self) -> Option<u32> {
+        self.threads.filter(|&n| n > 1)
+    }
+}
+
+/// Settings for zip members.
+#[derive(Debug, Clone, Default)]
+pub struct ZipOptions {
+    /// Compression method for members that are not stored
+    pub method: ZipMethod,
+    /// How symlinks are written
+    pub symlinks: ZipSymlinks,
+    /// Lowercase extensions (without the dot) that are always stored
+    pub store_extensions: Vec<String>,
+    /// Store members whose sample does not compress
+    pub store_if_larger: bool,
+    /// Store normalized permissions (see [`normalized_mode`])
+    pub normalize_modes: bool,
+    /// Add a sums member with the digest of every file
+    pub embed_checksums: Option<ChecksumAlgorithm>,
+    /// Contents of the deletion list member of an incremental archive
+    pub deleted: Option<Vec<u8>>,
+    /// Encrypt every member with `WinZip` AES-256 using this password
+    pub password: Option<String>,
+}
+
+impl ZipOptions {
+    /// Returns the base options for a member modified at `mtime`.
+    fn member_options(&self, mtime: u64) -> FileOptions<'_, ()> {
+        let options = FileOptions::default().last_modified_time(zip_datetime(mtime));
+        self.password.as_ref().map_or(options, |password| {
+            options.with_aes_encryption(AesMode::Aes256, password)
+        })
+    }
+}
+
+/// Bytes of each file compressed to decide whether compression helps.
+const ZIP_SAMPLE_SIZE: u64 = 256 * 1024;
+
+/// A writer that only counts the bytes written to it.
+#[derive(Default)]
+struct CountingSink(u64);
+
+impl Write for CountingSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns true if compressing the start of the file saves space.
+fn sample_compresses(path: &Path, method: ZipMethod, level: Option<i32>) -> io::Result<bool> {
+    let mut sample = Vec::new();
+    File::open(path)?
+        .take(ZIP_SAMPLE_SIZE)
+        .read_to_end(&mut sample)?;
+    if sample.is_empty() {
+        return Ok(false);
+    }
+
+    let level = level.unwrap_or(match method {
+        ZipMethod::Zstd => 3,
+        _ => 6,
+    });
+    let unsigned = u32::try_from(level).unwrap_or(6);
+    let compressed = match method {
+        ZipMethod::Store => return Ok(false),
+        ZipMethod::Deflate => {
+            let mut encoder =
+                DeflateEncoder::new(CountingSink::default(), GzCompression::new(unsigned));
+            encoder.write_all(&sample)?;
+            encoder.finish()?.0
+        }
+        ZipMethod::Bzip2 => {
+            let mut encoder =
+                BzEncoder::new(CountingSink::default(), bzip2::Compression::new(unsigned));
+            encoder.write_all(&sample)?;
+            encoder.finish()?.0
+        }
+        ZipMethod::Zstd => {
+            let mut encoder = ZstdEncoder::new(CountingSink::default(), level)?;
+            encoder.write_all(&sample)?;
+            encoder.finish()?.0
+        }
+    };
+    Ok(compressed < sample.len() as u64)
+}
+
+/// Chooses the compression method for one file in a zip archive.
+fn zip_file_method(
+    path: &Path,
+    name: &Path,
+    zip: &ZipOptions,
+    level: Option<i32>,
+) -> io::Result<ZipMethod> {
+    if zip.method == ZipMethod::Store {
+        return Ok(ZipMethod::Store);
+    }
+
+    let extension = name.extension().map(|e| e.to_string_lossy().to_lowercase());
+    if extension.is_some_and(|e| zip.store_extensions.contains(&e)) {
+        return Ok(ZipMethod::Store);
+    }
+
+    if zip.store_if_larger && !sample_compresses(path, zip.method, level)? {
+        return Ok(ZipMethod::Store);
+    }
+    Ok(zip.method)
+}
+
 /// Creates a progress bar for archive operations.
 pub fn create_progress_bar(len: u64, quiet: bool, verbose: bool) -> Option<ProgressBar> {
     if !quiet && !verbose {
@@ -28,65 +181,510 @@ pub fn create_progress_bar(len: u64, quiet: bool, verbose: bool) -> Option<Progr
     }
 }
 
+/// Settings for tar headers.
+#[derive(Debug, Clone, Default)]
+pub struct TarOptions {
+    /// Header format
+    pub format: TarFormat,
+    /// Zero timestamps and ownership
+    pub reproducible: bool,
+    /// Owner ids and names to store
+    pub owners: Ownership,
+    /// Store normalized permissions (see [`normalized_mode`])
+    pub normalize_modes: bool,
+    /// Add a sums member with the digest of every file
+    pub embed_checksums: Option<ChecksumAlgorithm>,
+    /// Contents of the deletion list member of an incremental archive
+    pub deleted: Option<Vec<u8>>,
+}
+
+/// Returns the permissions stored when modes are normalized: 0755 for
+/// directories and for files with any execute bit, 0644 for everything else.
+/// Setuid, setgid and sticky bits are dropped, so the umask and ownership
+/// tricks of the machine that built the archive do not show.
+pub const fn normalized_mode(entry_type: EntryType, mode: u32) -> u32 {
+    match entry_type {
+        EntryType::Symlink => 0o777,
+        EntryType::Directory => 0o755,
+        _ if mode & 0o111 != 0 => 0o755,
+        _ => 0o644,
+    }
+}
+
+/// Size of the ustar `name` field, also the whole name in v7.
+const USTAR_NAME_MAX: usize = 100;
+
+/// Size of the ustar `prefix` field.
+const USTAR_PREFIX_MAX: usize = 155;
+
+/// Longest user or group name the ustar fields hold, leaving room for the NUL.
+const OWNER_NAME_MAX: usize = 31;
+
+/// Largest value of an 8-byte octal field (uid, gid).
+const OCTAL8_MAX: u64 = 0o777_7777;
+
+/// Largest value of a 12-byte octal field (size): 8 GiB - 1.
+const OCTAL12_MAX: u64 = 0o777_7777_7777;
+
+/// Returns the member name for an entry; directories get a trailing slash,
+/// as GNU tar writes them.
+fn member_name(entry: &FileEntry) -> PathBuf {
+    if entry.entry_type == EntryType::Directory {
+        entry.relative_path.join("")
+    } else {
+        entry.relative_path.clone()
+    }
+}
+
+/// Returns true if `name` fits the ustar `name` field, or can be split at a
+/// `/` into `prefix` and `name`.
+fn fits_ustar_name(name: &[u8]) -> bool {
+    name.len() <= USTAR_NAME_MAX
+        || name.iter().enumerate().any(|(i, &b)| {
+            b == b'/'
+                && i <= USTAR_PREFIX_MAX
+                && (1..=USTAR_NAME_MAX).contains(&(name.len() - i - 1))
+        })
+}
+
+/// Lists every entry the ustar or v7 format cannot represent.
+///
+/// Returns one message per problem; other formats have none.
+pub fn tar_format_problems(entries: &[FileEntry], tar: &TarOptions) -> Vec<String> {
+    let (format_name, name_limit) = match tar.format {
+        TarFormat::Gnu | TarFormat::Pax => return Vec::new(),
+        TarFormat::Ustar => ("ustar", "100 bytes, or 155 + 100 split at a /"),
+        TarFormat::V7 => ("v7", "100 bytes"),
+    };
+
+    let mut problems = Vec::new();
+    let mut hard_links: HashMap<(u64, u64), &Path> = HashMap::new();
+    for entry in entries {
+        let path = entry.relative_path.display();
+        let name = member_name(entry);
+        let name = name.as_os_str().as_bytes();
+        let fits = if tar.format == TarFormat::Ustar {
+            fits_ustar_name(name)
+        } else {
+            name.len() <= USTAR_NAME_MAX
+        };
+        if !fits {
+            problems.push(format!(
+                "{path}: name is {} bytes ({format_name} allows {name_limit})",
+                name.len()
+            ));
+        }
+
+        let link = match entry.entry_type {
+            EntryType::Symlink => entry.link_target.as_deref(),
+            EntryType::File => entry
+                .hard_link_key
+                .and_then(|key| match hard_links.entry(key) {
+                    Entry::Occupied(first) => Some(*first.get()),
+                    Entry::Vacant(slot) => {
+                        slot.insert(&entry.relative_path);
+                        None
+                    }
+                }),
+            _ => None,
+        };
+        if let Some(link) = link {
+            let len = link.as_os_str().len();
+            if len > USTAR_NAME_MAX {
+                problems.push(format!(
+                    "{path}: link target is {len} bytes ({format_name} allows {USTAR_NAME_MAX})"
+                ));
+            }
+        }
+
+        if entry.size > OCTAL12_MAX {
+            problems.push(format!(
+                "{path}: size {} exceeds the 8 GiB {format_name} limit",
+                entry.size
+            ));
+        }
+        let owner = tar.owners.resolve(entry.uid, entry.gid);
+        for (field, id) in [("uid", owner.uid), ("gid", owner.gid)] {
+            if u64::from(id) > OCTAL8_MAX {
+                problems.push(format!(
+                    "{path}: {field} {id} exceeds the {format_name} limit of {OCTAL8_MAX}"
+                ));
+            }
+        }
+        if tar.format == TarFormat::Ustar {
+            for (field, name) in [("user", &owner.uname), ("group", &owner.gname)] {
+                if let Some(name) = name.as_deref().filter(|n| n.len() > OWNER_NAME_MAX) {
+                    problems.push(format!(
+                        "{path}: {field} name {name:?} is longer than {OWNER_NAME_MAX} bytes"
+                    ));
+                }
+            }
+        }
+    }
+    problems
+}
+
+/// Creates an empty header in the given format.
+fn new_header(format: TarFormat) -> tar::Header {
+    match format {
+        TarFormat::Gnu => tar::Header::new_gnu(),
+        TarFormat::Pax | TarFormat::Ustar => tar::Header::new_ustar(),
+        TarFormat::V7 => tar::Header::new_old(),
+    }
+}
+
 /// Sets tar header metadata from a file entry.
-pub fn set_header_metadata(
+pub fn set_header_metadata(header: &mut tar::Header, entry: &FileEntry, tar: &TarOptions) {
+    // Reproducible and --mtime runs already rewrote the entry's mtime
+    header.set_mtime(entry.mtime);
+    let owner = tar.owners.resolve(entry.uid, entry.gid);
+    header.set_uid(u64::from(owner.uid));
+    header.set_gid(u64::from(owner.gid));
+    set_owner_names(header, owner.uname.as_deref(), owner.gname.as_deref());
+    if tar.normalize_modes {
+        header.set_mode(normalized_mode(entry.entry_type, entry.mode));
+    } else {
+        header.set_mode(entry.mode);
+    }
+}
+
+/// Fills the user and group name fields; v7 headers have none.
+///
+/// Names longer than the field are cut short here; the pax format also
+/// carries them in full as PAX records.
+fn set_owner_names(header: &mut tar::Header, uname: Option<&str>, gname: Option<&str>) {
+    let (uname_field, gname_field) = if let Some(ustar) = header.as_ustar_mut() {
+        (&mut ustar.uname, &mut ustar.gname)
+    } else if let Some(gnu) = header.as_gnu_mut() {
+        (&mut gnu.uname, &mut gnu.gname)
+    } else {
+        return;
+    };
+    // The fields are NUL-terminated strings
+    for (field, name) in [(uname_field, uname), (gname_field, gname)] {
+        let name = name.unwrap_or_default().as_bytes();
+        set_truncated(field, &name[..name.len().min(OWNER_NAME_MAX)]);
+    }
+}
+
+/// Builds the PAX records for one member.
+///
+/// Every format gets the entry's extended attributes, as `SCHILY.xattr.<name>`
+/// keys understood by GNU tar and bsdtar. The pax format also records each
+/// field the ustar header cannot hold exactly: long or non-ASCII names, ids
+/// and sizes too large for octal, and sub-second mtimes, plus the
+/// `GNU.sparse.*` records of sparse files.
+fn pax_records(
+    entry: &FileEntry,
+    name: &Path,
+    link: Option<&Path>,
+    size: u64,
+    tar: &TarOptions,
+) -> Vec<(String, Vec<u8>)> {
+    let mut records: Vec<(String, Vec<u8>)> = entry
+        .xattrs
+        .iter()
+        .map(|(key, value)| (format!("SCHILY.xattr.{key}"), value.clone()))
+        .collect();
+    if tar.format != TarFormat::Pax {
+        return records;
+    }
+
+    if entry.sparse.is_some() {
+        records.extend(sparse::pax_records(&entry.relative_path, entry.size));
+    }
+    let name = name.as_os_str().as_bytes();
+    if !name.is_ascii() || !fits_ustar_name(name) {
+        records.push(("path".to_string(), name.to_vec()));
+    }
+    if let Some(link) = link.map(|l| l.as_os_str().as_bytes()) {
+        if !link.is_ascii() || link.len() > USTAR_NAME_MAX {
+            records.push(("linkpath".to_string(), link.to_vec()));
+        }
+    }
+    let owner = tar.owners.resolve(entry.uid, entry.gid);
+    for (key, id) in [("uid", owner.uid), ("gid", owner.gid)] {
+        if u64::from(id) > OCTAL8_MAX {
+            records.push((key.to_string(), id.to_string().into_bytes()));
+        }
+    }
+    for (key, name) in [("uname", owner.uname), ("gname", owner.gname)] {
+        if let Some(name) = name.filter(|n| !n.is_ascii() || n.len() > OWNER_NAME_MAX) {
+            records.push((key.to_string(), name.into_bytes()));
+        }
+    }
+    if size > OCTAL12_MAX {
+        records.push(("size".to_string(), size.to_string().into_bytes()));
+    }
+    if !tar.reproducible && entry.mtime_nsec != 0 {
+        let mtime = format!("{}.{:09}", entry.mtime, entry.mtime_nsec);
+        records.push(("mtime".to_string(), mtime.into_bytes()));
+    }
+    records
+}
+
+/// Copies as much of `value` as fits into a fixed-size header field.
+///
+/// Used for fields whose full value is carried by a PAX record.
+fn set_truncated(field: &mut [u8], value: &[u8]) {
+    let len = value.len().min(field.len());
+    field.fill(0);
+    field[..len].copy_from_slice(&value[..len]);
+}
+
+/// Writes one member: its PAX records, then the header and data.
+///
+/// GNU, ustar and v7 headers go through the builder, which falls back to GNU
+/// long-name entries. Pax headers are filled in here instead, so anything
+/// that does not fit is carried only by the PAX records.
+fn append_member<W: Write, R: Read>(
+    builder: &mut tar::Builder<W>,
     header: &mut tar::Header,
     entry: &FileEntry,
-    reproducible: bool,
-    preserve_owner: bool,
-) {
-    if reproducible {
-        // Reproducible mode: normalize everything
-        header.set_mtime(0);
-        header.set_uid(0);
-        header.set_gid(0);
+    link: Option<&Path>,
+    data: R,
+    tar: &TarOptions,
+) -> io::Result<()> {
+    let mut name = member_name(entry);
+    if tar.format == TarFormat::Pax && entry.sparse.is_some() {
+        name = sparse::pax_member_name(&name);
+    }
+    let records = pax_records(entry, &name, link, header.entry_size()?, tar);
+    if !records.is_empty() {
+        builder.append_pax_extensions(
+            records
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_slice())),
+        )?;
+    }
+
+    if tar.format != TarFormat::Pax {
+        return match link {
+            Some(link) => builder.append_link(header, &name, link),
+            None => builder.append_data(header, &name, data),
+        };
+    }
+
+    let has = |key: &str| records.iter().any(|(k, _)| k == key);
+    if has("path") {
+        set_truncated(&mut header.as_old_mut().name, name.as_os_str().as_bytes());
     } else {
-        header.set_mtime(entry.mtime);
-        if preserve_owner {
-            header.set_uid(u64::from(entry.uid));
-            header.set_gid(u64::from(entry.gid));
+        header.set_path(&name)?;
+    }
+    if let Some(link) = link {
+        if has("linkpath") {
+            set_truncated(
+                &mut header.as_old_mut().linkname,
+                link.as_os_str().as_bytes(),
+            );
         } else {
-            header.set_uid(0);
-            header.set_gid(0);
+            header.set_link_name(link)?;
         }
     }
-    header.set_mode(entry.mode);
+    if has("uid") {
+        header.set_uid(0);
+    }
+    if has("gid") {
+        header.set_gid(0);
+    }
+    if has("size") {
+        header.set_size(0);
+    }
+    header.set_cksum();
+    builder.append(header, data)
+}
+
+/// Writes a file with holes, storing only its data regions, which `data`
+/// yields back to back.
+///
+/// The GNU format lists the regions in the header; pax writes a PAX 1.0
+/// sparse member (see [`crate::sparse`]).
+fn append_sparse<W: Write>(
+    builder: &mut tar::Builder<W>,
+    header: &mut tar::Header,
+    entry: &FileEntry,
+    regions: &[Region],
+    data: impl Read,
+    tar: &TarOptions,
+) -> io::Result<()> {
+    let data_size = sparse::data_size(regions);
+
+    if tar.format == TarFormat::Pax {
+        let map = sparse::pax_map(regions, entry.size);
+        header.set_size(map.len() as u64 + data_size);
+        append_member(
+            builder,
+            header,
+            entry,
+            None,
+            map.as_slice().chain(data),
+            tar,
+        )
+    } else {
+        header.set_entry_type(tar::EntryType::GNUSparse);
+        header.set_size(data_size);
+        let extensions = sparse::gnu_sparse_header(header, regions, entry.size)?;
+        let data = extensions.as_slice().chain(data);
+        append_member(builder, header, entry, None, data, tar)
+    }
+}
+
+/// Writes a FIFO or device node, which has a header but no data.
+fn append_special<W: Write>(
+    builder: &mut tar::Builder<W>,
+    header: &mut tar::Header,
+    entry: &FileEntry,
+    tar: &TarOptions,
+) -> io::Result<()> {
+    header.set_entry_type(match entry.entry_type {
+        EntryType::CharDevice => tar::EntryType::Char,
+        EntryType::BlockDevice => tar::EntryType::Block,
+        _ => tar::EntryType::Fifo,
+    });
+    if let Some((major, minor)) = entry.device {
+        header.set_device_major(major)?;
+        header.set_device_minor(minor)?;
+    }
+    append_member(builder, header, entry, None, io::empty(), tar)
+}
+
+/// Appends a member raptar generates, such as the sums file.
+fn append_generated<W: Write>(
+    builder: &mut tar::Builder<W>,
+    entry: &FileEntry,
+    contents: &[u8],
+    tar: &TarOptions,
+) -> io::Result<()> {
+    let mut header = new_header(tar.format);
+    set_header_metadata(&mut header, entry, tar);
+    header.set_size(entry.size);
+    append_member(builder, &mut header, entry, None, contents, tar)
+}
+
+/// Writes a regular file's content, recording its digest in `sums`.
+fn append_file<W: Write>(
+    builder: &mut tar::Builder<W>,
+    header: &mut tar::Header,
+    entry: &FileEntry,
+    sums: Option<&mut SumsFile>,
+    tar: &TarOptions,
+) -> io::Result<()> {
+    let mut hasher = sums
+        .as_ref()
+        .map(|sums| FileHasher::new(sums.algorithm(), entry.size, entry.sparse.as_deref()));
+    let file = File::open(&entry.path)?;
+    if let Some(ref regions) = entry.sparse {
+        let data = HashingReader::new(RegionReader::new(file, regions), hasher.as_mut());
+        append_sparse(builder, header, entry, regions, data, tar)?;
+    } else {
+        header.set_size(entry.size);
+        let data = HashingReader::new(file, hasher.as_mut());
+        append_member(builder, header, entry, None, data, tar)?;
+    }
+    if let (Some(sums), Some(hasher)) = (sums, hasher) {
+        sums.add(entry, hasher.finish());
+    }
+    Ok(())
 }
 
 /// Creates a tar archive from the given entries.
-#[allow(clippy::fn_params_excessive_bools)]
+///
+/// Files sharing an inode are stored once; later paths are written as hard
+/// links to the first one archived, so links whose first path was excluded
+/// still get the content.
+///
+/// Returns the underlying writer so compressors can be finished explicitly.
 pub fn create_tar<W: Write>(
     writer: W,
     entries: &[FileEntry],
-    reproducible: bool,
-    preserve_owner: bool,
+    tar: &TarOptions,
     quiet: bool,
     verbose: bool,
-) -> Result<()> {
+) -> Result<W> {
     let mut builder = tar::Builder::new(writer);
     let progress = create_progress_bar(entries.len() as u64, quiet, verbose);
+    // First archived path for each multiply-linked inode
+    let mut hard_links: HashMap<(u64, u64), &Path> = HashMap::new();
+    let mut sums = tar.embed_checksums.map(SumsFile::new);
+
+    // The deletion list goes first so a restore can clear the old paths
+    // before members of another type take their place
+    if let Some(ref deleted) = tar.deleted {
+        let entry = incremental::deleted_entry(deleted, entries);
+        append_generated(&mut builder, &entry, deleted, tar)?;
+    }
 
     for entry in entries {
+        let mut header = new_header(tar.format);
+        set_header_metadata(&mut header, entry, tar);
+        header.set_size(0);
+
         match entry.entry_type {
             EntryType::Symlink => {
                 if let Some(ref target) = entry.link_target {
-                    let mut header = tar::Header::new_gnu();
                     header.set_entry_type(tar::EntryType::Symlink);
-                    set_header_metadata(&mut header, entry, reproducible, preserve_owner);
-                    header.set_size(0);
-                    builder.append_link(&mut header, &entry.relative_path, target)?;
+                    append_member(
+                        &mut builder,
+                        &mut header,
+                        entry,
+                        Some(target),
+                        io::empty(),
+                        tar,
+                    )?;
                 }
             }
             EntryType::File => {
-                let file = File::open(&entry.path)?;
-                let mut header = tar::Header::new_gnu();
-                header.set_size(entry.size);
-                set_header_metadata(&mut header, entry, reproducible, preserve_owner);
-                builder.append_data(&mut header, &entry.relative_path, file)?;
+                // Later paths to an already archived inode become hard links
+                let first = entry
+                    .hard_link_key
+                    .and_then(|key| match hard_links.entry(key) {
+                        Entry::Occupied(first) => Some(*first.get()),
+                        Entry::Vacant(slot) => {
+                            slot.insert(&entry.relative_path);
+                            None
+                        }
+                    });
+
+                if let Some(first) = first {
+                    if let Some(ref mut sums) = sums {
+                        sums.add_hard_link(entry);
+                    }
+                    header.set_entry_type(tar::EntryType::Link);
+                    append_member(
+                        &mut builder,
+                        &mut header,
+                        entry,
+                        Some(first),
+                        io::empty(),
+                        tar,
+                    )?;
+                } else {
+                    append_file(&mut builder, &mut header, entry, sums.as_mut(), tar)?;
+                }
             }
             EntryType::Directory => {
-                // Skip directories for now
+                header.set_entry_type(tar::EntryType::Directory);
+                append_member(&mut builder, &mut header, entry, None, io::empty(), tar)?;
+            }
+            EntryType::Fifo | EntryType::CharDevice | EntryType::BlockDevice
+                if tar.format == TarFormat::V7 =>
+            {
+                skip_entry(
+                    &entry.relative_path,
+                    entry.entry_type.name(),
+                    "v7 tar cannot store special files",
+                    quiet,
+                );
+            }
+            EntryType::Fifo | EntryType::CharDevice | EntryType::BlockDevice => {
+                append_special(&mut builder, &mut header, entry, tar)?;
             }
+            EntryType::Socket => skip_entry(
+                &entry.relative_path,
+                "socket",
+                "tar cannot store sockets",
+                quiet,
+            ),
         }
 
         if let Some(ref pb) = progress {
@@ -98,70 +696,204 @@ pub fn create_tar<W: Write>(
         pb.finish_and_clear();
     }
 
-    builder.finish()?;
+    if let Some(sums) = sums {
+        append_generated(&mut builder, &sums.entry(entries), sums.contents(), tar)?;
+    }
+    Ok(builder.into_inner()?)
+}
+
+/// Converts a Unix timestamp to a zip timestamp.
+///
+/// Times before 1980, which zip cannot represent, become 1980-01-01.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn zip_datetime(mtime: u64) -> zip::DateTime {
+    // Convert Unix timestamp to zip DateTime components
+    #[allow(clippy::cast_possible_wrap)]
+    let dt =
+        OffsetDateTime::from_unix_timestamp(mtime as i64).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+    zip::DateTime::from_date_and_time(
+        dt.year() as u16,
+        dt.month() as u8,
+        dt.day(),
+        dt.hour(),
+        dt.minute(),
+        dt.second(),
+    )
+    .unwrap_or_default()
+}
+
+/// Returns zip options for a file member compressed with `method`.
+fn zip_file_options(
+    method: ZipMethod,
+    level: Option<i32>,
+    base: FileOptions<'_, ()>,
+) -> FileOptions<'_, ()> {
+    let level = level.map(i64::from);
+    match method {
+        ZipMethod::Store => base.compression_method(CompressionMethod::Stored),
+        ZipMethod::Deflate => base
+            .compression_method(CompressionMethod::Deflated)
+            .compression_level(level),
+        ZipMethod::Bzip2 => base
+            .compression_method(CompressionMethod::Bzip2)
+            .compression_level(level),
+        ZipMethod::Zstd => base
+            .compression_method(CompressionMethod::Zstd)
+            .compression_level(level),
+    }
+}
+
+/// Warns about an entry left out of an archive.
+fn skip_entry(path: &Path, kind: &str, reason: &str, quiet: bool) {
+    if !quiet {
+        eprintln!(
+            "{} Skipping {} {}: {}",
+            "⚠".yellow(),
+            kind,
+            path.display(),
+            reason
+        );
+    }
+}
+
+/// Writes a zip member raptar generates, such as the sums file.
+fn write_generated<W: Write + io::Seek>(
+    zip: &mut zip::ZipWriter<W>,
+    entry: &FileEntry,
+    contents: &[u8],
+    zip_options: &ZipOptions,
+) -> Result<()> {
+    let options = zip_options
+        .member_options(entry.mtime)
+        .unix_permissions(0o644);
+    zip.start_file(&*entry.relative_path.to_string_lossy(), options)?;
+    zip.write_all(contents)?;
+    Ok(())
+}
+
+/// Copies a file into the current zip member, recording its digest in `sums`.
+fn copy_hashed<W: Write + io::Seek>(
+    path: &Path,
+    entry: &FileEntry,
+    size: u64,
+    sums: &mut Option<SumsFile>,
+    zip: &mut zip::ZipWriter<W>,
+) -> io::Result<()> {
+    let mut hasher = sums
+        .as_ref()
+        .map(|sums| FileHasher::new(sums.algorithm(), size, None));
+    let mut file = HashingReader::new(File::open(path)?, hasher.as_mut());
+    io::copy(&mut file, zip)?;
+    if let (Some(sums), Some(hasher)) = (sums.as_mut(), hasher) {
+        sums.add(entry, hasher.finish());
+    }
     Ok(())
 }
 
 /// Creates a zip archive from the given entries.
-#[allow(clippy::fn_params_excessive_bools)]
+///
+/// Symlinks are stored as real Unix symlinks, replaced by the file they point
+/// to, or left out, depending on the symlink policy. Files with an extension
+/// on the store list, or whose sample does not compress, are stored as-is.
+/// With a password, every member is encrypted with `WinZip` AES-256.
+/// Returns the writer the zip was given, which may be a streaming writer.
 pub fn create_zip<W: Write + io::Seek>(
-    writer: W,
+    mut zip: zip::ZipWriter<W>,
     entries: &[FileEntry],
-    reproducible: bool,
+    compression: &CompressionOptions,
+    zip_options: &ZipOptions,
     quiet: bool,
     verbose: bool,
-) -> Result<()> {
-    use zip::write::SimpleFileOptions;
-    use zip::CompressionMethod;
-
-    let mut zip = zip::ZipWriter::new(writer);
+) -> Result<W> {
     let progress = create_progress_bar(entries.len() as u64, quiet, verbose);
+    let mut sums = zip_options.embed_checksums.map(SumsFile::new);
+    let permissions = |entry_type: EntryType, mode: u32| {
+        if zip_options.normalize_modes {
+            normalized_mode(entry_type, mode)
+        } else {
+            mode & 0o7777
+        }
+    };
+
+    // The deletion list goes first, as in tar
+    if let Some(ref deleted) = zip_options.deleted {
+        let entry = incremental::deleted_entry(deleted, entries);
+        write_generated(&mut zip, &entry, deleted, zip_options)?;
+    }
 
     for entry in entries {
         let path_str = entry.relative_path.to_string_lossy();
 
-        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
-        let last_modified = if reproducible {
-            zip::DateTime::default()
-        } else {
-            // Convert Unix timestamp to zip DateTime components
-            #[allow(clippy::cast_possible_wrap)]
-            let dt = OffsetDateTime::from_unix_timestamp(entry.mtime as i64)
-                .unwrap_or(OffsetDateTime::UNIX_EPOCH);
-            #[allow(clippy::cast_sign_loss)]
-            zip::DateTime::from_date_and_time(
-                dt.year() as u16,
-                dt.month() as u8,
-                dt.day(),
-                dt.hour(),
-                dt.minute(),
-                dt.second(),
-            )
-            .unwrap_or_default()
-        };
-
-        let options = SimpleFileOptions::default()
-            .compression_method(CompressionMethod::Deflated)
-            .last_modified_time(last_modified);
+        let options = zip_options.member_options(entry.mtime);
 
         match entry.entry_type {
             EntryType::Symlink => {
                 if let Some(ref target) = entry.link_target {
-                    let options = options.unix_permissions(0o777);
-                    // Store symlink target as file content (zip convention for some tools)
-                    zip.start_file(&*path_str, options)?;
-                    zip.write_all(target.to_string_lossy().as_bytes())?;
+                    match zip_options.symlinks {
+                        ZipSymlinks::Link => {
+                            // Stored uncompressed with S_IFLNK set, as Info-ZIP does
+                            let options = options.unix_permissions(0o777);
+                            zip.add_symlink(&*path_str, target.to_string_lossy(), options)?;
+                        }
+                        ZipSymlinks::Follow => match fs::metadata(&entry.path) {
+                            Ok(meta) if meta.is_file() => {
+                                let method = zip_file_method(
+                                    &entry.path,
+                                    &entry.relative_path,
+                                    zip_options,
+                                    compression.level,
+                                )?;
+                                let options = zip_file_options(method, compression.level, options)
+                                    .unix_permissions(permissions(EntryType::File, meta.mode()));
+                                zip.start_file(&*path_str, options)?;
+                                copy_hashed(&entry.path, entry, meta.len(), &mut sums, &mut zip)?;
+                            }
+                            Ok(meta) if meta.is_dir() => skip_entry(
+                                &entry.relative_path,
+                                "symlink",
+                                "points to a directory (use --dereference to include its contents)",
+                                quiet,
+                            ),
+                            _ => skip_entry(
+                                &entry.relative_path,
+                                "symlink",
+                                "target is missing or not a regular file",
+                                quiet,
+                            ),
+                        },
+                        ZipSymlinks::Skip => {
+                            if verbose {
+                                eprintln!("  skipped symlink {}", entry.relative_path.display());
+                            }
+                        }
+                    }
                 }
             }
             EntryType::File => {
-                let options = options.unix_permissions(entry.mode & 0o7777);
+                let method = zip_file_method(
+                    &entry.path,
+                    &entry.relative_path,
+                    zip_options,
+                    compression.level,
+                )?;
+                let options = zip_file_options(method, compression.level, options)
+                    .unix_permissions(permissions(entry.entry_type, entry.mode));
                 zip.start_file(&*path_str, options)?;
-                let mut file = File::open(&entry.path)?;
-                io::copy(&mut file, &mut zip)?;
+                copy_hashed(&entry.path, entry, entry.size, &mut sums, &mut zip)?;
             }
             EntryType::Directory => {
-                // Skip directories
+                let options = options.unix_permissions(permissions(entry.entry_type, entry.mode));
+                zip.add_directory(&*path_str, options)?;
             }
+            EntryType::Fifo
+            | EntryType::CharDevice
+            | EntryType::BlockDevice
+            | EntryType::Socket => skip_entry(
+                &entry.relative_path,
+                entry.entry_type.name(),
+                "zip cannot store special files",
+                quiet,
+            ),
         }
 
         if let Some(ref pb) = progress {
@@ -173,69 +905,212 @@ pub fn create_zip<W: Write + io::Seek>(
         pb.finish_and_clear();
     }
 
-    zip.finish()?;
-    Ok(())
+    if let Some(sums) = sums {
+        let entry = sums.entry(entries);
+        write_generated(&mut zip, &entry, sums.contents(), zip_options)?;
+    }
+    Ok(zip.finish()?)
 }
 
 /// Creates a gzip-compressed tar archive.
-#[allow(clippy::fn_params_excessive_bools)]
+///
+/// With more than one thread the output is a series of gzip members (see
+/// [`crate::parallel`]).
 pub fn create_tar_gz<W: Write>(
     writer: W,
     entries: &[FileEntry],
-    reproducible: bool,
-    preserve_owner: bool,
+    tar: &TarOptions,
+    compression: &CompressionOptions,
     quiet: bool,
     verbose: bool,
-) -> Result<()> {
-    let encoder = GzEncoder::new(writer, GzCompression::default());
-    create_tar(
-        encoder,
-        entries,
-        reproducible,
-        preserve_owner,
-        quiet,
-        verbose,
-    )
+) -> Result<W> {
+    let level = GzCompression::new(compression.level_or(GzCompression::default().level()));
+    if let Some(threads) = compression.parallel_threads() {
+        let encoder = ParallelEncoder::gzip(writer, threads, level);
+        return Ok(create_tar(encoder, entries, tar, quiet, verbose)?.finish()?);
+    }
+
+    let encoder = GzEncoder::new(writer, level);
+    Ok(create_tar(encoder, entries, tar, quiet, verbose)?.finish()?)
 }
 
 /// Creates a bzip2-compressed tar archive.
-#[allow(clippy::fn_params_excessive_bools)]
+///
+/// With more than one thread the output is a series of bzip2 streams.
 pub fn create_tar_bz2<W: Write>(
     writer: W,
     entries: &[FileEntry],
-    reproducible: bool,
-    preserve_owner: bool,
+    tar: &TarOptions,
+    compression: &CompressionOptions,
     quiet: bool,
     verbose: bool,
-) -> Result<()> {
-    let encoder = BzEncoder::new(writer, bzip2::Compression::best());
-    create_tar(
-        encoder,
-        entries,
-        reproducible,
-        preserve_owner,
-        quiet,
-        verbose,
-    )
+) -> Result<W> {
+    let level = bzip2::Compression::new(compression.level_or(9));
+    if let Some(threads) = compression.parallel_threads() {
+        let encoder = ParallelEncoder::bzip2(writer, threads, level);
+        return Ok(create_tar(encoder, entries, tar, quiet, verbose)?.finish()?);
+    }
+
+    let encoder = BzEncoder::new(writer, level);
+    Ok(create_tar(encoder, entries, tar, quiet, verbose)?.finish()?)
 }
 
 /// Creates a zstd-compressed tar archive.
-#[allow(clippy::fn_params_excessive_bools)]
 pub fn create_tar_zst<W: Write>(
     writer: W,
     entries: &[FileEntry],
-    reproducible: bool,
-    preserve_owner: bool,
+    tar: &TarOptions,
+    compression: &CompressionOptions,
     quiet: bool,
     verbose: bool,
-) -> Result<()> {
-    let encoder = ZstdEncoder::new(writer, 3)?.auto_finish();
-    create_tar(
-        encoder,
-        entries,
-        reproducible,
-        preserve_owner,
-        quiet,
-        verbose,
-    )
+) -> Result<W> {
+    let mut encoder = ZstdEncoder::new(writer, compression.level.unwrap_or(3))?;
+    if compression.zstd_long {
+        encoder.long_distance_matching(true)?;
+    }
+    if let Some(window_log) = compression.zstd_window_log {
+        encoder.window_log(window_log)?;
+    }
+    if let Some(threads) = compression.parallel_threads() {
+        encoder.multithread(threads)?;
+    }
+    Ok(create_tar(encoder, entries, tar, quiet, verbose)?.finish()?)
+}
+
+/// Creates an xz-compressed tar archive.
+pub fn create_tar_xz<W: Write>(
+    writer: W,
+    entries: &[FileEntry],
+    tar: &TarOptions,
+    compression: &CompressionOptions,
+    quiet: bool,
+    verbose: bool,
+) -> Result<W> {
+    let encoder = XzEncoder::new(writer, compression.level_or(6));
+    Ok(create_tar(encoder, entries, tar, quiet, verbose)?.finish()?)
+}
+
+/// Creates an lz4-compressed tar archive (LZ4 frame format).
+pub fn create_tar_lz4<W: Write>(
+    writer: W,
+    entries: &[FileEntry],
+    tar: &TarOptions,
+    compression: &CompressionOptions,
+    quiet: bool,
+    verbose: bool,
+) -> Result<W> {
+    let encoder = Lz4EncoderBuilder::new()
+        .level(compression.level_or(0))
+        .build(writer)?;
+    let (writer, result) = create_tar(encoder, entries, tar, quiet, verbose)?.finish();
+    result?;
+    Ok(writer)
+}
+
+/// Keeps the first error from the writer under a brotli encoder, whose
+/// `into_inner` discards the error of its final write.
+struct ErrorTrap<W> {
+    inner: W,
+    error: Option<io::Error>,
+}
+
+impl<W> ErrorTrap<W> {
+    const fn new(inner: W) -> Self {
+        Self { inner, error: None }
+    }
+
+    /// Stores a copy of `error` unless one is already kept.
+    fn record(&mut self, error: io::Error) -> io::Error {
+        if error.kind() != io::ErrorKind::Interrupted && self.error.is_none() {
+            self.error = Some(io::Error::new(error.kind(), error.to_string()));
+        }
+        error
+    }
+
+    /// Returns the writer, or the first error it gave.
+    fn finish(self) -> io::Result<W> {
+        self.error.map_or(Ok(self.inner), Err)
+    }
+}
+
+impl<W: Write> Write for ErrorTrap<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf).map_err(|e| self.record(e))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush().map_err(|e| self.record(e))
+    }
+}
+
+/// Creates a brotli-compressed tar archive.
+pub fn create_tar_br<W: Write>(
+    writer: W,
+    entries: &[FileEntry],
+    tar: &TarOptions,
+    compression: &CompressionOptions,
+    quiet: bool,
+    verbose: bool,
+) -> Result<W> {
+    let encoder = BrotliEncoder::new(ErrorTrap::new(writer), 4096, compression.level_or(9), 22);
+    let mut writer = create_tar(encoder, entries, tar, quiet, verbose)?
+        .into_inner()
+        .finish()?;
+    writer.flush()?;
+    Ok(writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A full disk: every write fails, flushing does not.
+    #[derive(Debug)]
+    struct FullDisk;
+
+    impl Write for FullDisk {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::StorageFull, "no space left"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    type Create = fn(
+        FullDisk,
+        &[FileEntry],
+        &TarOptions,
+        &CompressionOptions,
+        bool,
+        bool,
+    ) -> Result<FullDisk>;
+
+    #[test]
+    fn test_write_errors_are_reported_by_every_codec() {
+        let codecs: [(&str, Create); 6] = [
+            ("gz", create_tar_gz),
+            ("bz2", create_tar_bz2),
+            ("zst", create_tar_zst),
+            ("xz", create_tar_xz),
+            ("lz4", create_tar_lz4),
+            ("br", create_tar_br),
+        ];
+        let (tar, compression) = (TarOptions::default(), CompressionOptions::default());
+        for (name, create) in codecs {
+            let result = create(FullDisk, &[], &tar, &compression, true, false);
+            assert!(result.is_err(), "tar.{name} reported success");
+        }
+    }
+
+    #[test]
+    fn test_error_trap_keeps_first_error() {
+        let mut trap = ErrorTrap::new(FullDisk);
+        assert!(trap.write(b"data").is_err());
+        assert_eq!(
+            trap.finish().unwrap_err().kind(),
+            io::ErrorKind::StorageFull
+        );
+    }
 }
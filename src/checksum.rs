@@ -0,0 +1,339 @@
+//! Checksums computed while archiving (`--checksums`).
+//!
+//! Member data is hashed as `create_tar` and `create_zip` read it, and the
+//! archive itself as its bytes are written, so nothing is read twice. The
+//! member digests can be embedded as a `SHA256SUMS` (or `B3SUMS`) member in
+//! the format `sha256sum -c` and `b3sum -c` check; the archive digest goes to
+//! `<output>.sha256` (or `.b3`) in the same format.
+
+use crate::sparse::Region;
+use crate::walk::FileEntry;
+use crate::ChecksumAlgorithm;
+
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Formats a digest as lowercase hex.
+pub fn to_hex(digest: &[u8]) -> String {
+    use std::fmt::Write as _;
+    digest.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+/// A running digest in one of the supported algorithms.
+pub enum Hasher {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    pub fn new(algorithm: ChecksumAlgorithm) -> Self {
+        match algorithm {
+            ChecksumAlgorithm::Sha256 => Self::Sha256(Sha256::new()),
+            ChecksumAlgorithm::Blake3 => Self::Blake3(Box::default()),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha256(hasher) => hasher.update(data),
+            Self::Blake3(hasher) => {
+                hasher.update(data);
+            }
+        }
+    }
+
+    /// Feeds `len` zero bytes, for the holes of a sparse file.
+    fn update_zeros(&mut self, mut len: u64) {
+        static ZEROS: [u8; 64 * 1024] = [0; 64 * 1024];
+        while len > 0 {
+            let chunk = usize::try_from(len).map_or(ZEROS.len(), |l| l.min(ZEROS.len()));
+            self.update(&ZEROS[..chunk]);
+            len -= chunk as u64;
+        }
+    }
+
+    /// Returns the digest as lowercase hex.
+    pub fn finish(self) -> String {
+        match self {
+            Self::Sha256(hasher) => to_hex(&hasher.finalize()),
+            Self::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+        }
+    }
+}
+
+/// Hashes the full contents of one file as its stored data is read.
+///
+/// For a sparse file only the data regions are read; the holes between them
+/// are hashed as zeros, so the digest matches the extracted file.
+pub struct FileHasher {
+    hasher: Hasher,
+    size: u64,
+    regions: Vec<Region>,
+    /// Index of the region being read
+    region: usize,
+    /// Bytes read from the current region
+    in_region: u64,
+    /// Offset in the file up to which content has been hashed
+    position: u64,
+}
+
+impl FileHasher {
+    /// Starts hashing a file of `size` bytes; `regions` are the data regions
+    /// of a sparse file.
+    pub fn new(algorithm: ChecksumAlgorithm, size: u64, regions: Option<&[Region]>) -> Self {
+        Self {
+            hasher: Hasher::new(algorithm),
+            size,
+            regions: regions.unwrap_or_default().to_vec(),
+            region: 0,
+            in_region: 0,
+            position: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        if self.regions.is_empty() {
+            self.hasher.update(data);
+            self.position += data.len() as u64;
+            return;
+        }
+
+        while !data.is_empty() {
+            let Some(&(offset, len)) = self.regions.get(self.region) else {
+                // More data than the map lists; hash it as it comes
+                self.hasher.update(data);
+                self.position += data.len() as u64;
+                return;
+            };
+            if self.in_region == 0 && offset > self.position {
+                self.hasher.update_zeros(offset - self.position);
+                self.position = offset;
+            }
+            let take = usize::try_from(len - self.in_region)
+                .map_or(data.len(), |left| left.min(data.len()));
+            self.hasher.update(&data[..take]);
+            self.in_region += take as u64;
+            self.position += take as u64;
+            data = &data[take..];
+            if self.in_region == len {
+                self.region += 1;
+                self.in_region = 0;
+            }
+        }
+    }
+
+    /// Returns the file's digest, counting a trailing hole.
+    pub fn finish(mut self) -> String {
+        if !self.regions.is_empty() && self.size > self.position {
+            self.hasher.update_zeros(self.size - self.position);
+        }
+        self.hasher.finish()
+    }
+}
+
+/// Passes reads through, feeding the data to a [`FileHasher`] if given.
+pub struct HashingReader<'a, R> {
+    inner: R,
+    hasher: Option<&'a mut FileHasher>,
+}
+
+impl<'a, R: Read> HashingReader<'a, R> {
+    pub const fn new(inner: R, hasher: Option<&'a mut FileHasher>) -> Self {
+        Self { inner, hasher }
+    }
+}
+
+impl<R: Read> Read for HashingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if let Some(ref mut hasher) = self.hasher {
+            hasher.update(&buf[..read]);
+        }
+        Ok(read)
+    }
+}
+
+/// Passes writes through, hashing the bytes written if an algorithm is set.
+pub struct HashingWriter<W> {
+    inner: W,
+    hasher: Option<Hasher>,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W, algorithm: Option<ChecksumAlgorithm>) -> Self {
+        Self {
+            inner,
+            hasher: algorithm.map(Hasher::new),
+        }
+    }
+
+    /// Returns the inner writer and the digest of everything written.
+    pub fn finish(self) -> (W, Option<String>) {
+        (self.inner, self.hasher.map(Hasher::finish))
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some(ref mut hasher) = self.hasher {
+            hasher.update(&buf[..written]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Formats one line of a sums file.
+///
+/// Names with a backslash or newline are escaped, and the line starts with a
+/// backslash, as coreutils does.
+pub fn sums_line(digest: &str, name: &str) -> String {
+    if name.contains(['\\', '\n']) {
+        let name = name.replace('\\', "\\\\").replace('\n', "\\n");
+        format!("\\{digest}  {name}\n")
+    } else {
+        format!("{digest}  {name}\n")
+    }
+}
+
+/// Collects member digests for the embedded sums file.
+pub struct SumsFile {
+    algorithm: ChecksumAlgorithm,
+    contents: String,
+    /// Digest of each multiply-linked inode, for its later hard links
+    hard_links: HashMap<(u64, u64), String>,
+}
+
+impl SumsFile {
+    pub fn new(algorithm: ChecksumAlgorithm) -> Self {
+        Self {
+            algorithm,
+            contents: String::new(),
+            hard_links: HashMap::new(),
+        }
+    }
+
+    pub const fn algorithm(&self) -> ChecksumAlgorithm {
+        self.algorithm
+    }
+
+    /// Records the digest of a file member.
+    pub fn add(&mut self, entry: &FileEntry, digest: String) {
+        self.contents
+            .push_str(&sums_line(&digest, &entry.relative_path.to_string_lossy()));
+        if let Some(key) = entry.hard_link_key {
+            self.hard_links.insert(key, digest);
+        }
+    }
+
+    /// Records a hard link with the digest of the file it links to.
+    pub fn add_hard_link(&mut self, entry: &FileEntry) {
+        let digest = entry
+            .hard_link_key
+            .and_then(|key| self.hard_links.get(&key))
+            .cloned();
+        if let Some(digest) = digest {
+            self.add(entry, digest);
+        }
+    }
+
+    /// Returns the sums file contents.
+    pub const fn contents(&self) -> &[u8] {
+        self.contents.as_bytes()
+    }
+
+    /// Builds the entry describing the sums member itself.
+    ///
+    /// It takes the newest mtime among `entries`, so reproducible archives
+    /// stay reproducible.
+    pub fn entry(&self, entries: &[FileEntry]) -> FileEntry {
+        FileEntry {
+            path: PathBuf::new(),
+            relative_path: PathBuf::from(self.algorithm.sums_name()),
+            size: self.contents.len() as u64,
+            entry_type: crate::EntryType::File,
+            link_target: None,
+            mode: 0o644,
+            uid: 0,
+            gid: 0,
+            uname: None,
+            gname: None,
+            mtime: entries.iter().map(|e| e.mtime).max().unwrap_or(0),
+            mtime_nsec: 0,
+            hard_link_key: None,
+            xattrs: Vec::new(),
+            device: None,
+            sparse: None,
+            disk: None,
+        }
+    }
+}
+
+/// Returns the path of the checksum file written next to `output`.
+pub fn sidecar_path(output: &Path, algorithm: ChecksumAlgorithm) -> PathBuf {
+    let mut name = OsString::from(output.as_os_str());
+    name.push(".");
+    name.push(algorithm.extension());
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    fn digest(algorithm: ChecksumAlgorithm, data: &[u8]) -> String {
+        let mut hasher = Hasher::new(algorithm);
+        hasher.update(data);
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_known_digests() {
+        assert_eq!(digest(ChecksumAlgorithm::Sha256, b""), EMPTY_SHA256);
+        assert_eq!(
+            digest(ChecksumAlgorithm::Blake3, b""),
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+        );
+    }
+
+    #[test]
+    fn test_sparse_file_hashes_holes_as_zeros() {
+        // 4 bytes of data at 2 and 2 at 10, in a 16-byte file
+        let regions = [(2, 4), (10, 2)];
+        let mut hasher = FileHasher::new(ChecksumAlgorithm::Sha256, 16, Some(&regions));
+        let mut reader = HashingReader::new(&b"abcdef"[..], Some(&mut hasher));
+        io::copy(&mut reader, &mut io::sink()).unwrap();
+
+        let mut full = vec![0u8; 16];
+        full[2..6].copy_from_slice(b"abcd");
+        full[10..12].copy_from_slice(b"ef");
+        assert_eq!(hasher.finish(), digest(ChecksumAlgorithm::Sha256, &full));
+    }
+
+    #[test]
+    fn test_hashing_writer() {
+        let mut writer = HashingWriter::new(Vec::new(), Some(ChecksumAlgorithm::Sha256));
+        writer.write_all(b"").unwrap();
+        let (inner, digest) = writer.finish();
+        assert!(inner.is_empty());
+        assert_eq!(digest.as_deref(), Some(EMPTY_SHA256));
+    }
+
+    #[test]
+    fn test_sums_line_escapes() {
+        assert_eq!(sums_line("ab", "a b.txt"), "ab  a b.txt\n");
+        assert_eq!(sums_line("ab", "a\nb"), "\\ab  a\\nb\n");
+    }
+}
@@ -1,5 +1,7 @@
 //! Configuration file handling.
 
+use crate::{Format, ZipMethod};
+
 use anyhow::{Context, Result};
 use colored::Colorize;
 use serde::Deserialize;
@@ -14,6 +16,17 @@ use std::path::{Path, PathBuf};
 pub struct Config {
     pub ignore: IgnoreConfig,
     pub defaults: DefaultsConfig,
+    pub compression: CompressionConfig,
+    pub zip: ZipConfig,
+    /// Member renaming rules, applied before `--transform`
+    pub transform: Vec<TransformConfig>,
+}
+
+/// One `[[transform]]` rule.
+#[derive(Debug, Deserialize)]
+pub struct TransformConfig {
+    /// Sed-like expression, e.g. `s,^dist/,,`
+    pub expr: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -57,6 +70,85 @@ pub struct DefaultsConfig {
     pub dereference: bool,
     /// Preserve ownership by default
     pub preserve_owner: bool,
+    /// Directory to place every member under (placeholders like `{dirname}`)
+    pub prefix: Option<String>,
+    /// Store 0644/0755 permissions; unset follows `reproducible`
+    pub normalize_modes: Option<bool>,
+    /// Minisign secret key to sign every archive with (`~/` is expanded)
+    pub sign: Option<String>,
+}
+
+/// Compression levels and codec tuning, per format.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CompressionConfig {
+    /// Gzip level for tar.gz (0-9)
+    pub gzip: Option<i32>,
+    /// Bzip2 level for tar.bz2 (1-9)
+    pub bzip2: Option<i32>,
+    /// Zstandard level for tar.zst (-7 to 22)
+    pub zstd: Option<i32>,
+    /// Xz level for tar.xz (0-9)
+    pub xz: Option<i32>,
+    /// Lz4 level for tar.lz4 (0-12, 3+ uses the high-compression mode)
+    pub lz4: Option<i32>,
+    /// Brotli quality for tar.br (0-11)
+    pub brotli: Option<i32>,
+    /// Deflate level for zip (1-9)
+    pub zip: Option<i32>,
+    /// Enable zstd long-distance matching
+    pub zstd_long: bool,
+    /// zstd window size as a power of two (10-31)
+    pub zstd_window_log: Option<u32>,
+}
+
+/// Zip member compression settings.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ZipConfig {
+    /// Compression method for zip members
+    pub method: Option<ZipMethod>,
+    /// Extensions that are always stored uncompressed (replaces the built-in list)
+    pub store_extensions: Vec<String>,
+    /// Store files whose compressed form would not be smaller
+    pub store_if_larger: bool,
+}
+
+/// Extensions of already-compressed formats that deflate cannot shrink.
+const DEFAULT_STORE_EXTENSIONS: &[&str] = &[
+    "7z", "apk", "avif", "br", "bz2", "deb", "docx", "epub", "flac", "gif", "gz", "heic", "jar",
+    "jpeg", "jpg", "lz4", "lzma", "m4a", "mkv", "mov", "mp3", "mp4", "odt", "ogg", "opus", "png",
+    "pptx", "rar", "rpm", "tbz2", "tgz", "txz", "webm", "webp", "whl", "woff", "woff2", "xlsx",
+    "xz", "zip", "zst",
+];
+
+impl Default for ZipConfig {
+    fn default() -> Self {
+        Self {
+            method: None,
+            store_extensions: DEFAULT_STORE_EXTENSIONS
+                .iter()
+                .map(|s| (*s).to_string())
+                .collect(),
+            store_if_larger: false,
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// Returns the configured level for a format, if any.
+    pub const fn level_for(&self, format: Format) -> Option<i32> {
+        match format {
+            Format::Tar => None,
+            Format::TarGz => self.gzip,
+            Format::TarBz2 => self.bzip2,
+            Format::TarZst => self.zstd,
+            Format::TarXz => self.xz,
+            Format::TarLz4 => self.lz4,
+            Format::TarBr => self.brotli,
+            Format::Zip => self.zip,
+        }
+    }
 }
 
 /// Returns the path to the config file.
@@ -65,6 +157,14 @@ pub fn config_path() -> Option<PathBuf> {
     dirs::home_dir().map(|d| d.join(".config").join("raptar").join("config.toml"))
 }
 
+/// Expands a leading `~/` in a path from the config file.
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
 /// Check if config file exists.
 pub fn config_exists() -> bool {
     config_path().is_some_and(|p| p.exists())
@@ -133,7 +233,7 @@ always_exclude = [
 # always_include = ["important.log", "dist/release.tar.gz"]
 
 [defaults]
-# Default output format (tar, tar.gz, tar.bz2, tar.zst, zip)
+# Default output format (tar, tar.gz, tar.bz2, tar.zst, tar.xz, tar.lz4, tar.br, zip)
 # format = "tar.gz"
 
 # Always create reproducible archives
@@ -144,6 +244,46 @@ always_exclude = [
 
 # Preserve file ownership by default
 # preserve_owner = false
+
+# Place every member under a top-level directory ({dirname}, {date})
+# prefix = "{dirname}"
+
+# Store 0644/0755 permissions without setuid/setgid/sticky bits
+# (unset: on for reproducible archives only)
+# normalize_modes = true
+
+# Sign every archive with this minisign secret key (--no-sign skips it)
+# sign = "~/.config/raptar/release.key"
+
+[compression]
+# Per-format compression levels (--level overrides for a single run)
+# gzip = 6        # tar.gz, 0-9
+# bzip2 = 9       # tar.bz2, 1-9
+# zstd = 3        # tar.zst, -7 to 22
+# xz = 6          # tar.xz, 0-9
+# lz4 = 0         # tar.lz4, 0-12
+# brotli = 9      # tar.br, 0-11
+# zip = 6         # zip, 1-9 (-7 to 22 with method = "zstd")
+
+# zstd long-distance matching and window size (2^N bytes)
+# zstd_long = false
+# zstd_window_log = 27
+
+[zip]
+# Compression method for zip members: store, deflate, bzip2, zstd
+# method = "deflate"
+
+# Extensions always stored uncompressed (replaces the built-in list of
+# already-compressed formats such as png, jpg, jar, gz, zip, mp4)
+# store_extensions = ["png", "jpg", "jar", "gz", "zip"]
+
+# Store a file when compressing a sample of it does not save space
+# (reads and compresses the first 256 KiB of every file a second time)
+# store_if_larger = false
+
+# Rename members on the way in (sed-like, applied before --transform)
+# [[transform]]
+# expr = "s,^dist/,,"
 "#;
 
     fs::write(&path, default_config)?;
@@ -242,7 +382,51 @@ pub fn show_config(config: &Config) {
         "  defaults.preserve_owner: {}",
         config.defaults.preserve_owner
     );
+    if let Some(ref prefix) = config.defaults.prefix {
+        println!("  defaults.prefix: {prefix}");
+    }
+    if let Some(normalize) = config.defaults.normalize_modes {
+        println!("  defaults.normalize_modes: {normalize}");
+    }
+    if let Some(ref key) = config.defaults.sign {
+        println!("  defaults.sign: {key}");
+    }
+
+    let levels = [
+        ("gzip", config.compression.gzip),
+        ("bzip2", config.compression.bzip2),
+        ("zstd", config.compression.zstd),
+        ("xz", config.compression.xz),
+        ("lz4", config.compression.lz4),
+        ("brotli", config.compression.brotli),
+        ("zip", config.compression.zip),
+    ];
+    for (name, level) in levels {
+        if let Some(level) = level {
+            println!("  compression.{name}: {level}");
+        }
+    }
+    println!("  compression.zstd_long: {}", config.compression.zstd_long);
+    if let Some(window_log) = config.compression.zstd_window_log {
+        println!("  compression.zstd_window_log: {window_log}");
+    }
+    if let Some(method) = config.zip.method {
+        println!("  zip.method: {method:?}");
+    }
+    println!(
+        "  zip.store_extensions: {}",
+        config.zip.store_extensions.join(", ")
+    );
+    println!("  zip.store_if_larger: {}", config.zip.store_if_larger);
+    for rule in &config.transform {
+        println!("  transform: {}", rule.expr);
+    }
+
+    print_usage_hints();
+}
 
+/// Prints examples of the CLI flags that complement the config file.
+fn print_usage_hints() {
     println!();
     println!("{}", "Usage:".bold());
     println!(
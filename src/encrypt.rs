@@ -0,0 +1,266 @@
+//! Encrypted archives (`--encrypt-to` and `--passphrase`).
+//!
+//! Tar archives are wrapped in the age format after compression, so
+//! `age -d` turns them back into an ordinary compressed tar. Recipients are
+//! age public keys (`age1...`) or SSH public keys; with `--passphrase` the
+//! file key is derived from a passphrase instead. Zip archives encrypt each
+//! member with `WinZip` AES-256, which 7-Zip and other zip tools can open.
+//!
+//! Passphrases come from `RAPTAR_PASSPHRASE`, or are typed at a prompt.
+//! Reading an age-encrypted archive takes identity files (`-i`): age keys
+//! made with `age-keygen`, or SSH private keys.
+
+use age::secrecy::SecretString;
+use age::stream::{StreamReader, StreamWriter};
+use anyhow::{bail, Context, Result};
+use std::cell::OnceCell;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Environment variable holding the passphrase, for unattended use.
+const PASSPHRASE_VAR: &str = "RAPTAR_PASSPHRASE";
+
+/// Start of a binary age file.
+const AGE_MAGIC: &[u8] = b"age-encryption.org/v1\n";
+/// Start of an ASCII-armored age file.
+const AGE_ARMOR: &[u8] = b"-----BEGIN AGE ENCRYPTED FILE-----";
+
+/// Returns true if `magic` is the start of an age file.
+pub fn is_age(magic: &[u8]) -> bool {
+    magic.starts_with(AGE_MAGIC) || magic.starts_with(AGE_ARMOR)
+}
+
+/// Reads a passphrase from the environment or a prompt, asking twice when
+/// `confirm` is set.
+pub fn read_passphrase(confirm: bool) -> Result<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_VAR) {
+        if passphrase.is_empty() {
+            bail!("The passphrase in {PASSPHRASE_VAR} is empty");
+        }
+        return Ok(passphrase);
+    }
+    let prompt = |text: &str| {
+        rpassword::prompt_password(text)
+            .with_context(|| format!("Failed to read the passphrase (or set {PASSPHRASE_VAR})"))
+    };
+    let passphrase = prompt("Passphrase: ")?;
+    if passphrase.is_empty() {
+        bail!("The passphrase is empty");
+    }
+    if confirm && prompt("Confirm passphrase: ")? != passphrase {
+        bail!("The passphrases do not match");
+    }
+    Ok(passphrase)
+}
+
+/// Parses an age (`age1...`) or SSH public key recipient.
+fn parse_recipient(recipient: &str) -> Result<Box<dyn age::Recipient>> {
+    let recipient = recipient.trim();
+    if let Ok(key) = recipient.parse::<age::x25519::Recipient>() {
+        return Ok(Box::new(key));
+    }
+    if let Ok(key) = recipient.parse::<age::ssh::Recipient>() {
+        return Ok(Box::new(key));
+    }
+    bail!("Invalid recipient {recipient:?}: expected an age public key (age1...) or an SSH public key")
+}
+
+/// How a new archive is encrypted.
+pub enum Encryption {
+    /// age, to public keys
+    Recipients(Vec<Box<dyn age::Recipient>>),
+    /// age with a passphrase for tar formats, `WinZip` AES-256 for zip
+    Passphrase(String),
+}
+
+impl Encryption {
+    /// Parses the `--encrypt-to` recipients.
+    pub fn to_recipients(recipients: &[String]) -> Result<Self> {
+        recipients
+            .iter()
+            .map(|r| parse_recipient(r))
+            .collect::<Result<_>>()
+            .map(Self::Recipients)
+    }
+
+    /// Returns the passphrase for zip member encryption.
+    pub fn passphrase(&self) -> Option<&str> {
+        match self {
+            Self::Recipients(_) => None,
+            Self::Passphrase(passphrase) => Some(passphrase),
+        }
+    }
+
+    /// Wraps `output` in an age stream; call `finish` on it when done.
+    pub fn wrap<W: Write>(&self, output: W) -> Result<StreamWriter<W>> {
+        let encryptor = match self {
+            Self::Recipients(recipients) => {
+                age::Encryptor::with_recipients(recipients.iter().map(Box::as_ref))?
+            }
+            Self::Passphrase(passphrase) => {
+                age::Encryptor::with_user_passphrase(SecretString::from(passphrase.clone()))
+            }
+        };
+        Ok(encryptor.wrap_output(output)?)
+    }
+}
+
+/// Asks for the passphrase of an encrypted SSH identity.
+#[derive(Clone)]
+struct Prompt;
+
+impl age::Callbacks for Prompt {
+    fn display_message(&self, message: &str) {
+        eprintln!("{message}");
+    }
+
+    fn confirm(&self, _message: &str, _yes: &str, _no: Option<&str>) -> Option<bool> {
+        None
+    }
+
+    fn request_public_string(&self, _description: &str) -> Option<String> {
+        None
+    }
+
+    fn request_passphrase(&self, description: &str) -> Option<SecretString> {
+        rpassword::prompt_password(format!("{description} "))
+            .ok()
+            .map(SecretString::from)
+    }
+}
+
+/// Loads the identities in an age identity file or an SSH private key.
+fn load_identities(path: &Path) -> Result<Vec<Box<dyn age::Identity>>> {
+    let contents = fs::read(path)
+        .with_context(|| format!("Failed to read identity file: {}", path.display()))?;
+
+    if let Ok(file) = age::IdentityFile::from_buffer(contents.as_slice()) {
+        return file
+            .into_identities()
+            .with_context(|| format!("Failed to load identities from {}", path.display()));
+    }
+    let key =
+        age::ssh::Identity::from_buffer(contents.as_slice(), Some(path.display().to_string()))
+            .with_context(|| {
+                format!(
+                    "Not an age identity file or SSH private key: {}",
+                    path.display()
+                )
+            })?;
+    if let age::ssh::Identity::Unsupported(_) = key {
+        bail!("Unsupported SSH key type: {}", path.display());
+    }
+    Ok(vec![Box::new(key.with_callbacks(Prompt))])
+}
+
+/// Keys for reading encrypted archives.
+#[derive(Default)]
+pub struct Keys {
+    identities: Vec<PathBuf>,
+    /// Passphrase, asked for at most once
+    passphrase: OnceCell<String>,
+}
+
+impl Keys {
+    pub const fn new(identities: Vec<PathBuf>) -> Self {
+        Self {
+            identities,
+            passphrase: OnceCell::new(),
+        }
+    }
+
+    /// Returns the passphrase, asking for it the first time.
+    pub fn passphrase(&self) -> Result<&str> {
+        if let Some(passphrase) = self.passphrase.get() {
+            return Ok(passphrase);
+        }
+        let passphrase = read_passphrase(false)?;
+        Ok(self.passphrase.get_or_init(|| passphrase))
+    }
+
+    /// Decrypts the age file read from `input`.
+    pub fn decrypt<R: Read>(&self, input: R, path: &Path) -> Result<StreamReader<R>> {
+        let decryptor = age::Decryptor::new(input)
+            .with_context(|| format!("Invalid age header: {}", path.display()))?;
+
+        let identities: Vec<Box<dyn age::Identity>> = if decryptor.is_scrypt() {
+            vec![Box::new(age::scrypt::Identity::new(SecretString::from(
+                self.passphrase()?.to_string(),
+            )))]
+        } else {
+            if self.identities.is_empty() {
+                bail!(
+                    "{} is encrypted to age recipients; pass an identity file with -i",
+                    path.display()
+                );
+            }
+            let mut identities = Vec::new();
+            for file in &self.identities {
+                identities.extend(load_identities(file)?);
+            }
+            identities
+        };
+
+        decryptor
+            .decrypt(identities.iter().map(Box::as_ref))
+            .with_context(|| format!("Failed to decrypt {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use age::secrecy::ExposeSecret;
+    use std::io::Cursor;
+
+    fn round_trip(encryption: &Encryption, keys: &Keys) -> Result<Vec<u8>> {
+        let mut writer = encryption.wrap(Vec::new())?;
+        writer.write_all(b"archive bytes")?;
+        let encrypted = writer.finish()?;
+        assert!(is_age(&encrypted));
+
+        let mut plain = Vec::new();
+        keys.decrypt(Cursor::new(encrypted), Path::new("test.age"))?
+            .read_to_end(&mut plain)?;
+        Ok(plain)
+    }
+
+    #[test]
+    fn test_recipient_round_trip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let identity = age::x25519::Identity::generate();
+        let key_file = tmp.path().join("key.txt");
+        fs::write(&key_file, identity.to_string().expose_secret()).unwrap();
+
+        let encryption = Encryption::to_recipients(&[identity.to_public().to_string()]).unwrap();
+        let plain = round_trip(&encryption, &Keys::new(vec![key_file])).unwrap();
+        assert_eq!(plain, b"archive bytes");
+    }
+
+    #[test]
+    fn test_wrong_identity_fails() {
+        let tmp = tempfile::tempdir().unwrap();
+        let other = age::x25519::Identity::generate();
+        let key_file = tmp.path().join("key.txt");
+        fs::write(&key_file, other.to_string().expose_secret()).unwrap();
+
+        let recipient = age::x25519::Identity::generate().to_public().to_string();
+        let encryption = Encryption::to_recipients(&[recipient]).unwrap();
+        assert!(round_trip(&encryption, &Keys::new(vec![key_file])).is_err());
+        assert!(round_trip(&encryption, &Keys::default()).is_err());
+    }
+
+    #[test]
+    fn test_passphrase_round_trip() {
+        let keys = Keys::default();
+        keys.passphrase.set("hunter2".to_string()).unwrap();
+        let encryption = Encryption::Passphrase("hunter2".to_string());
+        assert_eq!(round_trip(&encryption, &keys).unwrap(), b"archive bytes");
+    }
+
+    #[test]
+    fn test_invalid_recipient() {
+        assert!(Encryption::to_recipients(&["age1nope".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,626 @@
+//! Safe extraction of tar and zip archives.
+//!
+//! Every member path is validated before anything is written: absolute paths,
+//! `..` components and symlinks that resolve outside the destination are
+//! refused, and parent directories are checked after canonicalization so that
+//! pre-existing symlinks cannot redirect writes elsewhere. Symlink targets are
+//! resolved through the symlinks already on disk when they are created, and
+//! hard links may only point at files whose resolved parent is inside the
+//! destination.
+
+use crate::encrypt::Keys;
+use crate::incremental;
+use crate::reader::{self, ArchiveReader};
+use crate::sparse::{self, PaxSparse};
+use crate::ExtractArgs;
+
+use anyhow::{Context, Result};
+use colored::Colorize;
+use filetime::FileTime;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+
+/// Counts of what happened during extraction.
+#[derive(Debug, Default)]
+pub struct ExtractStats {
+    pub extracted: usize,
+    pub refused: usize,
+    pub skipped: usize,
+    /// Paths removed by the deletion lists of increments
+    pub deleted: usize,
+}
+
+/// A directory whose mode and mtime are applied after its contents are written.
+struct PendingDir {
+    path: PathBuf,
+    mode: Option<u32>,
+    mtime: Option<FileTime>,
+}
+
+/// Shared state for a single extraction run.
+struct Extractor<'a> {
+    dest: PathBuf,
+    args: &'a ExtractArgs,
+    verbose: bool,
+    /// Applying an increment: its deletion list is acted on, not extracted
+    restore: bool,
+    stats: ExtractStats,
+    pending_dirs: Vec<PendingDir>,
+}
+
+/// Checks that a member path stays inside the destination.
+pub fn check_member_path(path: &Path) -> Result<(), &'static str> {
+    for component in path.components() {
+        match component {
+            Component::Normal(_) | Component::CurDir => {}
+            Component::ParentDir => return Err("path contains '..'"),
+            Component::RootDir | Component::Prefix(_) => return Err("absolute path"),
+        }
+    }
+    Ok(())
+}
+
+/// Checks that a symlink at `link` pointing to `target` resolves inside the destination.
+pub fn check_link_target(link: &Path, target: &Path) -> Result<(), &'static str> {
+    if target.has_root() {
+        return Err("symlink target is absolute");
+    }
+
+    // Depth of the directory containing the link, relative to the destination
+    let mut depth = link
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .count()
+        .saturating_sub(1);
+
+    for component in target.components() {
+        match component {
+            Component::ParentDir => {
+                depth = depth
+                    .checked_sub(1)
+                    .ok_or("symlink target escapes destination")?;
+            }
+            Component::Normal(_) => depth += 1,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Removes `count` leading components from a member path.
+///
+/// Returns `None` when nothing is left, in which case the member is skipped.
+pub fn strip_components(path: &Path, count: usize) -> Option<PathBuf> {
+    let stripped: PathBuf = path
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .skip(count)
+        .collect();
+
+    if stripped.as_os_str().is_empty() {
+        None
+    } else {
+        Some(stripped)
+    }
+}
+
+/// Metadata carried by a tar member's PAX header.
+#[derive(Default)]
+struct PaxMetadata {
+    /// `SCHILY.xattr.*` records
+    xattrs: Vec<(OsString, Vec<u8>)>,
+    /// `mtime` record, which keeps sub-second precision
+    mtime: Option<FileTime>,
+}
+
+/// Parses a PAX `mtime` value such as `1700000000.123456789`.
+fn parse_pax_mtime(value: &str) -> Option<FileTime> {
+    let (seconds, fraction) = value.split_once('.').unwrap_or((value, ""));
+    let seconds: i64 = seconds.parse().ok()?;
+    let digits: String = fraction.chars().take(9).collect();
+    let nanos = if digits.is_empty() {
+        0
+    } else {
+        format!("{digits:0<9}").parse().ok()?
+    };
+    Some(FileTime::from_unix_time(seconds, nanos))
+}
+
+/// Reads the xattr and mtime records from a tar member's PAX header.
+fn pax_metadata(entry: &mut tar::Entry<'_, Box<dyn Read>>) -> Result<PaxMetadata> {
+    let mut pax = PaxMetadata::default();
+    let Some(extensions) = entry.pax_extensions()? else {
+        return Ok(pax);
+    };
+
+    for extension in extensions {
+        let extension = extension?;
+        if let Some(name) = extension.key_bytes().strip_prefix(b"SCHILY.xattr.") {
+            pax.xattrs.push((
+                OsStr::from_bytes(name).to_owned(),
+                extension.value_bytes().to_vec(),
+            ));
+        } else if extension.key_bytes() == b"mtime" {
+            pax.mtime = extension.value().ok().and_then(parse_pax_mtime);
+        }
+    }
+    Ok(pax)
+}
+
+/// Writes a PAX 1.0 sparse member, recreating its holes.
+fn unpack_sparse(
+    entry: &mut tar::Entry<'_, Box<dyn Read>>,
+    target: &Path,
+    sparse: &PaxSparse,
+) -> io::Result<()> {
+    let mode = entry.header().mode()?;
+    let mtime = entry.header().mtime()?;
+
+    let mut file = File::create(target)?;
+    sparse::unpack_pax(entry, sparse.real_size, &mut file)?;
+    file.set_permissions(fs::Permissions::from_mode(mode & 0o777))?;
+    let mtime = FileTime::from_unix_time(i64::try_from(mtime).unwrap_or(0), 0);
+    filetime::set_file_handle_times(&file, None, Some(mtime))
+}
+
+impl<'a> Extractor<'a> {
+    fn new(dest: PathBuf, args: &'a ExtractArgs, verbose: bool, restore: bool) -> Self {
+        Self {
+            dest,
+            args,
+            verbose,
+            restore,
+            stats: ExtractStats::default(),
+            pending_dirs: Vec::new(),
+        }
+    }
+
+    /// Records a refused member and warns about it.
+    fn refuse(&mut self, path: &Path, reason: &str) {
+        eprintln!(
+            "{} Refusing to extract {}: {}",
+            "⚠".yellow(),
+            path.display(),
+            reason
+        );
+        self.stats.refused += 1;
+    }
+
+    /// Records a member that was written.
+    fn extracted(&mut self, path: &Path) {
+        if self.verbose {
+            eprintln!("  {}", path.display());
+        }
+        self.stats.extracted += 1;
+    }
+
+    /// Applies extended attributes to an extracted path, warning about any the
+    /// filesystem or our privileges do not allow.
+    fn restore_xattrs(target: &Path, relative: &Path, xattrs: &[(OsString, Vec<u8>)]) {
+        for (name, value) in xattrs {
+            if let Err(e) = xattr::set(target, name, value) {
+                eprintln!(
+                    "{} Could not set xattr {} on {}: {}",
+                    "⚠".yellow(),
+                    name.to_string_lossy(),
+                    relative.display(),
+                    e
+                );
+            }
+        }
+    }
+
+    /// Removes an existing non-directory at `path` so it can be replaced.
+    ///
+    /// Writing through an existing symlink would follow it, so it must go
+    /// first. When restoring, a directory is removed too if the deletion list
+    /// has emptied it: the path has stopped being a directory since the
+    /// previous run.
+    fn remove_existing(&self, path: &Path) -> io::Result<()> {
+        match path.symlink_metadata() {
+            Ok(meta) if !meta.is_dir() => fs::remove_file(path),
+            Ok(_) if self.restore => fs::remove_dir(path),
+            _ => Ok(()),
+        }
+    }
+
+    /// Validates a member path and returns its path relative to the destination.
+    fn member_path(&mut self, path: &Path) -> Option<PathBuf> {
+        if let Err(reason) = check_member_path(path) {
+            self.refuse(path, reason);
+            return None;
+        }
+        strip_components(path, self.args.strip_components)
+    }
+
+    /// Validates a symlink member at `relative` pointing to `link` and
+    /// creates its parent directories. Returns false (and refuses) if the
+    /// link would point outside the destination.
+    fn prepare_link(&mut self, relative: &Path, target: &Path, link: &Path) -> Result<bool> {
+        if let Err(reason) = check_link_target(relative, link) {
+            self.refuse(relative, reason);
+            return Ok(false);
+        }
+        if !self.prepare_parent(relative, target)? {
+            return Ok(false);
+        }
+        if let Err(reason) = self.resolve_link(target, link) {
+            self.refuse(relative, reason);
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Follows a symlink target from the link's directory through what is
+    /// already on disk.
+    ///
+    /// `..` is only allowed after an existing directory: a missing path or a
+    /// file could be replaced by a symlink later in the archive.
+    fn resolve_link(&self, target: &Path, link: &Path) -> Result<(), &'static str> {
+        let parent = target.parent().unwrap_or(&self.dest);
+        let mut current = parent
+            .canonicalize()
+            .map_err(|_| "cannot resolve the symlink's directory")?;
+        let mut on_disk = true;
+        for component in link.components() {
+            match component {
+                Component::ParentDir if !on_disk => {
+                    return Err("symlink target goes up from a path that is not a directory");
+                }
+                Component::ParentDir => {
+                    current.pop();
+                }
+                Component::Normal(name) => {
+                    current.push(name);
+                    if on_disk {
+                        match current.canonicalize() {
+                            Ok(resolved) => {
+                                on_disk = resolved.is_dir();
+                                current = resolved;
+                            }
+                            Err(_) => on_disk = false,
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        if current.starts_with(&self.dest) {
+            Ok(())
+        } else {
+            Err("symlink target resolves outside destination")
+        }
+    }
+
+    /// Creates the parent directories of `target`, verifying they resolve
+    /// inside the destination. Returns false (and refuses) if they do not.
+    fn prepare_parent(&mut self, relative: &Path, target: &Path) -> Result<bool> {
+        let Some(parent) = target.parent() else {
+            return Ok(true);
+        };
+
+        // Check the deepest existing ancestor before creating anything below it
+        let mut existing = parent;
+        while !existing.exists() {
+            match existing.parent() {
+                Some(p) => existing = p,
+                None => break,
+            }
+        }
+        let resolved = existing
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve {}", existing.display()))?;
+        if !resolved.starts_with(&self.dest) {
+            self.refuse(relative, "parent directory resolves outside destination");
+            return Ok(false);
+        }
+
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        Ok(true)
+    }
+
+    /// Creates a directory and defers its metadata until extraction finishes.
+    fn extract_dir(
+        &mut self,
+        relative: &Path,
+        mode: Option<u32>,
+        mtime: Option<FileTime>,
+    ) -> Result<()> {
+        let target = self.dest.join(relative);
+        if !self.prepare_parent(relative, &target)? {
+            return Ok(());
+        }
+        if target.symlink_metadata().is_ok_and(|m| !m.is_dir()) {
+            fs::remove_file(&target)?;
+        }
+        fs::create_dir_all(&target)
+            .with_context(|| format!("Failed to create directory: {}", target.display()))?;
+        self.pending_dirs.push(PendingDir {
+            path: target,
+            mode,
+            mtime,
+        });
+        self.extracted(relative);
+        Ok(())
+    }
+
+    /// Creates a hard link to an earlier member, returning false if refused.
+    fn extract_hard_link(&mut self, relative: &Path, target: &Path, link: &Path) -> Result<bool> {
+        if let Err(reason) = check_member_path(link) {
+            self.refuse(relative, reason);
+            return Ok(false);
+        }
+        let Some(source) = strip_components(link, self.args.strip_components) else {
+            self.refuse(relative, "hard link target was stripped");
+            return Ok(false);
+        };
+        // The link name is only text; resolve it like a write path
+        let source_path = self.dest.join(&source);
+        if source_path
+            .symlink_metadata()
+            .is_ok_and(|m| m.file_type().is_symlink())
+        {
+            self.refuse(relative, "hard link target is a symlink");
+            return Ok(false);
+        }
+        let resolved = source_path
+            .parent()
+            .and_then(|parent| parent.canonicalize().ok());
+        if resolved.is_some_and(|parent| !parent.starts_with(&self.dest)) {
+            self.refuse(relative, "hard link target resolves outside destination");
+            return Ok(false);
+        }
+        if !self.prepare_parent(relative, target)? {
+            return Ok(false);
+        }
+        self.remove_existing(target)?;
+        fs::hard_link(&source_path, target).with_context(|| {
+            format!(
+                "Failed to hard link {} to {}",
+                relative.display(),
+                source.display()
+            )
+        })?;
+        Ok(true)
+    }
+
+    /// Extracts all members of a tar stream.
+    fn extract_tar(&mut self, mut archive: reader::TarReader) -> Result<()> {
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            // PAX 1.0 sparse members carry their real name in a record
+            let sparse = sparse::pax_sparse(&mut entry)?;
+            let path = match sparse {
+                Some(ref sparse) => sparse.name.clone(),
+                None => entry.path()?.into_owned(),
+            };
+            if self.apply_deletions(&path, &mut entry)? {
+                continue;
+            }
+            let Some(relative) = self.member_path(&path) else {
+                continue;
+            };
+            let target = self.dest.join(&relative);
+            let pax = pax_metadata(&mut entry)?;
+            let xattrs = if self.args.xattrs {
+                pax.xattrs.as_slice()
+            } else {
+                &[]
+            };
+            let header = entry.header();
+
+            match header.entry_type() {
+                tar::EntryType::Directory => {
+                    let mode = header.mode().ok();
+                    let mtime = pax.mtime.or_else(|| {
+                        header
+                            .mtime()
+                            .ok()
+                            .and_then(|t| i64::try_from(t).ok())
+                            .map(|t| FileTime::from_unix_time(t, 0))
+                    });
+                    self.extract_dir(&relative, mode, mtime)?;
+                    Self::restore_xattrs(&target, &relative, xattrs);
+                }
+                tar::EntryType::Symlink => {
+                    let link = entry.link_name()?.unwrap_or_default().into_owned();
+                    if !self.prepare_link(&relative, &target, &link)? {
+                        continue;
+                    }
+                    self.remove_existing(&target)?;
+                    entry.unpack(&target)?;
+                    if let Some(mtime) = pax.mtime {
+                        filetime::set_symlink_file_times(&target, mtime, mtime)?;
+                    }
+                    Self::restore_xattrs(&target, &relative, xattrs);
+                    self.extracted(&relative);
+                }
+                tar::EntryType::Link => {
+                    let link = entry.link_name()?.unwrap_or_default().into_owned();
+                    if self.extract_hard_link(&relative, &target, &link)? {
+                        Self::restore_xattrs(&target, &relative, xattrs);
+                        self.extracted(&relative);
+                    }
+                }
+                tar::EntryType::Regular
+                | tar::EntryType::Continuous
+                | tar::EntryType::GNUSparse => {
+                    if !self.prepare_parent(&relative, &target)? {
+                        continue;
+                    }
+                    self.remove_existing(&target)?;
+                    if let Some(ref sparse) = sparse {
+                        unpack_sparse(&mut entry, &target, sparse)
+                    } else {
+                        entry.unpack(&target).map(drop)
+                    }
+                    .with_context(|| format!("Failed to extract {}", relative.display()))?;
+                    if let Some(mtime) = pax.mtime {
+                        filetime::set_file_mtime(&target, mtime)?;
+                    }
+                    Self::restore_xattrs(&target, &relative, xattrs);
+                    self.extracted(&relative);
+                }
+                other => {
+                    eprintln!(
+                        "{} Skipping unsupported entry type {:?}: {}",
+                        "⚠".yellow(),
+                        other,
+                        relative.display()
+                    );
+                    self.stats.skipped += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Extracts all members of a zip archive.
+    fn extract_zip(&mut self, mut archive: reader::ZipReader, keys: &Keys) -> Result<()> {
+        for i in 0..archive.len() {
+            let mut file = reader::zip_member(&mut archive, i, keys)?;
+            let name = PathBuf::from(file.name());
+            if self.apply_deletions(&name, &mut file)? {
+                continue;
+            }
+            let Some(relative) = self.member_path(&name) else {
+                continue;
+            };
+            let target = self.dest.join(&relative);
+            let mode = file.unix_mode();
+            let mtime = file
+                .last_modified()
+                .and_then(reader::zip_timestamp)
+                .map(|t| FileTime::from_unix_time(t, 0));
+
+            if file.is_dir() {
+                self.extract_dir(&relative, mode, mtime)?;
+            } else if file.is_symlink() {
+                let mut link = String::new();
+                file.read_to_string(&mut link)?;
+                let link = PathBuf::from(link);
+                if !self.prepare_link(&relative, &target, &link)? {
+                    continue;
+                }
+                self.remove_existing(&target)?;
+                symlink(&link, &target)
+                    .with_context(|| format!("Failed to create symlink: {}", target.display()))?;
+                if let Some(mtime) = mtime {
+                    filetime::set_symlink_file_times(&target, mtime, mtime)?;
+                }
+                self.extracted(&relative);
+            } else {
+                if !self.prepare_parent(&relative, &target)? {
+                    continue;
+                }
+                self.remove_existing(&target)?;
+                let mut out = File::create(&target)
+                    .with_context(|| format!("Failed to create file: {}", target.display()))?;
+                io::copy(&mut file, &mut out)?;
+                if let Some(mode) = mode {
+                    out.set_permissions(fs::Permissions::from_mode(mode & 0o777))?;
+                }
+                if let Some(mtime) = mtime {
+                    filetime::set_file_handle_times(&out, None, Some(mtime))?;
+                }
+                self.extracted(&relative);
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the paths in the deletion list member when restoring,
+    /// returning true if `path` was it.
+    ///
+    /// The list is the first member of an increment, so the old paths are
+    /// gone before anything of another type is written in their place.
+    fn apply_deletions(&mut self, path: &Path, member: &mut impl Read) -> Result<bool> {
+        if !self.restore || path != Path::new(incremental::DELETED_NAME) {
+            return Ok(false);
+        }
+        let mut contents = Vec::new();
+        member.read_to_end(&mut contents)?;
+        for name in incremental::parse_deleted(&contents) {
+            let Some(relative) = self.member_path(&name) else {
+                continue;
+            };
+            let target = self.dest.join(&relative);
+            let Ok(meta) = target.symlink_metadata() else {
+                continue;
+            };
+            let parent = target.parent().unwrap_or(&self.dest);
+            let resolved = parent
+                .canonicalize()
+                .with_context(|| format!("Failed to resolve {}", parent.display()))?;
+            if !resolved.starts_with(&self.dest) {
+                self.refuse(&relative, "parent directory resolves outside destination");
+                continue;
+            }
+
+            if meta.is_dir() {
+                fs::remove_dir_all(&target)
+            } else {
+                fs::remove_file(&target)
+            }
+            .with_context(|| format!("Failed to delete {}", target.display()))?;
+            if self.verbose {
+                eprintln!("  {} {}", "deleted".red(), relative.display());
+            }
+            self.stats.deleted += 1;
+        }
+        Ok(true)
+    }
+
+    /// Applies deferred directory metadata, deepest directories first.
+    fn finish_dirs(&mut self) -> Result<()> {
+        self.pending_dirs.sort_by(|a, b| b.path.cmp(&a.path));
+        for dir in &self.pending_dirs {
+            if let Some(mode) = dir.mode {
+                fs::set_permissions(&dir.path, fs::Permissions::from_mode(mode & 0o777))?;
+            }
+            if let Some(mtime) = dir.mtime {
+                filetime::set_file_mtime(&dir.path, mtime)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Extracts an archive into the destination directory.
+pub fn extract_archive(args: &ExtractArgs, verbose: bool) -> Result<ExtractStats> {
+    let keys = Keys::new(args.identity.clone());
+    unpack(args, &keys, verbose, false)
+}
+
+/// Extracts one archive of an incremental chain, removing the paths in its
+/// deletion list as it goes.
+pub fn restore_archive(args: &ExtractArgs, keys: &Keys, verbose: bool) -> Result<ExtractStats> {
+    unpack(args, keys, verbose, true)
+}
+
+/// Opens and extracts an archive, acting on its deletion list if `restore`.
+fn unpack(args: &ExtractArgs, keys: &Keys, verbose: bool, restore: bool) -> Result<ExtractStats> {
+    let archive = reader::open(&args.archive, args.format, keys)?;
+
+    fs::create_dir_all(&args.directory)
+        .with_context(|| format!("Failed to create destination: {}", args.directory.display()))?;
+    let dest = args
+        .directory
+        .canonicalize()
+        .context("Failed to resolve destination")?;
+
+    let mut extractor = Extractor::new(dest, args, verbose, restore);
+
+    match archive {
+        ArchiveReader::Zip(archive) => extractor.extract_zip(archive, keys)?,
+        ArchiveReader::Tar(archive) => extractor.extract_tar(archive)?,
+    }
+    extractor.finish_dirs()?;
+
+    Ok(extractor.stats)
+}
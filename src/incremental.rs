@@ -0,0 +1,343 @@
+//! Incremental archives (`--listed-incremental`) and restoring them.
+//!
+//! A snapshot file records the size, mtime, ctime and inode of every member
+//! after each run, as the walk found them on disk. When the snapshot already
+//! exists, only entries that are new or differ from it are archived, and the
+//! names that have disappeared since go into a `.raptar-deleted` member, one
+//! per line. `raptar restore` extracts a level-0 archive and then each
+//! increment, removing the listed names.
+//!
+//! Names in both files are raw bytes with backslashes and newlines escaped
+//! as `\\` and `\n`.
+
+use crate::walk::{DiskState, EntryType, FileEntry};
+
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+/// Name of the member listing the names deleted since the previous run.
+pub const DELETED_NAME: &str = ".raptar-deleted";
+
+/// First line of a snapshot file.
+const HEADER: &str = "raptar-snapshot 2";
+
+/// Escapes a name for one line of a snapshot or deletion list.
+fn escape(name: &Path) -> Vec<u8> {
+    let mut escaped = Vec::new();
+    for &byte in name.as_os_str().as_bytes() {
+        match byte {
+            b'\\' => escaped.extend_from_slice(b"\\\\"),
+            b'\n' => escaped.extend_from_slice(b"\\n"),
+            _ => escaped.push(byte),
+        }
+    }
+    escaped
+}
+
+/// Reverses [`escape`]; `None` for an invalid escape.
+fn unescape(line: &[u8]) -> Option<PathBuf> {
+    let mut name = Vec::with_capacity(line.len());
+    let mut bytes = line.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte == b'\\' {
+            match bytes.next()? {
+                b'\\' => name.push(b'\\'),
+                b'n' => name.push(b'\n'),
+                _ => return None,
+            }
+        } else {
+            name.push(byte);
+        }
+    }
+    Some(PathBuf::from(OsStr::from_bytes(&name)))
+}
+
+/// The state of the tree after a run, keyed by member name.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    records: BTreeMap<PathBuf, DiskState>,
+}
+
+impl Snapshot {
+    /// Records every entry that was read from the tree.
+    fn of(entries: &[FileEntry]) -> Self {
+        let records = entries
+            .iter()
+            .filter_map(|entry| Some((entry.relative_path.clone(), entry.disk?)))
+            .collect();
+        Self { records }
+    }
+
+    /// Loads a snapshot file, or returns `None` if it does not exist yet.
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        let contents = match fs::read(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read snapshot: {}", path.display()))
+            }
+        };
+        Self::parse(&contents)
+            .map(Some)
+            .with_context(|| format!("Invalid snapshot file: {}", path.display()))
+    }
+
+    fn parse(contents: &[u8]) -> Result<Self> {
+        let mut lines = contents.split(|&b| b == b'\n');
+        if lines.next() != Some(HEADER.as_bytes()) {
+            bail!(
+                "expected a {HEADER:?} header; start a new chain for snapshots from other versions"
+            );
+        }
+        let mut records = BTreeMap::new();
+        for (number, line) in lines.enumerate().filter(|(_, l)| !l.is_empty()) {
+            let record = Self::parse_line(line)
+                .with_context(|| format!("malformed record on line {}", number + 2))?;
+            records.insert(record.0, record.1);
+        }
+        Ok(Self { records })
+    }
+
+    /// Parses `<size> <mtime>.<nsec> <ctime>.<nsec> <inode> <name>`.
+    fn parse_line(line: &[u8]) -> Option<(PathBuf, DiskState)> {
+        let mut fields = line.splitn(5, |&b| b == b' ');
+        let mut number = || std::str::from_utf8(fields.next()?).ok();
+        let size = number()?.parse().ok()?;
+        let (mtime, mtime_nsec) = number()?.split_once('.')?;
+        let (mtime, mtime_nsec) = (mtime.parse().ok()?, mtime_nsec.parse().ok()?);
+        let (ctime, ctime_nsec) = number()?.split_once('.')?;
+        let (ctime, ctime_nsec) = (ctime.parse().ok()?, ctime_nsec.parse().ok()?);
+        let inode = number()?.parse().ok()?;
+        let name = unescape(fields.next()?)?;
+        Some((
+            name,
+            DiskState {
+                size,
+                mtime,
+                mtime_nsec,
+                ctime,
+                ctime_nsec,
+                inode,
+            },
+        ))
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut contents = format!("{HEADER}\n").into_bytes();
+        for (name, disk) in &self.records {
+            contents.extend_from_slice(
+                format!(
+                    "{} {}.{:09} {}.{:09} {} ",
+                    disk.size, disk.mtime, disk.mtime_nsec, disk.ctime, disk.ctime_nsec, disk.inode
+                )
+                .as_bytes(),
+            );
+            contents.extend_from_slice(&escape(name));
+            contents.push(b'\n');
+        }
+        contents
+    }
+
+    /// Writes the snapshot, replacing the old one only once it is complete.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut file = tempfile::NamedTempFile::new_in(dir)
+            .with_context(|| format!("Failed to create snapshot in {}", dir.display()))?;
+        file.write_all(&self.to_bytes())?;
+        file.persist(path)
+            .with_context(|| format!("Failed to write snapshot: {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// What an incremental run archives.
+pub struct Increment {
+    /// Snapshot to save once the archive is written
+    pub snapshot: Snapshot,
+    /// Contents of the deletion list member; `None` for a level-0 archive
+    pub deleted: Option<Vec<u8>>,
+}
+
+/// Compares the entries with the snapshot at `path`, keeping only new and
+/// changed ones.
+///
+/// Without a snapshot every entry is kept and the archive is level 0.
+pub fn select(entries: &mut Vec<FileEntry>, path: &Path) -> Result<Increment> {
+    if entries
+        .iter()
+        .any(|e| e.relative_path == Path::new(DELETED_NAME))
+    {
+        bail!("The archive already has a {DELETED_NAME} member; --listed-incremental would replace it");
+    }
+
+    let snapshot = Snapshot::of(entries);
+    let Some(previous) = Snapshot::load(path)? else {
+        return Ok(Increment {
+            snapshot,
+            deleted: None,
+        });
+    };
+
+    entries
+        .retain(|e| e.disk.is_none() || previous.records.get(&e.relative_path) != e.disk.as_ref());
+    let deleted = previous
+        .records
+        .keys()
+        .filter(|name| !snapshot.records.contains_key(*name))
+        .flat_map(|name| {
+            let mut line = escape(name);
+            line.push(b'\n');
+            line
+        })
+        .collect();
+    Ok(Increment {
+        snapshot,
+        deleted: Some(deleted),
+    })
+}
+
+/// Returns the entry describing the deletion list member.
+///
+/// It takes the newest mtime among `entries`, like the sums member.
+pub fn deleted_entry(contents: &[u8], entries: &[FileEntry]) -> FileEntry {
+    FileEntry {
+        path: PathBuf::new(),
+        relative_path: PathBuf::from(DELETED_NAME),
+        size: contents.len() as u64,
+        entry_type: EntryType::File,
+        link_target: None,
+        mode: 0o644,
+        uid: 0,
+        gid: 0,
+        uname: None,
+        gname: None,
+        mtime: entries.iter().map(|e| e.mtime).max().unwrap_or(0),
+        mtime_nsec: 0,
+        hard_link_key: None,
+        xattrs: Vec::new(),
+        device: None,
+        sparse: None,
+        disk: None,
+    }
+}
+
+/// Parses the contents of a deletion list member, skipping invalid lines.
+pub fn parse_deleted(contents: &[u8]) -> Vec<PathBuf> {
+    contents
+        .split(|&b| b == b'\n')
+        .filter(|line| !line.is_empty())
+        .filter_map(unescape)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn entry(root: &Path, name: &str) -> FileEntry {
+        let mut entry = deleted_entry(b"", &[]);
+        entry.path = root.join(name);
+        entry.relative_path = PathBuf::from(name);
+        entry.disk = Some(DiskState::of(&fs::symlink_metadata(&entry.path).unwrap()));
+        entry
+    }
+
+    fn names(entries: &[FileEntry]) -> Vec<&Path> {
+        entries.iter().map(|e| e.relative_path.as_path()).collect()
+    }
+
+    #[test]
+    fn test_escape_round_trip() {
+        let name = Path::new("odd\\dir/new\nline");
+        assert_eq!(escape(name), b"odd\\\\dir/new\\nline");
+        assert_eq!(unescape(&escape(name)).unwrap(), name);
+        assert_eq!(unescape(b"bad\\x"), None);
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let tmp = TempDir::new().unwrap();
+        fs::write(tmp.path().join("a b"), "x").unwrap();
+        let snapshot = Snapshot::of(&[entry(tmp.path(), "a b")]);
+
+        let path = tmp.path().join("snap");
+        snapshot.save(&path).unwrap();
+        assert_eq!(Snapshot::load(&path).unwrap(), Some(snapshot));
+        assert_eq!(Snapshot::load(&tmp.path().join("missing")).unwrap(), None);
+
+        fs::write(&path, "raptar-snapshot 1\n").unwrap();
+        assert!(Snapshot::load(&path).is_err());
+    }
+
+    #[test]
+    fn test_select_keeps_changed_and_lists_deleted() {
+        let tmp = TempDir::new().unwrap();
+        for name in ["same", "changed", "gone"] {
+            fs::write(tmp.path().join(name), name).unwrap();
+        }
+        let snap = tmp.path().join("snap");
+
+        let mut entries: Vec<_> = ["same", "changed", "gone"]
+            .iter()
+            .map(|n| entry(tmp.path(), n))
+            .collect();
+        let level0 = select(&mut entries, &snap).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert!(level0.deleted.is_none());
+        level0.snapshot.save(&snap).unwrap();
+
+        fs::write(tmp.path().join("changed"), "longer contents").unwrap();
+        fs::write(tmp.path().join("new"), "new").unwrap();
+        let mut entries: Vec<_> = ["same", "changed", "new"]
+            .iter()
+            .map(|n| entry(tmp.path(), n))
+            .collect();
+        let level1 = select(&mut entries, &snap).unwrap();
+        assert_eq!(names(&entries), [Path::new("changed"), Path::new("new")]);
+        assert_eq!(level1.deleted.as_deref(), Some(&b"gone\n"[..]));
+        assert_eq!(
+            parse_deleted(b"gone\n\nodd\\nname\n"),
+            [PathBuf::from("gone"), PathBuf::from("odd\nname")]
+        );
+    }
+
+    #[test]
+    fn test_select_sees_rewrite_with_mtime_put_back() {
+        let tmp = TempDir::new().unwrap();
+        let file = tmp.path().join("file");
+        fs::write(&file, "old").unwrap();
+        let snap = tmp.path().join("snap");
+        let mut entries = vec![entry(tmp.path(), "file")];
+        select(&mut entries, &snap)
+            .unwrap()
+            .snapshot
+            .save(&snap)
+            .unwrap();
+
+        let mtime = filetime::FileTime::from_last_modification_time(&fs::metadata(&file).unwrap());
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        fs::write(&file, "new").unwrap();
+        filetime::set_file_mtime(&file, mtime).unwrap();
+        let mut entries = vec![entry(tmp.path(), "file")];
+        select(&mut entries, &snap).unwrap();
+        assert_eq!(names(&entries), [Path::new("file")]);
+    }
+
+    #[test]
+    fn test_select_refuses_deleted_member_name() {
+        let tmp = TempDir::new().unwrap();
+        fs::write(tmp.path().join(DELETED_NAME), "").unwrap();
+        let mut entries = vec![entry(tmp.path(), DELETED_NAME)];
+        assert!(select(&mut entries, &tmp.path().join("snap")).is_err());
+    }
+}
@@ -0,0 +1,261 @@
+//! Listing and reading members of existing archives.
+
+use crate::encrypt::Keys;
+use crate::reader::{self, ArchiveReader};
+use crate::sparse;
+use crate::walk::{EntryType, FileEntry};
+use crate::Format;
+
+use anyhow::{bail, Result};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// Normalizes a member name for comparison (drops `./` and trailing slashes).
+pub fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .collect()
+}
+
+/// Reads the owner names of a tar member, preferring its PAX records.
+///
+/// Empty names are treated as absent.
+fn owner_names(
+    entry: &mut tar::Entry<'_, Box<dyn Read>>,
+) -> Result<(Option<String>, Option<String>)> {
+    let header = entry.header();
+    let name = |field: Result<Option<&str>, std::str::Utf8Error>| {
+        field
+            .ok()
+            .flatten()
+            .filter(|n| !n.is_empty())
+            .map(str::to_string)
+    };
+    let (mut uname, mut gname) = (name(header.username()), name(header.groupname()));
+    if let Some(extensions) = entry.pax_extensions()? {
+        for extension in extensions {
+            let extension = extension?;
+            let value = extension.value().ok().filter(|v| !v.is_empty());
+            match extension.key() {
+                Ok("uname") => uname = value.map(str::to_string),
+                Ok("gname") => gname = value.map(str::to_string),
+                _ => {}
+            }
+        }
+    }
+    Ok((uname, gname))
+}
+
+/// Converts a tar member header into a file entry for display.
+///
+/// A hard link is shown as a file whose `link_target` is the member it
+/// points to.
+pub fn tar_entry(entry: &mut tar::Entry<'_, Box<dyn Read>>) -> Result<FileEntry> {
+    let sparse = sparse::pax_sparse(entry)?;
+    let (uname, gname) = owner_names(entry)?;
+    let header = entry.header();
+    let path = match sparse {
+        Some(ref sparse) => normalize(&sparse.name),
+        None => normalize(&entry.path()?),
+    };
+
+    let (entry_type, link_target) = match header.entry_type() {
+        tar::EntryType::Symlink => (
+            EntryType::Symlink,
+            entry.link_name()?.map(std::borrow::Cow::into_owned),
+        ),
+        tar::EntryType::Link => (EntryType::File, entry.link_name()?.map(|n| normalize(&n))),
+        tar::EntryType::Directory => (EntryType::Directory, None),
+        tar::EntryType::Fifo => (EntryType::Fifo, None),
+        tar::EntryType::Char => (EntryType::CharDevice, None),
+        tar::EntryType::Block => (EntryType::BlockDevice, None),
+        _ => (EntryType::File, None),
+    };
+    let device = match (header.device_major(), header.device_minor()) {
+        (Ok(Some(major)), Ok(Some(minor)))
+            if header.entry_type().is_character_special()
+                || header.entry_type().is_block_special() =>
+        {
+            Some((major, minor))
+        }
+        _ => None,
+    };
+
+    Ok(FileEntry {
+        path: path.clone(),
+        relative_path: path,
+        size: match sparse {
+            Some(sparse) => sparse.real_size,
+            None if entry_type == EntryType::File => entry.size(),
+            None => 0,
+        },
+        entry_type,
+        link_target,
+        mode: header.mode().unwrap_or(0o644),
+        uid: header
+            .uid()
+            .ok()
+            .and_then(|u| u32::try_from(u).ok())
+            .unwrap_or(0),
+        gid: header
+            .gid()
+            .ok()
+            .and_then(|g| u32::try_from(g).ok())
+            .unwrap_or(0),
+        uname,
+        gname,
+        mtime: header.mtime().unwrap_or(0),
+        mtime_nsec: 0,
+        hard_link_key: None,
+        xattrs: Vec::new(),
+        device,
+        sparse: None,
+        disk: None,
+    })
+}
+
+/// Converts a zip member into a file entry for display.
+pub fn zip_entry<R: Read>(file: &mut zip::read::ZipFile<'_, R>) -> Result<FileEntry> {
+    let path = normalize(Path::new(file.name()));
+    let mtime = file
+        .last_modified()
+        .and_then(reader::zip_timestamp)
+        .and_then(|t| u64::try_from(t).ok())
+        .unwrap_or(0);
+
+    let (entry_type, link_target) = if file.is_dir() {
+        (EntryType::Directory, None)
+    } else if file.is_symlink() {
+        let mut target = String::new();
+        file.read_to_string(&mut target)?;
+        (EntryType::Symlink, Some(PathBuf::from(target)))
+    } else {
+        (EntryType::File, None)
+    };
+
+    Ok(FileEntry {
+        path: path.clone(),
+        relative_path: path,
+        size: if entry_type == EntryType::File {
+            file.size()
+        } else {
+            0
+        },
+        entry_type,
+        link_target,
+        mode: file.unix_mode().unwrap_or(0o644),
+        uid: 0,
+        gid: 0,
+        uname: None,
+        gname: None,
+        mtime,
+        mtime_nsec: 0,
+        hard_link_key: None,
+        xattrs: Vec::new(),
+        device: None,
+        sparse: None,
+        disk: None,
+    })
+}
+
+/// Reads the member list of an archive.
+pub fn read_entries(path: &Path, format: Option<Format>, keys: &Keys) -> Result<Vec<FileEntry>> {
+    let mut entries = Vec::new();
+
+    match reader::open(path, format, keys)? {
+        ArchiveReader::Zip(mut archive) => {
+            for i in 0..archive.len() {
+                let mut file = reader::zip_member(&mut archive, i, keys)?;
+                entries.push(zip_entry(&mut file)?);
+            }
+        }
+        ArchiveReader::Tar(mut archive) => {
+            for entry in archive.entries()? {
+                entries.push(tar_entry(&mut entry?)?);
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Hard links followed by `--cat` before giving up on a cycle.
+const MAX_LINK_HOPS: usize = 8;
+
+/// Writes the contents of a single regular-file member to `out`.
+///
+/// A hard link is followed to the member it names, which comes earlier in
+/// the archive, so the archive is read again from the start.
+pub fn cat_member<W: Write>(
+    path: &Path,
+    format: Option<Format>,
+    keys: &Keys,
+    member: &Path,
+    mut out: W,
+) -> Result<()> {
+    let mut wanted = normalize(member);
+
+    for _ in 0..MAX_LINK_HOPS {
+        match reader::open(path, format, keys)? {
+            ArchiveReader::Zip(mut archive) => {
+                for i in 0..archive.len() {
+                    if normalize(Path::new(archive.name_for_index(i).unwrap_or_default())) != wanted
+                    {
+                        continue;
+                    }
+                    let mut file = reader::zip_member(&mut archive, i, keys)?;
+                    if !file.is_file() {
+                        bail!("Not a regular file: {}", wanted.display());
+                    }
+                    io::copy(&mut file, &mut out)?;
+                    return Ok(());
+                }
+                bail!("Member not found in archive: {}", wanted.display())
+            }
+            ArchiveReader::Tar(mut archive) => match cat_tar(&mut archive, &wanted, &mut out)? {
+                Some(target) => wanted = target,
+                None => return Ok(()),
+            },
+        }
+    }
+
+    bail!("Too many hard links to follow: {}", member.display())
+}
+
+/// Writes the tar member named `wanted` to `out`, or returns the name of
+/// the member it is a hard link to.
+fn cat_tar(
+    archive: &mut reader::TarReader,
+    wanted: &Path,
+    out: &mut impl Write,
+) -> Result<Option<PathBuf>> {
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let sparse = sparse::pax_sparse(&mut entry)?;
+        let path = match sparse {
+            Some(ref sparse) => normalize(&sparse.name),
+            None => normalize(&entry.path()?),
+        };
+        if path != wanted {
+            continue;
+        }
+        let kind = entry.header().entry_type();
+        if kind == tar::EntryType::Link {
+            if let Some(target) = entry.link_name()? {
+                return Ok(Some(normalize(&target)));
+            }
+        }
+        if !kind.is_file() && !kind.is_gnu_sparse() {
+            bail!("Not a regular file: {}", wanted.display());
+        }
+        match sparse {
+            Some(sparse) => sparse::expand_pax(&mut entry, sparse.real_size, out)?,
+            None => {
+                io::copy(&mut entry, out)?;
+            }
+        }
+        return Ok(None);
+    }
+
+    bail!("Member not found in archive: {}", wanted.display())
+}
@@ -7,7 +7,7 @@
 //! - Respects `.gitignore` and `.ignore` by default
 //! - Opt-in support for `.dockerignore`, `.npmignore`, and other ignore files
 //! - Configuration file at `~/.config/raptar/config.toml`
-//! - Multiple output formats: tar, tar.gz, tar.bz2, tar.zst, zip
+//! - Multiple output formats: tar, tar.gz, tar.bz2, tar.zst, tar.xz, tar.lz4, tar.br, zip
 //! - Reproducible builds with deterministic ordering and timestamps
 //! - Symlink preservation
 //! - Ownership and permission preservation
@@ -20,20 +20,40 @@
 //! override earlier ones, following standard gitignore semantics.
 
 mod archive;
+mod checksum;
 mod config;
 mod ecosystem;
+mod encrypt;
+mod extract;
+mod incremental;
+mod list;
+mod mtime;
+mod owner;
+mod parallel;
+mod reader;
 mod rules;
+mod sign;
+mod sparse;
+mod split;
+mod transform;
+mod verify;
 mod walk;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use bytesize::ByteSize;
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
 use colored::Colorize;
-use std::fs::File;
-use std::io::BufWriter;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, IsTerminal, Seek, Write};
 use std::path::{Path, PathBuf};
 
+use archive::{CompressionOptions, TarOptions, ZipOptions};
+use checksum::HashingWriter;
 pub use config::Config;
+use encrypt::Encryption;
+use owner::{AccountDb, Ownership};
+use sign::PrehashWriter;
 pub use walk::{EntryType, ExcludedFile, FileEntry};
 
 /// Supported archive formats.
@@ -50,11 +70,123 @@ pub enum Format {
     /// Zstandard-compressed tar archive
     #[value(name = "tar.zst", alias = "tzst")]
     TarZst,
+    /// Xz-compressed tar archive
+    #[value(name = "tar.xz", alias = "txz")]
+    TarXz,
+    /// Lz4-compressed tar archive
+    #[value(name = "tar.lz4", alias = "tlz4")]
+    TarLz4,
+    /// Brotli-compressed tar archive
+    #[value(name = "tar.br")]
+    TarBr,
     /// Zip archive
     Zip,
 }
 
+/// How symlinks are stored in zip archives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ZipSymlinks {
+    /// Store as Unix symlinks (restored by unzip, Python and most tools)
+    #[default]
+    Link,
+    /// Store the file the symlink points to
+    Follow,
+    /// Leave symlinks out
+    Skip,
+}
+
+/// Digest algorithm for `--checksums`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ChecksumAlgorithm {
+    /// SHA-256, checked with `sha256sum -c`
+    Sha256,
+    /// BLAKE3, checked with `b3sum -c`
+    Blake3,
+}
+
+impl ChecksumAlgorithm {
+    /// Returns the name of the embedded sums member.
+    const fn sums_name(self) -> &'static str {
+        match self {
+            Self::Sha256 => "SHA256SUMS",
+            Self::Blake3 => "B3SUMS",
+        }
+    }
+
+    /// Returns the extension of the archive checksum file.
+    const fn extension(self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Blake3 => "b3",
+        }
+    }
+}
+
+/// Compression method for zip members.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ZipMethod {
+    /// No compression
+    Store,
+    /// Deflate (readable everywhere)
+    #[default]
+    Deflate,
+    /// Bzip2
+    Bzip2,
+    /// Zstandard (needs a recent unzip)
+    Zstd,
+}
+
+/// Header format for tar archives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum TarFormat {
+    /// GNU tar (long names via GNU extensions)
+    #[default]
+    Gnu,
+    /// POSIX.1-2001 pax (long paths, large ids, sub-second mtimes, UTF-8 names)
+    Pax,
+    /// POSIX.1-1988 ustar (names up to 100 bytes, or 255 split at a /)
+    Ustar,
+    /// Unix V7 (names up to 100 bytes)
+    V7,
+}
+
+impl TarFormat {
+    /// Returns true if the format can carry PAX extended headers.
+    const fn supports_pax(self) -> bool {
+        matches!(self, Self::Gnu | Self::Pax)
+    }
+}
+
+impl ZipMethod {
+    /// Returns the accepted compression level range, or `None` if uncompressed.
+    const fn level_range(self) -> Option<(i32, i32)> {
+        match self {
+            Self::Store => None,
+            Self::Deflate | Self::Bzip2 => Some((1, 9)),
+            Self::Zstd => Some((-7, 22)),
+        }
+    }
+}
+
 impl Format {
+    /// Returns the accepted compression level range, or `None` if uncompressed.
+    const fn level_range(self) -> Option<(i32, i32)> {
+        match self {
+            Self::Tar => None,
+            Self::TarGz | Self::TarXz => Some((0, 9)),
+            Self::TarBz2 | Self::Zip => Some((1, 9)),
+            Self::TarZst => Some((-7, 22)),
+            Self::TarLz4 => Some((0, 12)),
+            Self::TarBr => Some((0, 11)),
+        }
+    }
+
+    /// Returns true if the format can compress on multiple threads.
+    const fn supports_threads(self) -> bool {
+        matches!(self, Self::TarGz | Self::TarBz2 | Self::TarZst)
+    }
+
     /// Returns the file extension for this format.
     const fn extension(self) -> &'static str {
         match self {
@@ -62,9 +194,171 @@ impl Format {
             Self::TarGz => "tar.gz",
             Self::TarBz2 => "tar.bz2",
             Self::TarZst => "tar.zst",
+            Self::TarXz => "tar.xz",
+            Self::TarLz4 => "tar.lz4",
+            Self::TarBr => "tar.br",
             Self::Zip => "zip",
         }
     }
+
+    /// Guesses the format from a file name, accepting aliases like `.tgz`.
+    fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        Self::value_variants()
+            .iter()
+            .copied()
+            .filter_map(|format| {
+                let value = format.to_possible_value()?;
+                let ext = value
+                    .get_name_and_aliases()
+                    .filter(|ext| name.ends_with(&format!(".{ext}")))
+                    .map(str::len)
+                    .max()?;
+                Some((format, ext))
+            })
+            .max_by_key(|&(_, len)| len)
+            .map(|(format, _)| format)
+    }
+}
+
+/// Operations on existing archives.
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Safely unpack an archive in any supported format
+    Extract(ExtractArgs),
+    /// List the contents of an archive
+    List(ListArgs),
+    /// Check an archive's minisign signature
+    VerifySignature(VerifySignatureArgs),
+    /// Compare an archive with the directory it was built from
+    Verify(VerifyArgs),
+    /// Extract a level-0 archive and then its increments, applying deletions
+    Restore(RestoreArgs),
+}
+
+/// Arguments for `raptar restore`.
+#[derive(clap::Args, Debug)]
+pub struct RestoreArgs {
+    /// Level-0 archive followed by its increments, oldest first
+    #[arg(required = true)]
+    pub archives: Vec<PathBuf>,
+
+    /// Destination directory (defaults to current directory)
+    #[arg(
+        short = 'C',
+        long = "directory",
+        default_value = ".",
+        value_name = "DIR"
+    )]
+    pub directory: PathBuf,
+
+    /// Strip N leading path components from member names
+    #[arg(long, default_value_t = 0, value_name = "N")]
+    pub strip_components: usize,
+
+    /// Restore extended attributes stored in the archives (tar formats)
+    #[arg(long)]
+    pub xattrs: bool,
+
+    /// age identity file or SSH private key for encrypted archives (can be repeated)
+    #[arg(short, long, value_name = "FILE")]
+    pub identity: Vec<PathBuf>,
+
+    /// Archive format (detected from contents if omitted)
+    #[arg(short, long, value_enum)]
+    pub format: Option<Format>,
+}
+
+/// Arguments for `raptar verify`.
+///
+/// The tree is scanned with the archiving options given before `verify`
+/// (and the config), so pass the ones the archive was made with.
+#[derive(clap::Args, Debug)]
+pub struct VerifyArgs {
+    /// Archive to check
+    pub archive: PathBuf,
+
+    /// Directory the archive was made from (defaults to current directory)
+    #[arg(default_value = ".")]
+    pub path: PathBuf,
+
+    /// age identity file or SSH private key for encrypted archives (can be repeated)
+    #[arg(short, long, value_name = "FILE")]
+    pub identity: Vec<PathBuf>,
+
+    /// Archive format (detected from contents if omitted)
+    #[arg(short, long, value_enum)]
+    pub format: Option<Format>,
+}
+
+/// Arguments for `raptar verify-signature`.
+#[derive(clap::Args, Debug)]
+pub struct VerifySignatureArgs {
+    /// Signed archive
+    pub archive: PathBuf,
+
+    /// Minisign public key file
+    #[arg(short = 'p', long, value_name = "FILE")]
+    pub public_key: PathBuf,
+
+    /// Signature file (defaults to <ARCHIVE>.minisig)
+    #[arg(short = 'x', long, value_name = "FILE")]
+    pub signature: Option<PathBuf>,
+}
+
+/// Arguments for `raptar extract`.
+#[derive(clap::Args, Debug)]
+pub struct ExtractArgs {
+    /// Archive to extract
+    pub archive: PathBuf,
+
+    /// Destination directory (defaults to current directory)
+    #[arg(
+        short = 'C',
+        long = "directory",
+        default_value = ".",
+        value_name = "DIR"
+    )]
+    pub directory: PathBuf,
+
+    /// Strip N leading path components from member names
+    #[arg(long, default_value_t = 0, value_name = "N")]
+    pub strip_components: usize,
+
+    /// Restore extended attributes stored in the archive (tar formats)
+    #[arg(long)]
+    pub xattrs: bool,
+
+    /// age identity file or SSH private key for encrypted archives (can be repeated)
+    #[arg(short, long, value_name = "FILE")]
+    pub identity: Vec<PathBuf>,
+
+    /// Archive format (detected from contents if omitted)
+    #[arg(short, long, value_enum)]
+    pub format: Option<Format>,
+}
+
+/// Arguments for `raptar list`.
+#[derive(clap::Args, Debug)]
+pub struct ListArgs {
+    /// Archive to list
+    pub archive: PathBuf,
+
+    /// Long format - show mode, owner and modification time
+    #[arg(short, long)]
+    pub long: bool,
+
+    /// Print the contents of one member to stdout instead of listing
+    #[arg(long, value_name = "MEMBER")]
+    pub cat: Option<PathBuf>,
+
+    /// age identity file or SSH private key for encrypted archives (can be repeated)
+    #[arg(short, long, value_name = "FILE")]
+    pub identity: Vec<PathBuf>,
+
+    /// Archive format (detected from contents if omitted)
+    #[arg(short, long, value_enum)]
+    pub format: Option<Format>,
 }
 
 /// 🦖 raptar - A smart archive tool that respects .gitignore and friends
@@ -72,11 +366,14 @@ impl Format {
 #[command(name = "raptar")]
 #[command(version, about, long_about = None)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Directory to archive (defaults to current directory)
     #[arg(default_value = ".")]
     pub path: PathBuf,
 
-    /// Output file (defaults to directory name with appropriate extension)
+    /// Output file (defaults to directory name with appropriate extension; `-` for stdout)
     #[arg(short, long)]
     pub output: Option<PathBuf>,
 
@@ -92,6 +389,18 @@ pub struct Args {
     #[arg(short, long)]
     pub size: bool,
 
+    /// Compression level (range depends on format; overrides config)
+    #[arg(long, allow_negative_numbers = true, value_name = "N")]
+    pub level: Option<i32>,
+
+    /// Compression threads for tar.gz, tar.bz2 and tar.zst (0 = all cores)
+    #[arg(long, value_name = "N")]
+    pub threads: Option<u32>,
+
+    /// Split the archive into parts of at most SIZE bytes (e.g. 500M, 2GiB; at most 1000 parts)
+    #[arg(long, value_name = "SIZE")]
+    pub split_size: Option<ByteSize>,
+
     // ========================================================================
     // Include/Exclude patterns
     // ========================================================================
@@ -141,20 +450,125 @@ pub struct Args {
     #[arg(long)]
     pub dereference: bool,
 
-    /// Preserve file ownership (uid/gid)
+    /// Preserve file ownership (uid/gid, with names from /etc/passwd and /etc/group)
     #[arg(long)]
     pub preserve_owner: bool,
 
-    /// Deterministic ordering and zero timestamps for reproducible archives
+    /// Store every member as owned by this user (tar formats)
+    #[arg(long, value_name = "NAME[:UID]")]
+    pub owner: Option<String>,
+
+    /// Store every member as owned by this group (tar formats)
+    #[arg(long, value_name = "NAME[:GID]")]
+    pub group: Option<String>,
+
+    /// Store only numeric ids, leaving the user and group names empty
+    #[arg(long)]
+    pub numeric_owner: bool,
+
+    /// Rewrite local users with `SOURCE DEST` lines from FILE (implies --preserve-owner)
+    ///
+    /// Local names are read from /etc/passwd only; accounts from NSS, LDAP or
+    /// the macOS directory are not seen, so give them as +ID.
+    #[arg(long, value_name = "FILE")]
+    pub owner_map: Option<PathBuf>,
+
+    /// Rewrite local groups with `SOURCE DEST` lines from FILE (implies --preserve-owner)
+    ///
+    /// Local names are read from /etc/group only; groups from NSS, LDAP or
+    /// the macOS directory are not seen, so give them as +ID.
+    #[arg(long, value_name = "FILE")]
+    pub group_map: Option<PathBuf>,
+
+    /// Header format for tar archives
+    #[arg(long, value_enum, default_value = "gnu", value_name = "FORMAT")]
+    pub tar_format: TarFormat,
+
+    /// Compression method for zip members (overrides config)
+    #[arg(long, value_enum, value_name = "METHOD")]
+    pub zip_method: Option<ZipMethod>,
+
+    /// Store extended attributes (`SELinux` labels, ACLs, capabilities) in tar formats
+    #[arg(long)]
+    pub xattrs: bool,
+
+    /// How to store symlinks in zip archives
+    #[arg(long, value_enum, default_value = "link", value_name = "POLICY")]
+    pub zip_symlinks: ZipSymlinks,
+
+    /// Store only the data regions of files with holes (tar formats)
+    #[arg(long)]
+    pub sparse: bool,
+
+    /// Leave out directories that contain no archived files
+    ///
+    /// Without it, directories that are empty on disk are archived, but a
+    /// directory whose contents were all excluded is always left out.
+    #[arg(long)]
+    pub no_empty_dirs: bool,
+
+    /// Rename members with a sed-like rule, e.g. 's,^dist/,,' (can be repeated)
+    #[arg(long = "transform", action = clap::ArgAction::Append, value_name = "EXPR")]
+    pub transform: Vec<String>,
+
+    /// Place every member under DIR ({dirname}, {date}; '' disables the config default)
+    #[arg(long, value_name = "DIR")]
+    pub prefix: Option<String>,
+
+    /// Archive only what changed since the run recorded in SNAPSHOT, then update it
+    #[arg(long, value_name = "SNAPSHOT")]
+    pub listed_incremental: Option<PathBuf>,
+
+    /// Write <output>.sha256 (or .b3) with the archive's digest
+    #[arg(long, value_enum, value_name = "ALGORITHM")]
+    pub checksums: Option<ChecksumAlgorithm>,
+
+    /// Also store a SHA256SUMS (or B3SUMS) member listing every file's digest
+    #[arg(long, requires = "checksums")]
+    pub embed_checksums: bool,
+
+    /// Write a minisign signature to <output>.minisig with this secret key
+    #[arg(long, value_name = "SECRET_KEY")]
+    pub sign: Option<PathBuf>,
+
+    /// Do not sign, even if the config sets a key
+    #[arg(long, conflicts_with = "sign")]
+    pub no_sign: bool,
+
+    /// Encrypt with age to RECIPIENT, an age or SSH public key (tar formats; can be repeated)
+    #[arg(long, action = clap::ArgAction::Append, value_name = "RECIPIENT")]
+    pub encrypt_to: Vec<String>,
+
+    /// Encrypt with a passphrase: age for tar formats, AES-256 members for zip
+    #[arg(long, conflicts_with = "encrypt_to")]
+    pub passphrase: bool,
+
+    /// Deterministic ordering and zero timestamps (clamped to `SOURCE_DATE_EPOCH` if set)
     #[arg(short, long)]
     pub reproducible: bool,
 
+    /// Store 0644/0755 permissions without setuid/setgid/sticky bits (default with -r)
+    #[arg(long, overrides_with = "no_normalize_modes")]
+    pub normalize_modes: bool,
+
+    /// Store permissions exactly as found on disk, even with -r
+    #[arg(long, overrides_with = "normalize_modes")]
+    pub no_normalize_modes: bool,
+
+    /// Set every mtime to WHEN: @EPOCH, YYYY-MM-DD[THH:MM:SS], or git (last commit)
+    #[arg(long, value_name = "WHEN")]
+    pub mtime: Option<String>,
+
+    /// With --mtime, only lower mtimes newer than WHEN
+    #[arg(long, requires = "mtime")]
+    pub clamp_mtime: bool,
+
     /// Minimal output
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     pub quiet: bool,
 
     /// Verbose output - show rules and exclusion reasons
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     pub verbose: bool,
 
     /// Show config file location and current settings
@@ -170,67 +584,195 @@ pub struct Args {
     pub edit_config: bool,
 }
 
-/// Displays a preview of files that would be archived.
-fn preview_files(entries: &[FileEntry], excluded: &[ExcludedFile], args: &Args) {
-    let total_size: u64 = entries.iter().map(|e| e.size).sum();
-    let symlink_count = entries
-        .iter()
-        .filter(|e| e.entry_type == EntryType::Symlink)
-        .count();
+impl Args {
+    /// Whether the archive is written to stdout (`-o -`).
+    pub fn streams_to_stdout(&self) -> bool {
+        self.output.as_deref() == Some(Path::new("-"))
+    }
+
+    /// Whether the archive is wrapped in age (tar formats with encryption).
+    pub fn age_encrypted(&self) -> bool {
+        self.format != Format::Zip && (!self.encrypt_to.is_empty() || self.passphrase)
+    }
+
+    /// Where progress messages and listings go: stderr when stdout carries
+    /// the archive, stdout otherwise.
+    pub fn human_output(&self) -> Box<dyn Write> {
+        if self.streams_to_stdout() {
+            Box::new(io::stderr())
+        } else {
+            Box::new(io::stdout())
+        }
+    }
+}
+
+/// Formats a Unix mode as an `ls -l` style permission string.
+fn format_mode(entry_type: EntryType, mode: u32) -> String {
+    let kind = match entry_type {
+        EntryType::Directory => 'd',
+        EntryType::Symlink => 'l',
+        EntryType::File => '-',
+        EntryType::Fifo => 'p',
+        EntryType::CharDevice => 'c',
+        EntryType::BlockDevice => 'b',
+        EntryType::Socket => 's',
+    };
+    let mut out = String::with_capacity(10);
+    out.push(kind);
+    for shift in [6, 3, 0] {
+        let bits = (mode >> shift) & 0o7;
+        out.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        out.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        out.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+    }
+    out
+}
 
-    println!("{}", "Files to be archived:".bold().green());
-    println!();
+/// Formats a Unix timestamp as `YYYY-MM-DD HH:MM` (UTC).
+fn format_mtime(mtime: u64) -> String {
+    let dt = i64::try_from(mtime)
+        .ok()
+        .and_then(|t| time::OffsetDateTime::from_unix_timestamp(t).ok())
+        .unwrap_or(time::OffsetDateTime::UNIX_EPOCH);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        dt.year(),
+        u8::from(dt.month()),
+        dt.day(),
+        dt.hour(),
+        dt.minute()
+    )
+}
 
+/// Prints one line per entry, optionally with sizes and long-format metadata.
+fn print_entries(out: &mut dyn Write, entries: &[FileEntry], size: bool, long: bool) -> Result<()> {
     for entry in entries {
+        // Files only have a link target when they are listed hard links
         let type_indicator = match entry.entry_type {
             EntryType::Symlink => " -> ",
-            EntryType::File | EntryType::Directory => "",
+            _ => " link to ",
         };
 
-        if args.size {
-            let size_str = if entry.entry_type == EntryType::Symlink {
-                "    link".to_string()
-            } else {
-                format!("{:>10}", ByteSize(entry.size))
+        write!(out, "  ")?;
+
+        if long {
+            let user = entry.uname.clone().unwrap_or_else(|| entry.uid.to_string());
+            let group = entry.gname.clone().unwrap_or_else(|| entry.gid.to_string());
+            write!(
+                out,
+                "{} {:>11} {} ",
+                format_mode(entry.entry_type, entry.mode),
+                format!("{user}/{group}"),
+                format_mtime(entry.mtime)
+            )?;
+        }
+
+        if size {
+            let size_str = match entry.entry_type {
+                EntryType::Symlink => "    link".to_string(),
+                EntryType::Directory => "     dir".to_string(),
+                EntryType::File if entry.link_target.is_some() => "    hard".to_string(),
+                EntryType::File => format!("{:>10}", ByteSize(entry.size)),
+                EntryType::Fifo => "    fifo".to_string(),
+                EntryType::CharDevice | EntryType::BlockDevice => "  device".to_string(),
+                EntryType::Socket => "  socket".to_string(),
             };
-            print!("  {} ", size_str.dimmed());
-        } else {
-            print!("  ");
+            write!(out, "{} ", size_str.dimmed())?;
         }
 
-        print!("{}", entry.relative_path.display());
+        write!(out, "{}", entry.relative_path.display())?;
+        if entry.entry_type == EntryType::Directory {
+            write!(out, "/")?;
+        }
 
         if let Some(ref target) = entry.link_target {
-            print!(
+            write!(
+                out,
                 "{}{}",
                 type_indicator.cyan(),
                 target.display().to_string().cyan()
-            );
+            )?;
         }
-        println!();
+        if size && entry.sparse.is_some() {
+            write!(
+                out,
+                " {}",
+                format!("(sparse, {} data)", ByteSize(entry.stored_size())).dimmed()
+            )?;
+        }
+        writeln!(out)?;
     }
+    Ok(())
+}
 
-    println!();
-    println!(
-        "{} {} files ({} symlinks), {} total",
+/// Counts the directory entries in a list.
+fn count_dirs(entries: &[FileEntry]) -> usize {
+    entries
+        .iter()
+        .filter(|e| e.entry_type == EntryType::Directory)
+        .count()
+}
+
+/// Prints the summary line with file, symlink and size totals.
+fn print_entries_summary(out: &mut dyn Write, entries: &[FileEntry]) -> Result<()> {
+    let total_size: u64 = entries.iter().map(|e| e.size).sum();
+    let symlink_count = entries
+        .iter()
+        .filter(|e| e.entry_type == EntryType::Symlink)
+        .count();
+    let dir_count = count_dirs(entries);
+
+    write!(
+        out,
+        "{} {} files ({} symlinks), {} directories, {} total",
         "Summary:".bold(),
-        entries.len(),
+        entries.len() - dir_count,
         symlink_count,
+        dir_count,
         ByteSize(total_size)
-    );
+    )?;
+    if entries.iter().any(|e| e.sparse.is_some()) {
+        let stored: u64 = entries.iter().map(FileEntry::stored_size).sum();
+        write!(
+            out,
+            " ({} logical, {} physical)",
+            ByteSize(total_size),
+            ByteSize(stored)
+        )?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Displays a preview of files that would be archived.
+fn preview_files(
+    out: &mut dyn Write,
+    entries: &[FileEntry],
+    excluded: &[ExcludedFile],
+    args: &Args,
+) -> Result<()> {
+    writeln!(out, "{}", "Files to be archived:".bold().green())?;
+    writeln!(out)?;
+
+    print_entries(out, entries, args.size, false)?;
+
+    writeln!(out)?;
+    print_entries_summary(out, entries)?;
 
     // Show excluded files in verbose mode
     if args.verbose && !excluded.is_empty() {
-        println!();
-        println!("{}", "Files excluded:".bold().yellow());
+        writeln!(out)?;
+        writeln!(out, "{}", "Files excluded:".bold().yellow())?;
         for file in excluded {
-            println!(
+            writeln!(
+                out,
                 "  {} {}",
                 file.path.display().to_string().dimmed(),
                 format!("({})", file.origin).dimmed()
-            );
+            )?;
         }
     }
+    Ok(())
 }
 
 /// Determines the output path for the archive.
@@ -244,10 +786,12 @@ fn get_output_path(args: &Args) -> Result<PathBuf> {
         |s| s.to_string_lossy().to_string(),
     );
 
+    let suffix = if args.age_encrypted() { ".age" } else { "" };
     Ok(PathBuf::from(format!(
-        "{}.{}",
+        "{}.{}{}",
         dir_name,
-        args.format.extension()
+        args.format.extension(),
+        suffix
     )))
 }
 
@@ -289,10 +833,30 @@ fn apply_config_defaults(args: &mut Args, config: &Config) {
     if config.defaults.preserve_owner && !args.preserve_owner {
         args.preserve_owner = true;
     }
+    if args.prefix.is_none() {
+        args.prefix.clone_from(&config.defaults.prefix);
+    }
+    if args.sign.is_none() && !args.no_sign {
+        args.sign = config.defaults.sign.as_deref().map(config::expand_home);
+    }
 }
 
 /// Exclude the output file from entries to prevent infinite archive growth.
-fn exclude_output_file(entries: &mut Vec<FileEntry>, output: &Path, quiet: bool) {
+///
+/// Nothing to do when streaming to stdout (`-`). When splitting, parts and
+/// the manifest left by an earlier run are excluded too, and so are the
+/// checksum and signature files. The `--sign` secret key and the
+/// `--listed-incremental` snapshot are never archived.
+fn exclude_output_file(entries: &mut Vec<FileEntry>, output: &Path, args: &Args) {
+    for private in [&args.sign, &args.listed_incremental] {
+        if let Some(path) = private.as_ref().and_then(|path| path.canonicalize().ok()) {
+            entries.retain(|e| e.path != path);
+        }
+    }
+    if output == Path::new("-") {
+        return;
+    }
+
     let abs_output = output.canonicalize().or_else(|_| {
         // File doesn't exist yet, canonicalize parent and append filename
         output
@@ -304,8 +868,21 @@ fn exclude_output_file(entries: &mut Vec<FileEntry>, output: &Path, quiet: bool)
 
     if let Ok(abs_output) = abs_output {
         let before_count = entries.len();
-        entries.retain(|e| e.path != abs_output);
-        if entries.len() < before_count && !quiet {
+        let split = args.split_size.is_some();
+        let checksum = args
+            .checksums
+            .map(|alg| checksum::sidecar_path(&abs_output, alg));
+        let signature = args
+            .sign
+            .as_ref()
+            .map(|_| sign::signature_path(&abs_output));
+        entries.retain(|e| {
+            e.path != abs_output
+                && !(split && split::belongs_to(&e.path, &abs_output))
+                && checksum.as_ref() != Some(&e.path)
+                && signature.as_ref() != Some(&e.path)
+        });
+        if entries.len() < before_count && !args.quiet {
             eprintln!(
                 "{} Excluding output file from archive: {}",
                 "ℹ".blue(),
@@ -315,78 +892,741 @@ fn exclude_output_file(entries: &mut Vec<FileEntry>, output: &Path, quiet: bool)
     }
 }
 
-/// Create the archive in the specified format.
-fn create_archive(output: &Path, entries: &[FileEntry], args: &Args) -> Result<()> {
-    let file = File::create(output)?;
-    let writer = BufWriter::new(file);
+/// Fails if an entry has the name of the sums member `--embed-checksums`
+/// would add.
+fn check_sums_member(entries: &[FileEntry], args: &Args) -> Result<()> {
+    let Some(algorithm) = args.checksums.filter(|_| args.embed_checksums) else {
+        return Ok(());
+    };
+    let name = algorithm.sums_name();
+    if entries.iter().any(|e| e.relative_path == Path::new(name)) {
+        anyhow::bail!(
+            "The archive already has a {name} member; --embed-checksums would replace it"
+        );
+    }
+    Ok(())
+}
 
-    match args.format {
-        Format::Tar => {
-            archive::create_tar(
-                writer,
-                entries,
-                args.reproducible,
-                args.preserve_owner,
-                args.quiet,
-                args.verbose,
-            )?;
+/// Keeps only the entries that changed since the `--listed-incremental`
+/// snapshot, adding the deletion list member to an increment. Returns the
+/// snapshot to save once the archive is written.
+fn select_increment(
+    entries: &mut Vec<FileEntry>,
+    args: &Args,
+    tar: &mut TarOptions,
+    zip: &mut ZipOptions,
+) -> Result<Option<incremental::Snapshot>> {
+    let Some(ref path) = args.listed_incremental else {
+        return Ok(None);
+    };
+    let increment = incremental::select(entries, path)?;
+    tar.deleted.clone_from(&increment.deleted);
+    zip.deleted = increment.deleted;
+    Ok(Some(increment.snapshot))
+}
+
+/// Reports entries the chosen tar header format cannot represent.
+///
+/// Fails before anything is written, unless only previewing.
+fn check_tar_format(entries: &[FileEntry], tar: &TarOptions, preview: bool) -> Result<()> {
+    let problems = archive::tar_format_problems(entries, tar);
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    let name = tar
+        .format
+        .to_possible_value()
+        .map_or_else(String::new, |v| v.get_name().to_string());
+    eprintln!(
+        "{} {} entries cannot be stored in {} format:",
+        "⚠".yellow(),
+        problems.len(),
+        name
+    );
+    for problem in &problems {
+        eprintln!("  {problem}");
+    }
+
+    if preview {
+        return Ok(());
+    }
+    anyhow::bail!("Use --tar-format pax or gnu to archive these entries")
+}
+
+/// Rejects option combinations the chosen format or output cannot honor.
+fn check_option_combinations(args: &Args) -> Result<()> {
+    if args.xattrs && args.format == Format::Zip {
+        anyhow::bail!("--xattrs needs a tar format; zip cannot store extended attributes");
+    }
+    if args.xattrs && !args.tar_format.supports_pax() {
+        anyhow::bail!("--xattrs needs PAX headers; use --tar-format pax or gnu");
+    }
+    if args.sparse && (args.format == Format::Zip || !args.tar_format.supports_pax()) {
+        anyhow::bail!("--sparse needs a tar format with --tar-format gnu or pax");
+    }
+    let sets_owners = args.owner.is_some()
+        || args.group.is_some()
+        || args.owner_map.is_some()
+        || args.group_map.is_some();
+    if sets_owners && args.format == Format::Zip {
+        anyhow::bail!(
+            "--owner, --group and the owner maps need a tar format; zip stores no owners"
+        );
+    }
+    let has_maps = args.owner_map.is_some() || args.group_map.is_some();
+    if has_maps && args.reproducible && !args.preserve_owner {
+        anyhow::bail!(
+            "--owner-map and --group-map keep the owners found on disk, which --reproducible \
+             leaves out; add --preserve-owner to store them anyway"
+        );
+    }
+    if let Some(size) = args.split_size {
+        if args.format == Format::Zip {
+            anyhow::bail!(
+                "--split-size cannot split zip archives: raptar does not write spanned zips, \
+                 and raw zip chunks are not readable on their own; use a tar format"
+            );
         }
-        Format::TarGz => {
-            archive::create_tar_gz(
-                writer,
-                entries,
-                args.reproducible,
-                args.preserve_owner,
-                args.quiet,
-                args.verbose,
-            )?;
+        if args.streams_to_stdout() {
+            anyhow::bail!("--split-size writes part files and cannot be used with -o -");
+        }
+        if size.as_u64() == 0 {
+            anyhow::bail!("--split-size must be greater than zero");
         }
+    }
+    if args.sign.is_some() && args.streams_to_stdout() {
+        anyhow::bail!("--sign writes <output>.minisig and cannot be used with -o -");
+    }
+    if !args.encrypt_to.is_empty() && args.format == Format::Zip {
+        anyhow::bail!(
+            "Zip members are encrypted with a passphrase (AES-256); use --passphrase, \
+             or a tar format with --encrypt-to"
+        );
+    }
+    if args.passphrase
+        && args.format == Format::Zip
+        && (args.checksums.is_some() || args.sign.is_some())
+    {
+        anyhow::bail!(
+            "--checksums and --sign hash a zip as it is streamed, and AES-encrypted \
+             members cannot be streamed; use a tar format with --passphrase"
+        );
+    }
+    if args.streams_to_stdout() && !args.preview && io::stdout().is_terminal() {
+        anyhow::bail!("Refusing to write archive data to a terminal; redirect stdout or pipe it");
+    }
+    Ok(())
+}
+
+/// Resolve the zip compression method from CLI args and config.
+fn zip_method(args: &Args, config: &Config) -> ZipMethod {
+    args.zip_method.or(config.zip.method).unwrap_or_default()
+}
+
+/// Whether to store normalized permissions: CLI flags, then the config
+/// default, then on for reproducible archives.
+fn normalize_modes(args: &Args, config: &Config) -> bool {
+    if args.normalize_modes || args.no_normalize_modes {
+        return args.normalize_modes;
+    }
+    config.defaults.normalize_modes.unwrap_or(args.reproducible)
+}
+
+/// Builds the owner settings for tar headers, reading the local account
+/// databases and any map files.
+///
+/// Map files name local accounts, so they turn on `--preserve-owner`; in
+/// reproducible mode that has to be asked for explicitly (see
+/// [`check_option_combinations`]).
+fn ownership(args: &Args) -> Result<Ownership> {
+    let users = AccountDb::load(Path::new("/etc/passwd"));
+    let groups = AccountDb::load(Path::new("/etc/group"));
+    let read_map = |path: &Path, db: &AccountDb, kind: &str| -> Result<_> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {kind} map {}", path.display()))?;
+        db.parse_map(&contents, kind)
+            .with_context(|| format!("Invalid {kind} map {}", path.display()))
+    };
+
+    let owner_map = match args.owner_map {
+        Some(ref path) => read_map(path, &users, "user")?,
+        None => HashMap::new(),
+    };
+    let group_map = match args.group_map {
+        Some(ref path) => read_map(path, &groups, "group")?,
+        None => HashMap::new(),
+    };
+    let has_maps = args.owner_map.is_some() || args.group_map.is_some();
+
+    Ok(Ownership {
+        preserve: (args.preserve_owner && !args.reproducible) || has_maps,
+        numeric: args.numeric_owner,
+        owner: args
+            .owner
+            .as_deref()
+            .map(|spec| users.account(spec, "user"))
+            .transpose()?,
+        group: args
+            .group
+            .as_deref()
+            .map(|spec| groups.account(spec, "group"))
+            .transpose()?,
+        owner_map,
+        group_map,
+        users,
+        groups,
+    })
+}
+
+/// Resolve zip member settings from CLI args and config.
+fn zip_options(args: &Args, config: &Config, encryption: Option<&Encryption>) -> ZipOptions {
+    ZipOptions {
+        method: zip_method(args, config),
+        symlinks: args.zip_symlinks,
+        store_extensions: config
+            .zip
+            .store_extensions
+            .iter()
+            .map(|ext| ext.trim_start_matches('.').to_lowercase())
+            .collect(),
+        store_if_larger: config.zip.store_if_larger,
+        normalize_modes: normalize_modes(args, config),
+        embed_checksums: args.checksums.filter(|_| args.embed_checksums),
+        deleted: None,
+        password: encryption
+            .and_then(Encryption::passphrase)
+            .map(str::to_string),
+    }
+}
+
+/// Parses the `--encrypt-to` recipients, or reads the `--passphrase`
+/// (except in preview, which writes nothing).
+fn encryption(args: &Args) -> Result<Option<Encryption>> {
+    if !args.encrypt_to.is_empty() {
+        return Encryption::to_recipients(&args.encrypt_to).map(Some);
+    }
+    if args.passphrase && !args.preview {
+        return Ok(Some(Encryption::Passphrase(encrypt::read_passphrase(
+            true,
+        )?)));
+    }
+    Ok(None)
+}
+
+/// Resolve compression settings from CLI args and config.
+fn compression_options(args: &Args, config: &Config) -> Result<CompressionOptions> {
+    let level = args
+        .level
+        .or_else(|| config.compression.level_for(args.format));
+
+    if let Some(level) = level {
+        let (name, range) = if args.format == Format::Zip {
+            let method = zip_method(args, config);
+            let name = method
+                .to_possible_value()
+                .map_or_else(String::new, |v| format!("zip ({})", v.get_name()));
+            (name, method.level_range())
+        } else {
+            (
+                args.format.extension().to_string(),
+                args.format.level_range(),
+            )
+        };
+
+        match range {
+            None => anyhow::bail!("Format {name} is uncompressed and does not take a level"),
+            Some((min, max)) if !(min..=max).contains(&level) => anyhow::bail!(
+                "Invalid compression level {level} for {name} (expected {min} to {max})"
+            ),
+            Some(_) => {}
+        }
+    }
+
+    if let Some(window_log) = config.compression.zstd_window_log {
+        if !(10..=31).contains(&window_log) {
+            anyhow::bail!("Invalid zstd_window_log {window_log} (expected 10 to 31)");
+        }
+    }
+
+    let threads = match args.threads {
+        Some(_) if !args.format.supports_threads() => anyhow::bail!(
+            "Format {} does not support --threads (use tar.gz, tar.bz2 or tar.zst)",
+            args.format.extension()
+        ),
+        Some(0) => Some(
+            std::thread::available_parallelism()
+                .map_or(1, |n| u32::try_from(n.get()).unwrap_or(u32::MAX)),
+        ),
+        threads => threads,
+    };
+
+    Ok(CompressionOptions {
+        level,
+        zstd_long: config.compression.zstd_long,
+        zstd_window_log: config.compression.zstd_window_log,
+        threads,
+    })
+}
+
+/// What [`create_archive`] learned about the archive while writing it.
+struct Written {
+    /// Part list of a split archive
+    manifest: Option<split::Manifest>,
+    /// Archive digest for `--checksums`
+    digest: Option<String>,
+    /// BLAKE2b-512 of the archive for `--sign`
+    prehash: Option<Vec<u8>>,
+}
+
+/// Buffered archive output, hashed as it is written.
+type Output<W> = BufWriter<HashingWriter<PrehashWriter<W>>>;
+
+/// Wraps the archive destination with the hashers the options ask for.
+fn output_writer<W: Write>(inner: W, args: &Args) -> Output<W> {
+    let prehash = PrehashWriter::new(inner, args.sign.is_some());
+    BufWriter::new(HashingWriter::new(prehash, args.checksums))
+}
+
+/// Flushes the output and returns the destination with the archive digest
+/// and prehash.
+fn finish_output<W: Write>(writer: Output<W>) -> Result<(W, Option<String>, Option<Vec<u8>>)> {
+    let (writer, digest) = writer
+        .into_inner()
+        .map_err(io::IntoInnerError::into_error)?
+        .finish();
+    let (mut writer, prehash) = writer.finish();
+    writer.flush()?;
+    Ok((writer, digest, prehash))
+}
+
+/// Writes a tar archive in the compressed format `args` asks for.
+fn write_tar<W: Write>(
+    writer: W,
+    entries: &[FileEntry],
+    args: &Args,
+    tar: &TarOptions,
+    compression: &CompressionOptions,
+) -> Result<W> {
+    let (quiet, verbose) = (args.quiet, args.verbose);
+    match args.format {
+        Format::Tar => archive::create_tar(writer, entries, tar, quiet, verbose),
+        Format::TarGz => archive::create_tar_gz(writer, entries, tar, compression, quiet, verbose),
         Format::TarBz2 => {
-            archive::create_tar_bz2(
-                writer,
-                entries,
-                args.reproducible,
-                args.preserve_owner,
-                args.quiet,
-                args.verbose,
-            )?;
+            archive::create_tar_bz2(writer, entries, tar, compression, quiet, verbose)
         }
         Format::TarZst => {
-            archive::create_tar_zst(
-                writer,
-                entries,
-                args.reproducible,
-                args.preserve_owner,
-                args.quiet,
-                args.verbose,
-            )?;
+            archive::create_tar_zst(writer, entries, tar, compression, quiet, verbose)
+        }
+        Format::TarXz => archive::create_tar_xz(writer, entries, tar, compression, quiet, verbose),
+        Format::TarLz4 => {
+            archive::create_tar_lz4(writer, entries, tar, compression, quiet, verbose)
         }
-        Format::Zip => {
-            archive::create_zip(writer, entries, args.reproducible, args.quiet, args.verbose)?;
+        Format::TarBr => archive::create_tar_br(writer, entries, tar, compression, quiet, verbose),
+        Format::Zip => unreachable!("zip is written by create_zip"),
+    }
+}
+
+/// Create the archive in the specified format.
+///
+/// An output of `-` streams the archive to stdout. Zip needs to seek back
+/// to fill in sizes, so it is built in an anonymous temporary file and then
+/// copied out; with `--checksums` or `--sign` it is streamed instead. With
+/// `--split-size`, tar formats are written as numbered parts and the
+/// manifest describing them is returned. Encrypted tar archives are wrapped
+/// in age after compression.
+fn create_archive(
+    output: &Path,
+    entries: &[FileEntry],
+    args: &Args,
+    tar: &TarOptions,
+    compression: &CompressionOptions,
+    zip: &ZipOptions,
+    encryption: Option<&Encryption>,
+) -> Result<Written> {
+    let to_stdout = output == Path::new("-");
+
+    if args.format == Format::Zip && (args.checksums.is_some() || args.sign.is_some()) {
+        // Streamed with data descriptors: digests are taken as the bytes
+        // go out, which rules out seeking back to patch local headers
+        let writer: Box<dyn Write> = if to_stdout {
+            Box::new(io::stdout().lock())
+        } else {
+            Box::new(File::create(output)?)
+        };
+        let writer = zip::ZipWriter::new_stream(output_writer(writer, args));
+        let writer =
+            archive::create_zip(writer, entries, compression, zip, args.quiet, args.verbose)?;
+        let (_, digest, prehash) = finish_output(writer.into_inner())?;
+        return Ok(Written {
+            manifest: None,
+            digest,
+            prehash,
+        });
+    }
+
+    if args.format == Format::Zip {
+        let file = if to_stdout {
+            tempfile::tempfile().context("Failed to create a temporary file for zip output")?
+        } else {
+            File::create(output)?
+        };
+        let writer = zip::ZipWriter::new(BufWriter::new(file));
+        let mut file =
+            archive::create_zip(writer, entries, compression, zip, args.quiet, args.verbose)?
+                .into_inner()
+                .map_err(io::IntoInnerError::into_error)?;
+
+        if to_stdout {
+            file.seek(io::SeekFrom::Start(0))?;
+            let mut stdout = io::stdout().lock();
+            io::copy(&mut file, &mut stdout)?;
+            stdout.flush()?;
         }
+        return Ok(Written {
+            manifest: None,
+            digest: None,
+            prehash: None,
+        });
     }
+
+    let mut split = args
+        .split_size
+        .map(|size| split::SplitWriter::new(output, size.as_u64()));
+    let writer: Box<dyn Write + '_> = if to_stdout {
+        Box::new(io::stdout().lock())
+    } else if let Some(ref mut split) = split {
+        Box::new(split)
+    } else {
+        Box::new(File::create(output)?)
+    };
+    let writer = output_writer(writer, args);
+
+    let writer = match encryption {
+        Some(encryption) => {
+            write_tar(encryption.wrap(writer)?, entries, args, tar, compression)?.finish()?
+        }
+        None => write_tar(writer, entries, args, tar, compression)?,
+    };
+    let (writer, digest, prehash) = finish_output(writer)?;
+    drop(writer);
+
+    Ok(Written {
+        manifest: split.map(split::SplitWriter::finish).transpose()?,
+        digest,
+        prehash,
+    })
+}
+
+/// Warns about a part beyond the last one written, left by an earlier run
+/// with a smaller split size; joining it in would corrupt the archive.
+fn warn_stale_part(output: &Path, manifest: &split::Manifest, quiet: bool) {
+    let stale = split::part_path(output, manifest.parts.len());
+    if stale.exists() && !quiet {
+        eprintln!(
+            "{} {} is left over from an earlier run; remove it before joining the parts",
+            "⚠".yellow(),
+            stale.display()
+        );
+    }
+}
+
+/// Writes the checksum and signature files that go next to the archive.
+fn write_sidecars(
+    out: &mut dyn Write,
+    output: &Path,
+    args: &Args,
+    written: &Written,
+    signing_key: Option<&sign::SecretKey>,
+) -> Result<()> {
+    if let (Some(algorithm), Some(digest)) = (args.checksums, &written.digest) {
+        write_checksum_file(out, output, algorithm, digest, args.quiet)?;
+    }
+    if let (Some(key), Some(prehash)) = (signing_key, &written.prehash) {
+        let path = sign::write_signature(key, output, prehash)?;
+        if !args.quiet {
+            writeln!(out, "🦖 Signature: {}", path.display())?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes `<output>.sha256` (or `.b3`) with the archive's digest, or prints
+/// the digest when the archive went to stdout.
+fn write_checksum_file(
+    out: &mut dyn Write,
+    output: &Path,
+    algorithm: ChecksumAlgorithm,
+    digest: &str,
+    quiet: bool,
+) -> Result<()> {
+    if output == Path::new("-") {
+        writeln!(out, "{digest}  -")?;
+        return Ok(());
+    }
+
+    let name = output
+        .file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().to_string());
+    let path = checksum::sidecar_path(output, algorithm);
+    fs::write(&path, checksum::sums_line(digest, &name))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    if !quiet {
+        writeln!(out, "🦖 Checksum: {}", path.display())?;
+    }
+    Ok(())
+}
+
+/// Says what is about to be written.
+fn announce(out: &mut dyn Write, output: &Path, args: &Args, entries: &[FileEntry]) -> Result<()> {
+    if args.quiet {
+        return Ok(());
+    }
+    let target = if args.streams_to_stdout() {
+        "stdout".to_string()
+    } else {
+        output.display().to_string()
+    };
+    writeln!(
+        out,
+        "🦖 Creating {} with {} files...",
+        target.cyan(),
+        entries.len() - count_dirs(entries)
+    )?;
+    out.flush()?;
     Ok(())
 }
 
 /// Print the final summary after creating an archive.
-fn print_summary(output: &Path, entries: &[FileEntry]) -> Result<()> {
-    let output_size = std::fs::metadata(output)?.len();
+fn print_summary(
+    out: &mut dyn Write,
+    output: &Path,
+    manifest: Option<&split::Manifest>,
+    entries: &[FileEntry],
+) -> Result<()> {
     let input_size: u64 = entries.iter().map(|e| e.size).sum();
+    if output == Path::new("-") {
+        writeln!(out, "🦖 Done! {} streamed to stdout", ByteSize(input_size))?;
+        return Ok(());
+    }
+
+    let output_size = match manifest {
+        Some(manifest) => manifest.size,
+        None => std::fs::metadata(output)?.len(),
+    };
     let ratio = if input_size > 0 {
         (output_size as f64 / input_size as f64) * 100.0
     } else {
         100.0
     };
 
-    println!(
+    write!(
+        out,
         "🦖 Done! {} → {} ({:.1}% of original)",
         ByteSize(input_size),
         ByteSize(output_size),
         ratio
-    );
+    )?;
+    if let Some(manifest) = manifest {
+        write!(
+            out,
+            " in {} parts, manifest {}",
+            manifest.parts.len(),
+            split::manifest_path(output).display()
+        )?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Extract an archive and report what happened.
+fn run_extract(extract_args: &ExtractArgs, args: &Args) -> Result<()> {
+    if !args.quiet {
+        println!(
+            "🦖 Extracting {} into {}...",
+            extract_args.archive.display().to_string().cyan(),
+            extract_args.directory.display()
+        );
+    }
+
+    let stats = extract::extract_archive(extract_args, args.verbose)?;
+
+    if stats.refused > 0 {
+        anyhow::bail!(
+            "Refused {} unsafe member(s); {} extracted",
+            stats.refused,
+            stats.extracted
+        );
+    }
+
+    if !args.quiet {
+        if stats.skipped > 0 {
+            println!(
+                "🦖 Done! Extracted {} entries ({} skipped)",
+                stats.extracted, stats.skipped
+            );
+        } else {
+            println!("🦖 Done! Extracted {} entries", stats.extracted);
+        }
+    }
     Ok(())
 }
 
+/// Run the restore subcommand: each archive in turn, stopping at the first
+/// that refuses members.
+fn run_restore(restore_args: &RestoreArgs, args: &Args) -> Result<()> {
+    let keys = encrypt::Keys::new(restore_args.identity.clone());
+    let mut total = extract::ExtractStats::default();
+
+    for archive in &restore_args.archives {
+        if !args.quiet {
+            println!(
+                "🦖 Restoring {} into {}...",
+                archive.display().to_string().cyan(),
+                restore_args.directory.display()
+            );
+        }
+        let extract_args = ExtractArgs {
+            archive: archive.clone(),
+            directory: restore_args.directory.clone(),
+            strip_components: restore_args.strip_components,
+            xattrs: restore_args.xattrs,
+            identity: restore_args.identity.clone(),
+            format: restore_args.format,
+        };
+        let stats = extract::restore_archive(&extract_args, &keys, args.verbose)?;
+        if stats.refused > 0 {
+            anyhow::bail!(
+                "Refused {} unsafe member(s) in {}; restore stopped",
+                stats.refused,
+                archive.display()
+            );
+        }
+        total.extracted += stats.extracted;
+        total.deleted += stats.deleted;
+    }
+
+    if !args.quiet {
+        println!(
+            "🦖 Done! Restored {} archive(s): {} entries extracted, {} deleted",
+            restore_args.archives.len(),
+            total.extracted,
+            total.deleted
+        );
+    }
+    Ok(())
+}
+
+/// Run the verify-signature subcommand.
+fn run_verify_signature(verify_args: &VerifySignatureArgs, args: &Args) -> Result<()> {
+    let trusted = sign::verify_file(
+        &verify_args.archive,
+        verify_args.signature.as_deref(),
+        &verify_args.public_key,
+    )?;
+    if !args.quiet {
+        println!("🦖 Signature and trusted comment verified");
+        println!("Trusted comment: {trusted}");
+    }
+    Ok(())
+}
+
+/// Compare an archive with its source tree, failing on any difference.
+fn run_verify(verify_args: &VerifyArgs, mut args: Args, config: &Config) -> Result<()> {
+    args.path.clone_from(&verify_args.path);
+    apply_config_defaults(&mut args, config);
+    if !args.path.exists() {
+        anyhow::bail!("Path does not exist: {}", args.path.display());
+    }
+    if !args.quiet {
+        println!(
+            "🦖 Verifying {} against {}...",
+            verify_args.archive.display().to_string().cyan(),
+            args.path.display()
+        );
+    }
+
+    let (mut entries, _) = walk::collect_files(&args, config)?;
+    exclude_output_file(&mut entries, &verify_args.archive, &args);
+    let options = verify::VerifyOptions {
+        normalize_modes: normalize_modes(&args, config),
+        owners: ownership(&args)?,
+        tar_format: args.tar_format,
+        zip_symlinks: args.zip_symlinks,
+    };
+    let keys = encrypt::Keys::new(verify_args.identity.clone());
+    let report = verify::verify_archive(
+        &verify_args.archive,
+        verify_args.format,
+        &keys,
+        &entries,
+        &options,
+    )?;
+
+    let mut out = io::stdout().lock();
+    for (path, difference) in &report.differences {
+        let label = format!("{:<8}", difference.label());
+        match difference {
+            verify::Difference::Metadata(details) => writeln!(
+                out,
+                "  {} {}: {}",
+                label.red(),
+                path.display(),
+                details.join(", ")
+            )?,
+            _ => writeln!(out, "  {} {}", label.red(), path.display())?,
+        }
+    }
+    if !report.differences.is_empty() {
+        anyhow::bail!(
+            "{} difference(s) between {} and {}",
+            report.differences.len(),
+            verify_args.archive.display(),
+            args.path.display()
+        );
+    }
+    if !args.quiet {
+        writeln!(
+            out,
+            "🦖 Archive matches the tree ({} members verified)",
+            report.checked
+        )?;
+    }
+    Ok(())
+}
+
+/// List an archive's members, or print one member with `--cat`.
+fn run_list(list_args: &ListArgs) -> Result<()> {
+    let keys = encrypt::Keys::new(list_args.identity.clone());
+
+    if let Some(ref member) = list_args.cat {
+        let stdout = std::io::stdout();
+        return list::cat_member(
+            &list_args.archive,
+            list_args.format,
+            &keys,
+            member,
+            stdout.lock(),
+        );
+    }
+
+    let entries = list::read_entries(&list_args.archive, list_args.format, &keys)?;
+
+    let mut out = io::stdout().lock();
+    writeln!(
+        out,
+        "{} {}",
+        "Archive contents:".bold().green(),
+        list_args.archive.display()
+    )?;
+    writeln!(out)?;
+
+    print_entries(&mut out, &entries, true, list_args.long)?;
+
+    writeln!(out)?;
+    print_entries_summary(&mut out, &entries)
+}
+
 fn main() -> Result<()> {
     let mut args = Args::parse();
     let config = config::load_config();
@@ -396,6 +1636,17 @@ fn main() -> Result<()> {
         return Ok(());
     }
 
+    // Handle subcommands operating on existing archives
+    if let Some(command) = args.command.take() {
+        return match command {
+            Command::Extract(extract_args) => run_extract(&extract_args, &args),
+            Command::List(list_args) => run_list(&list_args),
+            Command::VerifySignature(verify_args) => run_verify_signature(&verify_args, &args),
+            Command::Verify(verify_args) => run_verify(&verify_args, args, &config),
+            Command::Restore(restore_args) => run_restore(&restore_args, &args),
+        };
+    }
+
     // Handle --list-ecosystems
     if args.list_ecosystems {
         ecosystem::print_ecosystem_list();
@@ -417,8 +1668,13 @@ fn main() -> Result<()> {
         anyhow::bail!("Path does not exist: {}", args.path.display());
     }
 
+    check_option_combinations(&args)?;
+
+    // Human output moves to stderr when the archive goes to stdout
+    let mut out = args.human_output();
+
     if !args.quiet {
-        println!("🦖 Scanning files...");
+        writeln!(out, "🦖 Scanning files...")?;
     }
 
     // Determine output path early so we can exclude it from the archive
@@ -428,21 +1684,45 @@ fn main() -> Result<()> {
         Some(get_output_path(&args)?)
     };
 
+    let compression = compression_options(&args, &config)?;
+    // Unlock the key first, so a wrong password fails before any work
+    let signing_key = match args.sign {
+        Some(ref path) if !args.preview => Some(sign::SecretKey::load(path)?),
+        _ => None,
+    };
+    let encryption = encryption(&args)?;
+    let mut zip = zip_options(&args, &config, encryption.as_ref());
+    let mut tar = TarOptions {
+        format: args.tar_format,
+        reproducible: args.reproducible,
+        owners: ownership(&args)?,
+        normalize_modes: normalize_modes(&args, &config),
+        embed_checksums: args.checksums.filter(|_| args.embed_checksums),
+        deleted: None,
+    };
+
     let (mut entries, excluded) = walk::collect_files(&args, &config)?;
 
     // Exclude the output file itself to prevent infinite growth
     if let Some(ref out) = output {
-        exclude_output_file(&mut entries, out, args.quiet);
+        exclude_output_file(&mut entries, out, &args);
     }
+    check_sums_member(&entries, &args)?;
+    let snapshot = select_increment(&mut entries, &args, &mut tar, &mut zip)?;
 
-    if entries.is_empty() {
-        println!("{}", "No files to archive!".yellow());
+    // An increment is written even when nothing changed, to keep the chain
+    if entries.is_empty() && tar.deleted.is_none() {
+        writeln!(out, "{}", "No files to archive!".yellow())?;
         return Ok(());
     }
 
+    if args.format != Format::Zip {
+        check_tar_format(&entries, &tar, args.preview)?;
+    }
+
     // Preview or size estimation mode
     if args.preview || args.size {
-        preview_files(&entries, &excluded, &args);
+        preview_files(&mut out, &entries, &excluded, &args)?;
         if args.preview {
             return Ok(());
         }
@@ -451,20 +1731,30 @@ fn main() -> Result<()> {
     // Create archive
     let output = output.expect("output path should be set for non-preview mode");
 
-    if !args.quiet {
-        println!(
-            "🦖 Creating {} with {} files...",
-            output.display().to_string().cyan(),
-            entries.len()
-        );
-    }
+    announce(&mut out, &output, &args, &entries)?;
 
-    create_archive(&output, &entries, &args)?;
+    let written = create_archive(
+        &output,
+        &entries,
+        &args,
+        &tar,
+        &compression,
+        &zip,
+        encryption.as_ref(),
+    )?;
+
+    if let Some(ref manifest) = written.manifest {
+        warn_stale_part(&output, manifest, args.quiet);
+    }
 
     if !args.quiet {
-        print_summary(&output, &entries)?;
+        print_summary(&mut out, &output, written.manifest.as_ref(), &entries)?;
     }
+    write_sidecars(&mut out, &output, &args, &written, signing_key.as_ref())?;
 
+    if let (Some(snapshot), Some(path)) = (snapshot, args.listed_incremental.as_deref()) {
+        snapshot.save(path)?;
+    }
     Ok(())
 }
 
@@ -476,11 +1766,15 @@ mod tests {
 
     fn default_args(path: PathBuf) -> Args {
         Args {
+            command: None,
             path,
             output: None,
             format: Format::TarGz,
             preview: false,
             size: false,
+            level: None,
+            threads: None,
+            split_size: None,
             with_exclude: vec![],
             with_include: vec![],
             with_ignorefile: vec![],
@@ -491,8 +1785,32 @@ mod tests {
             with_ecosystem: vec![],
             list_ecosystems: false,
             reproducible: false,
+            normalize_modes: false,
+            no_normalize_modes: false,
+            mtime: None,
+            clamp_mtime: false,
             dereference: false,
             preserve_owner: false,
+            owner: None,
+            group: None,
+            numeric_owner: false,
+            owner_map: None,
+            group_map: None,
+            checksums: None,
+            embed_checksums: false,
+            sign: None,
+            no_sign: false,
+            encrypt_to: Vec::new(),
+            passphrase: false,
+            xattrs: false,
+            zip_method: None,
+            zip_symlinks: ZipSymlinks::Link,
+            tar_format: TarFormat::Gnu,
+            sparse: false,
+            no_empty_dirs: false,
+            transform: vec![],
+            prefix: None,
+            listed_incremental: None,
             quiet: true,
             verbose: false,
             show_config: false,
@@ -525,11 +1843,161 @@ mod tests {
         assert_eq!(Format::TarZst.extension(), "tar.zst");
     }
 
+    #[test]
+    fn test_format_extension_tar_xz() {
+        assert_eq!(Format::TarXz.extension(), "tar.xz");
+    }
+
+    #[test]
+    fn test_format_extension_tar_lz4() {
+        assert_eq!(Format::TarLz4.extension(), "tar.lz4");
+    }
+
+    #[test]
+    fn test_format_extension_tar_br() {
+        assert_eq!(Format::TarBr.extension(), "tar.br");
+    }
+
     #[test]
     fn test_format_extension_zip() {
         assert_eq!(Format::Zip.extension(), "zip");
     }
 
+    #[test]
+    fn test_format_mode() {
+        assert_eq!(format_mode(EntryType::File, 0o644), "-rw-r--r--");
+        assert_eq!(format_mode(EntryType::Directory, 0o755), "drwxr-xr-x");
+        assert_eq!(format_mode(EntryType::Symlink, 0o777), "lrwxrwxrwx");
+        assert_eq!(format_mode(EntryType::Fifo, 0o644), "prw-r--r--");
+        assert_eq!(format_mode(EntryType::CharDevice, 0o666), "crw-rw-rw-");
+    }
+
+    #[test]
+    fn test_format_mtime() {
+        assert_eq!(format_mtime(0), "1970-01-01 00:00");
+        assert_eq!(format_mtime(1_000_000_000), "2001-09-09 01:46");
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(Format::from_path(Path::new("a.tar")), Some(Format::Tar));
+        assert_eq!(
+            Format::from_path(Path::new("a.tar.gz")),
+            Some(Format::TarGz)
+        );
+        assert_eq!(Format::from_path(Path::new("a.tgz")), Some(Format::TarGz));
+        assert_eq!(Format::from_path(Path::new("a.tzst")), Some(Format::TarZst));
+        assert_eq!(Format::from_path(Path::new("a.txz")), Some(Format::TarXz));
+        assert_eq!(
+            Format::from_path(Path::new("a.tar.br")),
+            Some(Format::TarBr)
+        );
+        assert_eq!(Format::from_path(Path::new("a.zip")), Some(Format::Zip));
+        assert_eq!(Format::from_path(Path::new("a.txt")), None);
+    }
+
+    #[test]
+    fn test_compression_level_from_config() {
+        let args = default_args(PathBuf::from("."));
+        let mut config = default_config();
+        config.compression.gzip = Some(9);
+        config.compression.zstd = Some(19);
+
+        let options = compression_options(&args, &config).unwrap();
+        assert_eq!(options.level, Some(9));
+    }
+
+    #[test]
+    fn test_compression_level_cli_overrides_config() {
+        let mut args = default_args(PathBuf::from("."));
+        args.level = Some(1);
+        let mut config = default_config();
+        config.compression.gzip = Some(9);
+
+        let options = compression_options(&args, &config).unwrap();
+        assert_eq!(options.level, Some(1));
+    }
+
+    #[test]
+    fn test_compression_level_out_of_range() {
+        let mut args = default_args(PathBuf::from("."));
+        args.format = Format::TarBz2;
+        args.level = Some(0);
+
+        assert!(compression_options(&args, &default_config()).is_err());
+    }
+
+    #[test]
+    fn test_compression_level_rejected_for_tar() {
+        let mut args = default_args(PathBuf::from("."));
+        args.format = Format::Tar;
+        args.level = Some(3);
+
+        assert!(compression_options(&args, &default_config()).is_err());
+    }
+
+    #[test]
+    fn test_compression_zstd_tuning_from_config() {
+        let mut args = default_args(PathBuf::from("."));
+        args.format = Format::TarZst;
+        let mut config = default_config();
+        config.compression.zstd_long = true;
+        config.compression.zstd_window_log = Some(27);
+
+        let options = compression_options(&args, &config).unwrap();
+        assert_eq!(options.level, None);
+        assert!(options.zstd_long);
+        assert_eq!(options.zstd_window_log, Some(27));
+    }
+
+    #[test]
+    fn test_compression_level_uses_zip_method_range() {
+        let mut args = default_args(PathBuf::from("."));
+        args.format = Format::Zip;
+        args.level = Some(19);
+        assert!(compression_options(&args, &default_config()).is_err());
+
+        args.zip_method = Some(ZipMethod::Zstd);
+        assert!(compression_options(&args, &default_config()).is_ok());
+
+        args.zip_method = Some(ZipMethod::Store);
+        args.level = Some(1);
+        assert!(compression_options(&args, &default_config()).is_err());
+    }
+
+    #[test]
+    fn test_zip_options_from_config() {
+        let args = default_args(PathBuf::from("."));
+        let mut config = default_config();
+        config.zip.method = Some(ZipMethod::Bzip2);
+        config.zip.store_extensions = vec![".PNG".to_string(), "jar".to_string()];
+
+        let options = zip_options(&args, &config, None);
+        assert_eq!(options.method, ZipMethod::Bzip2);
+        assert_eq!(options.store_extensions, vec!["png", "jar"]);
+    }
+
+    #[test]
+    fn test_normalize_modes_follows_reproducible() {
+        let mut args = default_args(PathBuf::from("."));
+        let mut config = default_config();
+        assert!(!normalize_modes(&args, &config));
+
+        args.reproducible = true;
+        assert!(normalize_modes(&args, &config));
+
+        config.defaults.normalize_modes = Some(false);
+        assert!(!normalize_modes(&args, &config));
+
+        args.normalize_modes = true;
+        assert!(normalize_modes(&args, &config));
+
+        args.normalize_modes = false;
+        args.no_normalize_modes = true;
+        config.defaults.normalize_modes = Some(true);
+        assert!(!normalize_modes(&args, &config));
+    }
+
     #[test]
     fn test_collect_files_basic() {
         let tmp = TempDir::new().unwrap();
@@ -542,6 +2010,51 @@ mod tests {
         assert_eq!(entries[0].relative_path.to_string_lossy(), "file.txt");
     }
 
+    #[test]
+    fn test_collect_files_records_directories() {
+        let tmp = TempDir::new().unwrap();
+        fs::create_dir_all(tmp.path().join("logs")).unwrap();
+        fs::create_dir_all(tmp.path().join("src")).unwrap();
+        fs::write(tmp.path().join("src/main.rs"), "fn main() {}").unwrap();
+
+        let mut args = default_args(tmp.path().to_path_buf());
+        args.reproducible = true;
+        let (entries, _) = walk::collect_files(&args, &default_config()).unwrap();
+
+        let summary: Vec<_> = entries
+            .iter()
+            .map(|e| (e.relative_path.to_string_lossy().to_string(), e.entry_type))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("logs".to_string(), EntryType::Directory),
+                ("src".to_string(), EntryType::Directory),
+                ("src/main.rs".to_string(), EntryType::File),
+            ]
+        );
+        assert_eq!(entries[0].size, 0);
+    }
+
+    #[test]
+    fn test_collect_files_no_empty_dirs() {
+        let tmp = TempDir::new().unwrap();
+        fs::create_dir_all(tmp.path().join("logs/archive")).unwrap();
+        fs::create_dir_all(tmp.path().join("src")).unwrap();
+        fs::write(tmp.path().join("src/main.rs"), "fn main() {}").unwrap();
+
+        let mut args = default_args(tmp.path().to_path_buf());
+        args.no_empty_dirs = true;
+        let (entries, _) = walk::collect_files(&args, &default_config()).unwrap();
+
+        let paths: Vec<_> = entries
+            .iter()
+            .map(|e| e.relative_path.to_string_lossy().to_string())
+            .collect();
+        assert!(paths.contains(&"src".to_string()));
+        assert!(!paths.iter().any(|p| p.starts_with("logs")));
+    }
+
     #[test]
     fn test_collect_files_respects_gitignore() {
         let tmp = TempDir::new().unwrap();
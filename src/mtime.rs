@@ -0,0 +1,212 @@
+//! Timestamp overrides: `--mtime`, `--clamp-mtime` and `SOURCE_DATE_EPOCH`.
+//!
+//! Following the Reproducible Builds convention, `--reproducible` clamps
+//! every mtime to `SOURCE_DATE_EPOCH` when it is set: newer files get that
+//! time, older ones keep theirs. Without it, reproducible archives use the
+//! epoch. `--mtime` picks the time explicitly and sets every entry to it, or
+//! clamps with `--clamp-mtime`.
+
+use crate::Args;
+
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::process::Command;
+use time::{Date, Month, PrimitiveDateTime, Time};
+
+/// How entry modification times are rewritten.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MtimePolicy {
+    /// Every entry gets this time
+    Set(u64),
+    /// Entries newer than this time get it; older ones keep theirs
+    Clamp(u64),
+}
+
+impl MtimePolicy {
+    /// Applies the policy to a `(seconds, nanoseconds)` modification time.
+    pub const fn apply(self, mtime: u64, nsec: u32) -> (u64, u32) {
+        match self {
+            Self::Set(time) => (time, 0),
+            Self::Clamp(time) if mtime >= time => (time, 0),
+            Self::Clamp(_) => (mtime, nsec),
+        }
+    }
+
+    /// The time entries are set or clamped to.
+    pub const fn time(self) -> u64 {
+        match self {
+            Self::Set(time) | Self::Clamp(time) => time,
+        }
+    }
+}
+
+/// Parses `YYYY-MM-DD` with an optional `THH:MM[:SS]` (or space-separated)
+/// time of day, in UTC. A trailing `Z` is accepted.
+fn parse_datetime(value: &str) -> Option<u64> {
+    let value = value.strip_suffix('Z').unwrap_or(value);
+    let (date, time) = value
+        .split_once(['T', ' '])
+        .map_or((value, None), |(d, t)| (d, Some(t)));
+
+    let mut parts = date.splitn(3, '-');
+    let year = parts.next()?.parse().ok()?;
+    let month = Month::try_from(parts.next()?.parse::<u8>().ok()?).ok()?;
+    let day = parts.next()?.parse().ok()?;
+    let date = Date::from_calendar_date(year, month, day).ok()?;
+
+    let time = match time {
+        None => Time::MIDNIGHT,
+        Some(time) => {
+            let mut parts = time.splitn(3, ':');
+            let hour = parts.next()?.parse().ok()?;
+            let minute = parts.next()?.parse().ok()?;
+            let second = parts.next().map_or(Some(0), |s| s.parse().ok())?;
+            Time::from_hms(hour, minute, second).ok()?
+        }
+    };
+
+    let timestamp = PrimitiveDateTime::new(date, time)
+        .assume_utc()
+        .unix_timestamp();
+    u64::try_from(timestamp).ok()
+}
+
+/// Returns the committer time of the last commit in the repository at `root`.
+fn git_commit_time(root: &Path) -> Result<u64> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(root)
+        .args(["log", "-1", "--format=%ct"])
+        .output()
+        .context("Failed to run git for --mtime git")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "--mtime git needs a git repository with at least one commit: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .context("Unexpected output from git log")
+}
+
+/// Parses an `--mtime` value: `@EPOCH`, a UTC date such as `2024-05-01` or
+/// `2024-05-01T12:00:00Z`, or `git` for the last commit time of `root`.
+pub fn parse_mtime(value: &str, root: &Path) -> Result<u64> {
+    if value == "git" {
+        return git_commit_time(root);
+    }
+    if let Some(epoch) = value.strip_prefix('@') {
+        return epoch
+            .parse()
+            .with_context(|| format!("Invalid epoch in --mtime: {value}"));
+    }
+    parse_datetime(value).with_context(|| {
+        format!("Invalid --mtime {value:?} (expected @EPOCH, YYYY-MM-DD[THH:MM:SS] or git)")
+    })
+}
+
+/// Works out the policy from the options and `SOURCE_DATE_EPOCH`.
+fn resolve_with(
+    mtime: Option<&str>,
+    clamp: bool,
+    reproducible: bool,
+    source_date_epoch: Option<&str>,
+    root: &Path,
+) -> Result<Option<MtimePolicy>> {
+    if let Some(value) = mtime {
+        let time = parse_mtime(value, root)?;
+        return Ok(Some(if clamp {
+            MtimePolicy::Clamp(time)
+        } else {
+            MtimePolicy::Set(time)
+        }));
+    }
+    if !reproducible {
+        return Ok(None);
+    }
+    match source_date_epoch.filter(|v| !v.is_empty()) {
+        Some(value) => {
+            let time = value
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid SOURCE_DATE_EPOCH: {value}"))?;
+            Ok(Some(MtimePolicy::Clamp(time)))
+        }
+        None => Ok(Some(MtimePolicy::Set(0))),
+    }
+}
+
+/// Works out the mtime policy for a run; `None` keeps the files' own times.
+pub fn resolve(args: &Args, root: &Path) -> Result<Option<MtimePolicy>> {
+    let source_date_epoch = std::env::var("SOURCE_DATE_EPOCH").ok();
+    resolve_with(
+        args.mtime.as_deref(),
+        args.clamp_mtime,
+        args.reproducible,
+        source_date_epoch.as_deref(),
+        root,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_epoch_and_dates() {
+        let root = Path::new(".");
+        assert_eq!(parse_mtime("@1700000000", root).unwrap(), 1_700_000_000);
+        assert_eq!(parse_mtime("1970-01-02", root).unwrap(), 86_400);
+        assert_eq!(
+            parse_mtime("2023-11-14T22:13:20Z", root).unwrap(),
+            1_700_000_000
+        );
+        assert_eq!(
+            parse_mtime("2023-11-14 22:13:20", root).unwrap(),
+            1_700_000_000
+        );
+        assert!(parse_mtime("yesterday", root).is_err());
+        assert!(parse_mtime("2023-13-01", root).is_err());
+        assert!(parse_mtime("@-5", root).is_err());
+    }
+
+    #[test]
+    fn test_apply_set_and_clamp() {
+        assert_eq!(MtimePolicy::Set(100).apply(50, 7), (100, 0));
+        assert_eq!(MtimePolicy::Clamp(100).apply(50, 7), (50, 7));
+        assert_eq!(MtimePolicy::Clamp(100).apply(150, 7), (100, 0));
+    }
+
+    #[test]
+    fn test_resolve_source_date_epoch() {
+        let root = Path::new(".");
+        assert_eq!(
+            resolve_with(None, false, false, Some("5"), root).unwrap(),
+            None
+        );
+        assert_eq!(
+            resolve_with(None, false, true, None, root).unwrap(),
+            Some(MtimePolicy::Set(0))
+        );
+        assert_eq!(
+            resolve_with(None, false, true, Some("1700000000"), root).unwrap(),
+            Some(MtimePolicy::Clamp(1_700_000_000))
+        );
+        assert!(resolve_with(None, false, true, Some("soon"), root).is_err());
+    }
+
+    #[test]
+    fn test_resolve_explicit_mtime_wins() {
+        let root = Path::new(".");
+        assert_eq!(
+            resolve_with(Some("@10"), false, true, Some("99"), root).unwrap(),
+            Some(MtimePolicy::Set(10))
+        );
+        assert_eq!(
+            resolve_with(Some("@10"), true, false, None, root).unwrap(),
+            Some(MtimePolicy::Clamp(10))
+        );
+    }
+}
@@ -0,0 +1,296 @@
+//! Owner and group rewriting for tar headers.
+//!
+//! By default raptar stores every member as uid/gid 0 without names. With
+//! `--preserve-owner` the ids found on disk are kept, and the user and group
+//! names are filled in from the local `/etc/passwd` and `/etc/group` files
+//! (unless `--numeric-owner`). `--owner-map` and `--group-map` rewrite
+//! specific accounts, and `--owner`/`--group` force one account for every
+//! member.
+//!
+//! Map files use the GNU tar format: one `SOURCE DEST` pair per line, where
+//! SOURCE is a local name or `+ID` and DEST is `NAME`, `NAME:ID` or `+ID`.
+//! Blank lines and `#` comments are ignored.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// An account as stored in the archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Account {
+    pub id: u32,
+    /// Name for the `uname`/`gname` field; `None` stores only the id
+    pub name: Option<String>,
+}
+
+/// Names and ids from a passwd- or group-format database.
+#[derive(Debug, Clone, Default)]
+pub struct AccountDb {
+    names: HashMap<u32, String>,
+    ids: HashMap<String, u32>,
+}
+
+impl AccountDb {
+    /// Parses `name:password:id:...` lines; malformed lines are ignored.
+    pub fn parse(contents: &str) -> Self {
+        let mut db = Self::default();
+        for line in contents.lines() {
+            let mut fields = line.split(':');
+            let (Some(name), Some(_), Some(id)) = (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            let Ok(id) = id.parse() else {
+                continue;
+            };
+            if name.is_empty() || name.starts_with('#') {
+                continue;
+            }
+            db.names.entry(id).or_insert_with(|| name.to_string());
+            db.ids.entry(name.to_string()).or_insert(id);
+        }
+        db
+    }
+
+    /// Loads a database file; a missing file yields an empty database.
+    pub fn load(path: &Path) -> Self {
+        fs::read_to_string(path).map_or_else(|_| Self::default(), |c| Self::parse(&c))
+    }
+
+    /// Returns the name of an id.
+    pub fn name(&self, id: u32) -> Option<&str> {
+        self.names.get(&id).map(String::as_str)
+    }
+
+    /// Returns the id of a name.
+    pub fn id(&self, name: &str) -> Option<u32> {
+        self.ids.get(name).copied()
+    }
+
+    /// Parses an account spec: `NAME`, `NAME:ID` or `+ID`.
+    ///
+    /// A bare name must exist in this database; a bare id takes its name
+    /// from it, if any.
+    pub fn account(&self, spec: &str, kind: &str) -> Result<Account> {
+        if let Some(id) = spec.strip_prefix('+') {
+            let id = id
+                .parse()
+                .with_context(|| format!("Invalid {kind} id in {spec:?}"))?;
+            return Ok(Account {
+                id,
+                name: self.name(id).map(str::to_string),
+            });
+        }
+
+        let (name, id) = if let Some((name, id)) = spec.split_once(':') {
+            let id = id
+                .parse()
+                .with_context(|| format!("Invalid {kind} id in {spec:?}"))?;
+            (name, id)
+        } else {
+            let id = self.id(spec).with_context(|| {
+                format!("Unknown local {kind} {spec:?}; give its id as {spec}:ID")
+            })?;
+            (spec, id)
+        };
+        if name.is_empty() {
+            anyhow::bail!("Empty {kind} name in {spec:?}");
+        }
+        Ok(Account {
+            id,
+            name: Some(name.to_string()),
+        })
+    }
+
+    /// Parses a map file into source id → replacement account.
+    pub fn parse_map(&self, contents: &str, kind: &str) -> Result<HashMap<u32, Account>> {
+        let mut map = HashMap::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let context = || format!("line {}: {line}", number + 1);
+            let mut fields = line.split_whitespace();
+            let (Some(source), Some(dest), None) = (fields.next(), fields.next(), fields.next())
+            else {
+                anyhow::bail!("Expected `SOURCE DEST` on {}", context());
+            };
+
+            let source = match source.strip_prefix('+') {
+                Some(id) => id.parse().with_context(context)?,
+                None => self
+                    .id(source)
+                    .with_context(|| format!("Unknown local {kind} on {}", context()))?,
+            };
+            map.insert(source, self.account(dest, kind).with_context(context)?);
+        }
+        Ok(map)
+    }
+}
+
+/// The owner and group stored for one member.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Owner {
+    pub uid: u32,
+    pub gid: u32,
+    pub uname: Option<String>,
+    pub gname: Option<String>,
+}
+
+/// How owners are written to tar headers.
+#[derive(Debug, Clone, Default)]
+pub struct Ownership {
+    /// Start from the ids found on disk instead of 0
+    pub preserve: bool,
+    /// Leave the user and group name fields empty
+    pub numeric: bool,
+    /// Forced owner for every member
+    pub owner: Option<Account>,
+    /// Forced group for every member
+    pub group: Option<Account>,
+    pub owner_map: HashMap<u32, Account>,
+    pub group_map: HashMap<u32, Account>,
+    pub users: AccountDb,
+    pub groups: AccountDb,
+}
+
+/// Picks the stored id and name for one side (user or group).
+fn resolve_one(
+    id: u32,
+    preserve: bool,
+    map: &HashMap<u32, Account>,
+    forced: Option<&Account>,
+    db: &AccountDb,
+    numeric: bool,
+) -> (u32, Option<String>) {
+    let account = forced
+        .or_else(|| map.get(&id).filter(|_| preserve))
+        .cloned()
+        .unwrap_or_else(|| Account {
+            id: if preserve { id } else { 0 },
+            // Names are only looked up for real owners
+            name: preserve.then(|| db.name(id).map(str::to_string)).flatten(),
+        });
+    let name = if numeric { None } else { account.name };
+    (account.id, name)
+}
+
+impl Ownership {
+    /// Returns the owner stored for a file owned by `uid`/`gid` on disk.
+    pub fn resolve(&self, uid: u32, gid: u32) -> Owner {
+        let (uid, uname) = resolve_one(
+            uid,
+            self.preserve,
+            &self.owner_map,
+            self.owner.as_ref(),
+            &self.users,
+            self.numeric,
+        );
+        let (gid, gname) = resolve_one(
+            gid,
+            self.preserve,
+            &self.group_map,
+            self.group.as_ref(),
+            &self.groups,
+            self.numeric,
+        );
+        Owner {
+            uid,
+            gid,
+            uname,
+            gname,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWD: &str = "root:x:0:0:root:/root:/bin/sh\n\
+                          alice:x:1000:1000::/home/alice:/bin/sh\n\
+                          app:x:1500:1500::/srv/app:/sbin/nologin\n";
+
+    fn users() -> AccountDb {
+        AccountDb::parse(PASSWD)
+    }
+
+    #[test]
+    fn test_account_specs() {
+        let db = users();
+        assert_eq!(
+            db.account("app", "user").unwrap(),
+            Account {
+                id: 1500,
+                name: Some("app".to_string())
+            }
+        );
+        assert_eq!(db.account("deploy:42", "user").unwrap().id, 42);
+        assert_eq!(
+            db.account("+1000", "user").unwrap().name.as_deref(),
+            Some("alice")
+        );
+        assert_eq!(db.account("+77", "user").unwrap().name, None);
+        assert!(db.account("nobody-here", "user").is_err());
+        assert!(db.account("x:y", "user").is_err());
+    }
+
+    #[test]
+    fn test_parse_map() {
+        let db = users();
+        let map = db
+            .parse_map(
+                "# local -> deploy\nalice  app\n+2000 deploy:3000\n\n",
+                "user",
+            )
+            .unwrap();
+        assert_eq!(map[&1000].id, 1500);
+        assert_eq!(map[&2000].name.as_deref(), Some("deploy"));
+        assert!(db.parse_map("alice", "user").is_err());
+        assert!(db.parse_map("ghost app", "user").is_err());
+    }
+
+    #[test]
+    fn test_default_stores_root_without_names() {
+        let ownership = Ownership {
+            users: users(),
+            ..Ownership::default()
+        };
+        let owner = ownership.resolve(1000, 1000);
+        assert_eq!((owner.uid, owner.gid), (0, 0));
+        assert_eq!(owner.uname, None);
+    }
+
+    #[test]
+    fn test_preserve_fills_names_and_applies_map() {
+        let db = users();
+        let ownership = Ownership {
+            preserve: true,
+            owner_map: db.parse_map("alice app", "user").unwrap(),
+            users: db,
+            ..Ownership::default()
+        };
+        let mapped = ownership.resolve(1000, 1000);
+        assert_eq!(mapped.uid, 1500);
+        assert_eq!(mapped.uname.as_deref(), Some("app"));
+        let root = ownership.resolve(0, 0);
+        assert_eq!(root.uname.as_deref(), Some("root"));
+    }
+
+    #[test]
+    fn test_forced_owner_and_numeric() {
+        let db = users();
+        let ownership = Ownership {
+            owner: Some(db.account("app", "user").unwrap()),
+            numeric: true,
+            users: db,
+            ..Ownership::default()
+        };
+        let owner = ownership.resolve(1000, 1000);
+        assert_eq!(owner.uid, 1500);
+        assert_eq!(owner.uname, None);
+        assert_eq!(owner.gid, 0);
+    }
+}
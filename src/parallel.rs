@@ -0,0 +1,237 @@
+//! Parallel block compression for gzip and bzip2.
+//!
+//! Both formats allow complete streams to be concatenated, so the input is cut
+//! into fixed-size blocks that are compressed independently on worker threads
+//! (one gzip member or bzip2 stream per block) and written out in order. Any
+//! gunzip or bunzip2 decodes the result as a single file, the same approach
+//! pigz and pbzip2 take. Block boundaries depend only on the block size, so
+//! the output is deterministic.
+
+use bzip2::write::BzEncoder;
+use flate2::write::GzEncoder;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// Uncompressed bytes per gzip member; large enough that restarting the
+/// dictionary costs little ratio.
+const GZIP_BLOCK_SIZE: usize = 1024 * 1024;
+
+/// Uncompressed bytes per bzip2 stream, matching the 900k maximum block size.
+const BZIP2_BLOCK_SIZE: usize = 900 * 1000;
+
+type CompressFn = Arc<dyn Fn(&[u8]) -> io::Result<Vec<u8>> + Send + Sync>;
+type Job = (usize, Vec<u8>);
+type Done = (usize, io::Result<Vec<u8>>);
+
+/// A writer that compresses blocks on a pool of threads.
+///
+/// Call [`ParallelEncoder::finish`] to flush the last block; dropping the
+/// encoder discards any buffered data.
+pub struct ParallelEncoder<W: Write> {
+    writer: W,
+    block_size: usize,
+    buffer: Vec<u8>,
+    jobs: Option<Sender<Job>>,
+    results: Receiver<Done>,
+    workers: Vec<JoinHandle<()>>,
+    /// Blocks handed to workers so far
+    submitted: usize,
+    /// Index of the next block to write
+    written: usize,
+    /// Compressed blocks waiting for their predecessors
+    ready: BTreeMap<usize, Vec<u8>>,
+    /// Maximum blocks in flight before `write` waits for results
+    max_pending: usize,
+}
+
+impl<W: Write> ParallelEncoder<W> {
+    /// Creates a gzip encoder that writes one member per block.
+    pub fn gzip(writer: W, threads: u32, level: flate2::Compression) -> Self {
+        let compress: CompressFn = Arc::new(move |block: &[u8]| {
+            let mut encoder = GzEncoder::new(Vec::with_capacity(block.len() / 2), level);
+            encoder.write_all(block)?;
+            encoder.finish()
+        });
+        Self::new(writer, threads, GZIP_BLOCK_SIZE, &compress)
+    }
+
+    /// Creates a bzip2 encoder that writes one stream per block.
+    pub fn bzip2(writer: W, threads: u32, level: bzip2::Compression) -> Self {
+        let compress: CompressFn = Arc::new(move |block: &[u8]| {
+            let mut encoder = BzEncoder::new(Vec::with_capacity(block.len() / 4), level);
+            encoder.write_all(block)?;
+            encoder.finish()
+        });
+        Self::new(writer, threads, BZIP2_BLOCK_SIZE, &compress)
+    }
+
+    fn new(writer: W, threads: u32, block_size: usize, compress: &CompressFn) -> Self {
+        let (job_tx, job_rx) = mpsc::channel::<Job>();
+        let (done_tx, done_rx) = mpsc::channel::<Done>();
+        let job_rx = Arc::new(Mutex::new(job_rx));
+
+        let workers = (0..threads.max(1))
+            .map(|_| {
+                let jobs = Arc::clone(&job_rx);
+                let done = done_tx.clone();
+                let compress = Arc::clone(compress);
+                thread::spawn(move || loop {
+                    let job = match jobs.lock() {
+                        Ok(jobs) => jobs.recv(),
+                        Err(_) => break,
+                    };
+                    let Ok((index, block)) = job else {
+                        break;
+                    };
+                    if done.send((index, compress(&block))).is_err() {
+                        break;
+                    }
+                })
+            })
+            .collect();
+
+        Self {
+            writer,
+            block_size,
+            buffer: Vec::with_capacity(block_size),
+            jobs: Some(job_tx),
+            results: done_rx,
+            workers,
+            submitted: 0,
+            written: 0,
+            ready: BTreeMap::new(),
+            max_pending: threads.max(1) as usize * 2,
+        }
+    }
+
+    /// Hands the buffered block to the workers, waiting if too many are queued.
+    fn submit(&mut self) -> io::Result<()> {
+        let block = std::mem::replace(&mut self.buffer, Vec::with_capacity(self.block_size));
+        self.jobs
+            .as_ref()
+            .and_then(|jobs| jobs.send((self.submitted, block)).ok())
+            .ok_or_else(|| io::Error::other("compression worker exited"))?;
+        self.submitted += 1;
+
+        while self.submitted - self.written > self.max_pending {
+            self.receive()?;
+        }
+        Ok(())
+    }
+
+    /// Waits for one compressed block and writes every block that is now in order.
+    fn receive(&mut self) -> io::Result<()> {
+        let (index, result) = self
+            .results
+            .recv()
+            .map_err(|_| io::Error::other("compression worker exited"))?;
+        self.ready.insert(index, result?);
+
+        while let Some(data) = self.ready.remove(&self.written) {
+            self.writer.write_all(&data)?;
+            self.written += 1;
+        }
+        Ok(())
+    }
+
+    /// Compresses the remaining input and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        // An empty input still needs one (empty) member to be a valid file
+        if !self.buffer.is_empty() || self.submitted == 0 {
+            self.submit()?;
+        }
+        self.jobs = None;
+
+        while self.written < self.submitted {
+            self.receive()?;
+        }
+        for worker in self.workers.drain(..) {
+            worker
+                .join()
+                .map_err(|_| io::Error::other("compression worker panicked"))?;
+        }
+
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for ParallelEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = (self.block_size - self.buffer.len()).min(buf.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        if self.buffer.len() == self.block_size {
+            self.submit()?;
+        }
+        Ok(len)
+    }
+
+    /// Flushes only the underlying writer; partial blocks are kept so block
+    /// boundaries do not depend on when the caller flushes.
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::MultiGzDecoder;
+    use std::io::Read;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..=250u8).cycle().take(len).collect()
+    }
+
+    fn gzip(data: &[u8], threads: u32) -> Vec<u8> {
+        let mut encoder = ParallelEncoder::gzip(Vec::new(), threads, flate2::Compression::fast());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_gzip_round_trip_multiple_blocks() {
+        let data = sample(GZIP_BLOCK_SIZE * 3 + 17);
+        let compressed = gzip(&data, 4);
+
+        let mut decoded = Vec::new();
+        MultiGzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn test_gzip_output_is_deterministic() {
+        let data = sample(GZIP_BLOCK_SIZE * 2 + 5);
+        assert_eq!(gzip(&data, 3), gzip(&data, 3));
+    }
+
+    #[test]
+    fn test_empty_input_is_valid_gzip() {
+        let compressed = gzip(&[], 2);
+
+        let mut decoded = Vec::new();
+        MultiGzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert!(decoded.is_empty());
+    }
+
+    #[test]
+    fn test_bzip2_round_trip_multiple_blocks() {
+        let data = sample(BZIP2_BLOCK_SIZE * 2 + 1);
+        let mut encoder = ParallelEncoder::bzip2(Vec::new(), 2, bzip2::Compression::fast());
+        encoder.write_all(&data).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut decoded = Vec::new();
+        bzip2::read::MultiBzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+    }
+}
@@ -0,0 +1,153 @@
+//! Reading existing archives: decryption, format detection and decompression.
+
+use crate::encrypt::{self, Keys};
+use crate::Format;
+
+use age::armor::ArmoredReader;
+use anyhow::{bail, Context, Result};
+use brotli::Decompressor as BrotliDecoder;
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+use liblzma::read::XzDecoder;
+use lz4::Decoder as Lz4Decoder;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use zstd::stream::read::Decoder as ZstdDecoder;
+
+/// A tar archive reader over any decompressed stream.
+pub type TarReader = tar::Archive<Box<dyn Read>>;
+
+/// A readable, seekable archive source.
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// A zip archive reader over a plain or decrypted file.
+pub type ZipReader = zip::ZipArchive<Box<dyn ReadSeek>>;
+
+/// An archive opened for reading.
+pub enum ArchiveReader {
+    Tar(TarReader),
+    Zip(ZipReader),
+}
+
+/// Detects the format of an archive from its leading bytes.
+///
+/// Falls back to the file extension (without a trailing `.age`) for formats
+/// without a reliable magic number (brotli, old-style v7 tar).
+fn detect_format(magic: &[u8], path: &Path) -> Result<Format> {
+    if magic.starts_with(&[0x1f, 0x8b]) {
+        return Ok(Format::TarGz);
+    }
+    if magic.starts_with(b"BZh") {
+        return Ok(Format::TarBz2);
+    }
+    if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        return Ok(Format::TarZst);
+    }
+    if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        return Ok(Format::TarXz);
+    }
+    if magic.starts_with(&[0x04, 0x22, 0x4d, 0x18]) {
+        return Ok(Format::TarLz4);
+    }
+    if magic.starts_with(b"PK\x03\x04") || magic.starts_with(b"PK\x05\x06") {
+        return Ok(Format::Zip);
+    }
+    if magic.get(257..262) == Some(b"ustar".as_slice()) {
+        return Ok(Format::Tar);
+    }
+
+    let name = if path.extension().is_some_and(|ext| ext == "age") {
+        path.with_extension("")
+    } else {
+        path.to_path_buf()
+    };
+    match Format::from_path(&name) {
+        Some(format) => Ok(format),
+        None => bail!("Unrecognized archive format: {}", path.display()),
+    }
+}
+
+/// Reads up to 512 leading bytes and rewinds.
+fn peek(source: &mut dyn ReadSeek) -> Result<Vec<u8>> {
+    let mut magic = Vec::with_capacity(512);
+    source.take(512).read_to_end(&mut magic)?;
+    source.seek(SeekFrom::Start(0))?;
+    Ok(magic)
+}
+
+/// Opens an archive, decrypting it if it is an age file.
+///
+/// The format is detected from the (decrypted) contents unless given.
+pub fn open(path: &Path, format: Option<Format>, keys: &Keys) -> Result<ArchiveReader> {
+    let file = BufReader::new(
+        File::open(path).with_context(|| format!("Failed to open archive: {}", path.display()))?,
+    );
+    let mut source: Box<dyn ReadSeek> = Box::new(file);
+    let mut magic = peek(&mut source)?;
+    if encrypt::is_age(&magic) {
+        let file = BufReader::new(File::open(path)?);
+        source = Box::new(keys.decrypt(ArmoredReader::new(file), path)?);
+        magic = peek(&mut source)?;
+    }
+
+    let format = match format {
+        Some(format) => format,
+        None => detect_format(&magic, path)?,
+    };
+
+    if format == Format::Zip {
+        let archive = zip::ZipArchive::new(source)
+            .with_context(|| format!("Failed to read zip archive: {}", path.display()))?;
+        return Ok(ArchiveReader::Zip(archive));
+    }
+
+    let reader: Box<dyn Read> = match format {
+        Format::Tar => Box::new(source),
+        Format::TarGz => Box::new(MultiGzDecoder::new(source)),
+        Format::TarBz2 => Box::new(MultiBzDecoder::new(source)),
+        Format::TarZst => {
+            // Accept windows up to 2 GiB so long-distance archives decode
+            let mut decoder = ZstdDecoder::new(source)?;
+            decoder.window_log_max(31)?;
+            Box::new(decoder)
+        }
+        Format::TarXz => Box::new(XzDecoder::new_multi_decoder(source)),
+        Format::TarLz4 => Box::new(Lz4Decoder::new(source)?),
+        Format::TarBr => Box::new(BrotliDecoder::new(source, 4096)),
+        Format::Zip => unreachable!("zip is opened above"),
+    };
+
+    Ok(ArchiveReader::Tar(tar::Archive::new(reader)))
+}
+
+/// Returns a zip member, decrypting it with the passphrase if it is
+/// encrypted.
+pub fn zip_member<'a>(
+    archive: &'a mut ZipReader,
+    index: usize,
+    keys: &Keys,
+) -> Result<zip::read::ZipFile<'a, Box<dyn ReadSeek>>> {
+    if archive.by_index_raw(index)?.encrypted() {
+        let passphrase = keys.passphrase()?;
+        return archive
+            .by_index_decrypt(index, passphrase.as_bytes())
+            .context("Failed to decrypt zip member (wrong passphrase?)");
+    }
+    Ok(archive.by_index(index)?)
+}
+
+/// Converts a zip timestamp to Unix seconds.
+///
+/// Zip stores local time without a zone; `create_zip` writes UTC, so that is
+/// how it is read back.
+pub fn zip_timestamp(dt: zip::DateTime) -> Option<i64> {
+    let month = time::Month::try_from(dt.month()).ok()?;
+    let datetime = time::Date::from_calendar_date(i32::from(dt.year()), month, dt.day())
+        .ok()?
+        .with_hms(dt.hour(), dt.minute(), dt.second())
+        .ok()?;
+    Some(datetime.assume_utc().unix_timestamp())
+}
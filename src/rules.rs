@@ -9,7 +9,7 @@ use colored::Colorize;
 use globset::{Glob, GlobMatcher};
 use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Write};
 use std::path::{Path, PathBuf};
 
 /// Action to take when a rule matches.
@@ -627,9 +627,9 @@ pub fn parse_ignore_file(path: &Path, index: &mut RuleIndex, action: Action) ->
 }
 
 /// Print rules in verbose mode.
-pub fn print_rules_verbose(index: &RuleIndex, verbose: bool) {
+pub fn print_rules_verbose(out: &mut dyn Write, index: &RuleIndex, verbose: bool) -> Result<()> {
     if !verbose {
-        return;
+        return Ok(());
     }
 
     // Group by source
@@ -642,16 +642,17 @@ pub fn print_rules_verbose(index: &RuleIndex, verbose: bool) {
     }
 
     for (source, rules) in &by_source {
-        println!("Excluding ({}):", source.cyan());
+        writeln!(out, "Excluding ({}):", source.cyan())?;
         for rule in rules {
             let indicator = if rule.action == Action::Include {
                 "+".green()
             } else {
                 "-".red()
             };
-            println!("  {} {}", indicator, rule.info.original);
+            writeln!(out, "  {} {}", indicator, rule.info.original)?;
         }
     }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -0,0 +1,441 @@
+//! Detached minisign signatures (`--sign` and `raptar verify-signature`).
+//!
+//! Keys and signatures use the minisign file formats, so `minisign -V`
+//! verifies what raptar signs, and keys made with `minisign -G` work here.
+//! Archives are signed in minisign's prehashed mode: the ed25519 signature
+//! covers the BLAKE2b-512 digest of the archive, which is computed while the
+//! archive is written. Verification also accepts legacy (non-prehashed)
+//! signatures.
+//!
+//! Encrypted secret keys are unlocked with the password in
+//! `RAPTAR_SIGN_PASSWORD`, or one typed at a prompt.
+
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use blake2::digest::consts::U32;
+use blake2::{Blake2b, Blake2b512, Digest};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Signature algorithm id of keys and legacy signatures.
+const ALG_ED25519: [u8; 2] = *b"Ed";
+/// Signature algorithm id of prehashed signatures.
+const ALG_PREHASHED: [u8; 2] = *b"ED";
+/// Key derivation ids of secret keys.
+const KDF_NONE: [u8; 2] = [0, 0];
+const KDF_SCRYPT: [u8; 2] = *b"Sc";
+/// Checksum algorithm id of secret keys (BLAKE2b-256).
+const CHECKSUM_BLAKE2B: [u8; 2] = *b"B2";
+
+/// Length of the decoded secret key file payload.
+const SECRET_KEY_LEN: usize = 158;
+/// Length of the encrypted part: key id, ed25519 keypair and checksum.
+const KEYNUM_LEN: usize = 104;
+
+/// Environment variable holding the password of an encrypted secret key.
+const PASSWORD_VAR: &str = "RAPTAR_SIGN_PASSWORD";
+
+type KeyId = [u8; 8];
+
+/// Formats a key id the way minisign prints it.
+fn key_id_hex(id: KeyId) -> String {
+    format!("{:016X}", u64::from_le_bytes(id))
+}
+
+/// Returns the base64 line of a minisign file, after its untrusted comment.
+fn payload_line<'a>(contents: &'a str, what: &str) -> Result<&'a str> {
+    let mut lines = contents.lines();
+    let comment = lines.next().unwrap_or_default();
+    if !comment.starts_with("untrusted comment:") {
+        anyhow::bail!("Not a minisign {what}: missing untrusted comment");
+    }
+    lines
+        .next()
+        .map(str::trim)
+        .with_context(|| format!("Not a minisign {what}: missing key data"))
+}
+
+/// Derives the mask of an encrypted secret key with scrypt, using the
+/// parameter choice of libsodium's `crypto_pwhash_scryptsalsa208sha256`.
+fn scrypt_mask(password: &[u8], salt: &[u8], opslimit: u64, memlimit: u64) -> Result<Vec<u8>> {
+    let opslimit = opslimit.max(32_768);
+    let r: u32 = 8;
+    let n_log2 = |max_n: u64| (1..63u8).find(|&n| (1u64 << n) > max_n / 2).unwrap_or(63);
+    let (log_n, p) = if opslimit < memlimit / 32 {
+        (n_log2(opslimit / (u64::from(r) * 4)), 1)
+    } else {
+        let log_n = n_log2(memlimit / (u64::from(r) * 128));
+        let max_rp = ((opslimit / 4) >> log_n).min(0x3fff_ffff);
+        (
+            log_n,
+            u32::try_from(max_rp / u64::from(r)).unwrap_or(1).max(1),
+        )
+    };
+
+    let params = scrypt::Params::new(log_n, r, p, 64)
+        .map_err(|_| anyhow::anyhow!("Unsupported key derivation parameters in secret key"))?;
+    let mut mask = vec![0; KEYNUM_LEN];
+    scrypt::scrypt(password, salt, &params, &mut mask)
+        .map_err(|_| anyhow::anyhow!("Key derivation failed"))?;
+    Ok(mask)
+}
+
+/// Returns the password for an encrypted key.
+fn read_password(path: &Path) -> Result<String> {
+    if let Ok(password) = std::env::var(PASSWORD_VAR) {
+        return Ok(password);
+    }
+    rpassword::prompt_password(format!("Password for {}: ", path.display()))
+        .with_context(|| format!("Failed to read the password (or set {PASSWORD_VAR})"))
+}
+
+/// An ed25519 secret key from a minisign key file.
+pub struct SecretKey {
+    id: KeyId,
+    key: SigningKey,
+}
+
+impl SecretKey {
+    /// Loads a secret key file, asking for its password if it is encrypted.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read secret key {}", path.display()))?;
+        Self::parse(&contents, || read_password(path))
+            .with_context(|| format!("Invalid secret key {}", path.display()))
+    }
+
+    /// Parses a secret key file; `password` is only called for encrypted keys.
+    fn parse(contents: &str, password: impl FnOnce() -> Result<String>) -> Result<Self> {
+        let data = BASE64
+            .decode(payload_line(contents, "secret key")?)
+            .context("Secret key is not valid base64")?;
+        if data.len() != SECRET_KEY_LEN || data[0..2] != ALG_ED25519 {
+            anyhow::bail!("Not an ed25519 minisign secret key");
+        }
+        if data[4..6] != CHECKSUM_BLAKE2B {
+            anyhow::bail!("Unsupported checksum algorithm in secret key");
+        }
+
+        let mut keynum = data[54..].to_vec();
+        match [data[2], data[3]] {
+            KDF_NONE => {}
+            KDF_SCRYPT => {
+                let salt = &data[6..38];
+                let opslimit = u64::from_le_bytes(data[38..46].try_into()?);
+                let memlimit = u64::from_le_bytes(data[46..54].try_into()?);
+                let mask = scrypt_mask(password()?.as_bytes(), salt, opslimit, memlimit)?;
+                keynum.iter_mut().zip(mask).for_each(|(b, m)| *b ^= m);
+            }
+            _ => anyhow::bail!("Unsupported key derivation in secret key"),
+        }
+
+        let id: KeyId = keynum[0..8].try_into()?;
+        let keypair: [u8; 64] = keynum[8..72].try_into()?;
+        let checksum = Blake2b::<U32>::new()
+            .chain_update(ALG_ED25519)
+            .chain_update(id)
+            .chain_update(keypair)
+            .finalize();
+        if checksum.as_slice() != &keynum[72..] {
+            anyhow::bail!("Wrong password or corrupt key");
+        }
+
+        let key = SigningKey::from_keypair_bytes(&keypair).context("Corrupt ed25519 keypair")?;
+        Ok(Self { id, key })
+    }
+
+    /// Builds a prehashed minisign signature for an archive whose BLAKE2b-512
+    /// digest is `prehash`.
+    pub fn sign(&self, prehash: &[u8], file_name: &str, timestamp: u64) -> String {
+        let signature = self.key.sign(prehash).to_bytes();
+        let trusted_comment = format!("timestamp:{timestamp}\tfile:{file_name}\thashed");
+        let global = self
+            .key
+            .sign(&[&signature[..], trusted_comment.as_bytes()].concat())
+            .to_bytes();
+
+        let mut payload = Vec::with_capacity(74);
+        payload.extend(ALG_PREHASHED);
+        payload.extend(self.id);
+        payload.extend(signature);
+        format!(
+            "untrusted comment: signature from raptar secret key {}\n{}\ntrusted comment: {}\n{}\n",
+            key_id_hex(self.id),
+            BASE64.encode(payload),
+            trusted_comment,
+            BASE64.encode(global)
+        )
+    }
+}
+
+/// An ed25519 public key from a minisign key file.
+pub struct PublicKey {
+    id: KeyId,
+    key: VerifyingKey,
+}
+
+impl PublicKey {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read public key {}", path.display()))?;
+        Self::parse(&contents).with_context(|| format!("Invalid public key {}", path.display()))
+    }
+
+    fn parse(contents: &str) -> Result<Self> {
+        let data = BASE64
+            .decode(payload_line(contents, "public key")?)
+            .context("Public key is not valid base64")?;
+        if data.len() != 42 || data[0..2] != ALG_ED25519 {
+            anyhow::bail!("Not an ed25519 minisign public key");
+        }
+        let key: [u8; 32] = data[10..].try_into()?;
+        Ok(Self {
+            id: data[2..10].try_into()?,
+            key: VerifyingKey::from_bytes(&key).context("Corrupt ed25519 public key")?,
+        })
+    }
+
+    /// Checks a minisign signature over the data read from `data`.
+    ///
+    /// Returns the trusted comment on success.
+    pub fn verify(&self, signature_file: &str, mut data: impl Read) -> Result<String> {
+        let mut lines = signature_file.lines();
+        let (Some(_), Some(signature), Some(trusted), Some(global)) =
+            (lines.next(), lines.next(), lines.next(), lines.next())
+        else {
+            anyhow::bail!("Signature file is truncated");
+        };
+        let signature = BASE64
+            .decode(signature.trim())
+            .context("Signature is not valid base64")?;
+        let trusted = trusted
+            .strip_prefix("trusted comment: ")
+            .context("Signature has no trusted comment")?;
+        let global = BASE64
+            .decode(global.trim())
+            .context("Global signature is not valid base64")?;
+        if signature.len() != 74 || global.len() != 64 {
+            anyhow::bail!("Signature has the wrong length");
+        }
+
+        let id: KeyId = signature[2..10].try_into()?;
+        if id != self.id {
+            anyhow::bail!(
+                "Signature was made with key {}, not {}",
+                key_id_hex(id),
+                key_id_hex(self.id)
+            );
+        }
+        let bytes: [u8; 64] = signature[10..].try_into()?;
+        let message = match [signature[0], signature[1]] {
+            ALG_PREHASHED => {
+                let mut hasher = PrehashWriter::new(io::sink(), true);
+                io::copy(&mut data, &mut hasher)?;
+                hasher.finish().1.unwrap_or_default()
+            }
+            ALG_ED25519 => {
+                let mut contents = Vec::new();
+                data.read_to_end(&mut contents)?;
+                contents
+            }
+            _ => anyhow::bail!("Unsupported signature algorithm"),
+        };
+
+        self.key
+            .verify(&message, &Signature::from_bytes(&bytes))
+            .map_err(|_| anyhow::anyhow!("Signature does not match the file"))?;
+        let global: [u8; 64] = global.as_slice().try_into()?;
+        self.key
+            .verify(
+                &[&bytes[..], trusted.as_bytes()].concat(),
+                &Signature::from_bytes(&global),
+            )
+            .map_err(|_| anyhow::anyhow!("Trusted comment has been tampered with"))?;
+        Ok(trusted.to_string())
+    }
+}
+
+/// Passes writes through, computing the BLAKE2b-512 prehash when enabled.
+pub struct PrehashWriter<W> {
+    inner: W,
+    hasher: Option<Blake2b512>,
+}
+
+impl<W: Write> PrehashWriter<W> {
+    pub fn new(inner: W, enabled: bool) -> Self {
+        Self {
+            inner,
+            hasher: enabled.then(Blake2b512::new),
+        }
+    }
+
+    /// Returns the inner writer and the prehash of everything written.
+    pub fn finish(self) -> (W, Option<Vec<u8>>) {
+        (self.inner, self.hasher.map(|h| h.finalize().to_vec()))
+    }
+}
+
+impl<W: Write> Write for PrehashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some(ref mut hasher) = self.hasher {
+            hasher.update(&buf[..written]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Returns the path of the signature written next to `output`.
+pub fn signature_path(output: &Path) -> PathBuf {
+    let mut name = output.as_os_str().to_os_string();
+    name.push(".minisig");
+    PathBuf::from(name)
+}
+
+/// Writes `<output>.minisig` for an archive with the given prehash.
+pub fn write_signature(key: &SecretKey, output: &Path, prehash: &[u8]) -> Result<PathBuf> {
+    let file_name = output
+        .file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().to_string());
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let path = signature_path(output);
+    fs::write(&path, key.sign(prehash, &file_name, timestamp))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+/// Checks `<archive>.minisig` (or `signature`) against a public key file.
+///
+/// Returns the trusted comment on success.
+pub fn verify_file(archive: &Path, signature: Option<&Path>, public_key: &Path) -> Result<String> {
+    let key = PublicKey::load(public_key)?;
+    let signature_path = signature.map_or_else(|| signature_path(archive), Path::to_path_buf);
+    let signature = fs::read_to_string(&signature_path)
+        .with_context(|| format!("Failed to read signature {}", signature_path.display()))?;
+    let data =
+        File::open(archive).with_context(|| format!("Failed to open {}", archive.display()))?;
+    key.verify(&signature, io::BufReader::new(data))
+        .with_context(|| format!("{} failed verification", archive.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED: [u8; 32] = [7; 32];
+    const ID: KeyId = [1, 2, 3, 4, 5, 6, 7, 8];
+
+    /// Builds secret and public key files, encrypting the secret key with
+    /// `password` (cheap scrypt parameters) if given.
+    fn key_files(password: Option<&str>) -> (String, String) {
+        let key = SigningKey::from_bytes(&SEED);
+        let keypair = key.to_keypair_bytes();
+        let checksum = Blake2b::<U32>::new()
+            .chain_update(ALG_ED25519)
+            .chain_update(ID)
+            .chain_update(keypair)
+            .finalize();
+        let mut keynum = [&ID[..], &keypair, &checksum].concat();
+
+        let salt = [9u8; 32];
+        let (opslimit, memlimit) = (32_768u64, 16u64 << 20);
+        let kdf = password.map_or(KDF_NONE, |password| {
+            let mask = scrypt_mask(password.as_bytes(), &salt, opslimit, memlimit).unwrap();
+            keynum.iter_mut().zip(mask).for_each(|(b, m)| *b ^= m);
+            KDF_SCRYPT
+        });
+        let secret = [
+            &ALG_ED25519[..],
+            &kdf,
+            &CHECKSUM_BLAKE2B,
+            &salt,
+            &opslimit.to_le_bytes(),
+            &memlimit.to_le_bytes(),
+            &keynum,
+        ]
+        .concat();
+        let public = [&ALG_ED25519[..], &ID, key.verifying_key().as_bytes()].concat();
+        (
+            format!(
+                "untrusted comment: test secret key\n{}\n",
+                BASE64.encode(secret)
+            ),
+            format!(
+                "untrusted comment: test public key\n{}\n",
+                BASE64.encode(public)
+            ),
+        )
+    }
+
+    fn prehash(data: &[u8]) -> Vec<u8> {
+        Blake2b512::digest(data).to_vec()
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let (secret, public) = key_files(None);
+        let secret = SecretKey::parse(&secret, || unreachable!()).unwrap();
+        let public = PublicKey::parse(&public).unwrap();
+
+        let signature = secret.sign(&prehash(b"archive"), "out.tar.gz", 1_700_000_000);
+        let trusted = public.verify(&signature, &b"archive"[..]).unwrap();
+        assert_eq!(trusted, "timestamp:1700000000\tfile:out.tar.gz\thashed");
+        assert!(public.verify(&signature, &b"archivE"[..]).is_err());
+    }
+
+    #[test]
+    fn test_tampered_trusted_comment() {
+        let (secret, public) = key_files(None);
+        let secret = SecretKey::parse(&secret, || unreachable!()).unwrap();
+        let public = PublicKey::parse(&public).unwrap();
+
+        let signature = secret.sign(&prehash(b"archive"), "out.tar.gz", 1);
+        let forged = signature.replace("file:out.tar.gz", "file:other.tar.gz");
+        assert!(public.verify(&forged, &b"archive"[..]).is_err());
+    }
+
+    #[test]
+    fn test_encrypted_key() {
+        let (secret, _) = key_files(Some("hunter2"));
+        assert!(SecretKey::parse(&secret, || Ok("hunter2".to_string())).is_ok());
+        let wrong = SecretKey::parse(&secret, || Ok("hunter3".to_string()));
+        assert!(wrong.err().unwrap().to_string().contains("Wrong password"));
+    }
+
+    #[test]
+    fn test_legacy_signature() {
+        let (_, public) = key_files(None);
+        let public = PublicKey::parse(&public).unwrap();
+        let key = SigningKey::from_bytes(&SEED);
+
+        let signature = key.sign(b"archive").to_bytes();
+        let trusted = "timestamp:1";
+        let global = key.sign(&[&signature[..], trusted.as_bytes()].concat());
+        let payload = [&ALG_ED25519[..], &ID, &signature].concat();
+        let file = format!(
+            "untrusted comment: x\n{}\ntrusted comment: {trusted}\n{}\n",
+            BASE64.encode(payload),
+            BASE64.encode(global.to_bytes())
+        );
+        assert!(public.verify(&file, &b"archive"[..]).is_ok());
+    }
+
+    #[test]
+    fn test_prehash_writer() {
+        let mut writer = PrehashWriter::new(Vec::new(), true);
+        writer.write_all(b"archive").unwrap();
+        let (inner, digest) = writer.finish();
+        assert_eq!(inner, b"archive");
+        assert_eq!(digest.unwrap(), prehash(b"archive"));
+    }
+}
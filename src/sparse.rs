@@ -0,0 +1,352 @@
+//! Sparse file detection and the GNU and PAX 1.0 sparse tar encodings.
+//!
+//! With `--sparse`, the walk asks the filesystem for the data regions of each
+//! file that occupies fewer blocks than its length (`SEEK_DATA`/`SEEK_HOLE`).
+//! Only those regions are stored; holes are recreated on extraction.
+//!
+//! The GNU format lists the regions in the header (and extension blocks after
+//! it, 21 regions each). The pax format uses the PAX 1.0 layout GNU tar
+//! writes: `GNU.sparse.*` records, a member named `GNUSparseFile.0/<name>`,
+//! and a decimal region map at the start of the member data.
+
+use rustix::fs::SeekFrom;
+use std::fs::{File, Metadata};
+use std::io::{self, Read, Seek, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// Tar block size.
+const BLOCK_SIZE: usize = 512;
+
+/// A data region of a sparse file: `(offset, length)`.
+pub type Region = (u64, u64);
+
+/// Returns the data regions of a file with holes, or `None` if it is dense.
+///
+/// Filesystems that cannot report holes are treated as dense.
+pub fn data_regions(path: &Path, metadata: &Metadata) -> io::Result<Option<Vec<Region>>> {
+    let size = metadata.len();
+    // Cheap check first: a file using as many blocks as its length has no holes
+    if size == 0 || metadata.blocks() * 512 >= size {
+        return Ok(None);
+    }
+
+    let file = File::open(path)?;
+    let mut regions = Vec::new();
+    let mut offset = 0;
+    while offset < size {
+        let start = match rustix::fs::seek(&file, SeekFrom::Data(offset)) {
+            Ok(start) => start,
+            // No data past `offset`: the rest of the file is a hole
+            Err(rustix::io::Errno::NXIO) => break,
+            Err(rustix::io::Errno::INVAL) if regions.is_empty() => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let end = rustix::fs::seek(&file, SeekFrom::Hole(start))?.min(size);
+        if end > start {
+            regions.push((start, end - start));
+        }
+        offset = end;
+    }
+
+    if regions == [(0, size)] {
+        return Ok(None);
+    }
+    Ok(Some(regions))
+}
+
+/// Returns the number of bytes stored for a set of regions.
+pub fn data_size(regions: &[Region]) -> u64 {
+    regions.iter().map(|&(_, len)| len).sum()
+}
+
+/// Reads the data regions of a file back to back.
+pub struct RegionReader {
+    file: File,
+    regions: Vec<Region>,
+    /// Index of the next region to start
+    next: usize,
+    /// Bytes left in the current region
+    remaining: u64,
+}
+
+impl RegionReader {
+    pub fn new(file: File, regions: &[Region]) -> Self {
+        Self {
+            file,
+            regions: regions.to_vec(),
+            next: 0,
+            remaining: 0,
+        }
+    }
+}
+
+impl Read for RegionReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.remaining == 0 {
+            let Some(&(offset, len)) = self.regions.get(self.next) else {
+                return Ok(0);
+            };
+            self.next += 1;
+            self.file.seek(io::SeekFrom::Start(offset))?;
+            self.remaining = len;
+        }
+
+        let max = usize::try_from(self.remaining).unwrap_or(usize::MAX);
+        let len = buf.len().min(max);
+        let read = self.file.read(&mut buf[..len])?;
+        if read == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "file shrank while being archived",
+            ));
+        }
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+/// Returns the regions to list in a GNU sparse map.
+///
+/// A file ending in a hole gets a final empty region at its end, as GNU tar
+/// writes, so readers know the full length.
+fn gnu_map(regions: &[Region], real_size: u64) -> Vec<Region> {
+    let mut map = regions.to_vec();
+    let end = map.last().map_or(0, |&(offset, len)| offset + len);
+    if end < real_size || map.is_empty() {
+        map.push((real_size, 0));
+    }
+    map
+}
+
+/// Fills in the sparse fields of a GNU header and returns the extension
+/// blocks that must follow it, ahead of the data.
+pub fn gnu_sparse_header(
+    header: &mut tar::Header,
+    regions: &[Region],
+    real_size: u64,
+) -> io::Result<Vec<u8>> {
+    let map = gnu_map(regions, real_size);
+    let (first, rest) = map.split_at(map.len().min(4));
+
+    let gnu = header
+        .as_gnu_mut()
+        .ok_or_else(|| io::Error::other("GNU sparse entries need a GNU header"))?;
+    for (slot, &(offset, len)) in gnu.sparse.iter_mut().zip(first) {
+        slot.set_offset(offset);
+        slot.set_length(len);
+    }
+    gnu.set_real_size(real_size);
+    gnu.set_is_extended(!rest.is_empty());
+
+    let mut blocks = Vec::new();
+    let chunks: Vec<&[Region]> = rest.chunks(21).collect();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let mut ext = tar::GnuExtSparseHeader::new();
+        for (slot, &(offset, len)) in ext.sparse_mut().iter_mut().zip(chunk.iter()) {
+            slot.set_offset(offset);
+            slot.set_length(len);
+        }
+        ext.set_is_extended(i + 1 < chunks.len());
+        blocks.extend_from_slice(ext.as_bytes());
+    }
+    Ok(blocks)
+}
+
+/// Returns the PAX 1.0 member name for a sparse file:
+/// `<dir>/GNUSparseFile.0/<file>`.
+///
+/// GNU tar puts its process id in place of the `0`; a fixed value keeps
+/// archives reproducible.
+pub fn pax_member_name(name: &Path) -> PathBuf {
+    let parent = name.parent().unwrap_or_else(|| Path::new(""));
+    parent
+        .join("GNUSparseFile.0")
+        .join(name.file_name().unwrap_or_default())
+}
+
+/// Returns the `GNU.sparse.*` records describing a PAX 1.0 sparse file.
+pub fn pax_records(name: &Path, real_size: u64) -> Vec<(String, Vec<u8>)> {
+    use std::os::unix::ffi::OsStrExt;
+
+    vec![
+        ("GNU.sparse.major".to_string(), b"1".to_vec()),
+        ("GNU.sparse.minor".to_string(), b"0".to_vec()),
+        (
+            "GNU.sparse.name".to_string(),
+            name.as_os_str().as_bytes().to_vec(),
+        ),
+        (
+            "GNU.sparse.realsize".to_string(),
+            real_size.to_string().into_bytes(),
+        ),
+    ]
+}
+
+/// Encodes the PAX 1.0 region map that precedes the data, padded to a block.
+///
+/// Like the GNU map, it ends with an empty region when the file ends in a
+/// hole; GNU tar sizes the extracted file from the map alone.
+pub fn pax_map(regions: &[Region], real_size: u64) -> Vec<u8> {
+    let regions = gnu_map(regions, real_size);
+    let mut map = format!("{}\n", regions.len()).into_bytes();
+    for &(offset, len) in &regions {
+        // Writing to a Vec cannot fail
+        let _ = write!(map, "{offset}\n{len}\n");
+    }
+    map.resize(map.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, 0);
+    map
+}
+
+/// Reads one decimal number and its trailing newline from a PAX 1.0 map.
+fn read_map_number<R: Read>(reader: &mut R, consumed: &mut usize) -> io::Result<u64> {
+    let mut digits = String::new();
+    let mut byte = [0u8; 1];
+    loop {
+        reader.read_exact(&mut byte)?;
+        *consumed += 1;
+        match byte[0] {
+            b'\n' => break,
+            b @ b'0'..=b'9' if digits.len() < 20 => digits.push(char::from(b)),
+            _ => return Err(io::Error::other("malformed sparse map")),
+        }
+    }
+    digits
+        .parse()
+        .map_err(|_| io::Error::other("malformed sparse map"))
+}
+
+/// Reads the PAX 1.0 region map from the start of a member's data, leaving
+/// the reader at the first data byte.
+pub fn read_pax_map<R: Read>(reader: &mut R) -> io::Result<Vec<Region>> {
+    let mut consumed = 0;
+    let count = read_map_number(reader, &mut consumed)?;
+    let mut regions = Vec::new();
+    for _ in 0..count {
+        let offset = read_map_number(reader, &mut consumed)?;
+        let len = read_map_number(reader, &mut consumed)?;
+        regions.push((offset, len));
+    }
+
+    // Skip the padding up to the next block
+    let padding = (BLOCK_SIZE - consumed % BLOCK_SIZE) % BLOCK_SIZE;
+    io::copy(&mut reader.take(padding as u64), &mut io::sink())?;
+    Ok(regions)
+}
+
+/// The real name and size of a PAX 1.0 sparse member.
+pub struct PaxSparse {
+    pub name: PathBuf,
+    pub real_size: u64,
+}
+
+/// Reads the `GNU.sparse.*` records of a tar member, if it is a PAX 1.0
+/// sparse file.
+pub fn pax_sparse<R: Read>(entry: &mut tar::Entry<'_, R>) -> io::Result<Option<PaxSparse>> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let Some(extensions) = entry.pax_extensions()? else {
+        return Ok(None);
+    };
+
+    let (mut major, mut name, mut real_size) = (None, None, None);
+    for extension in extensions {
+        let extension = extension?;
+        match extension.key_bytes() {
+            b"GNU.sparse.major" => major = extension.value().ok().map(str::to_string),
+            b"GNU.sparse.name" => {
+                name = Some(PathBuf::from(std::ffi::OsStr::from_bytes(
+                    extension.value_bytes(),
+                )));
+            }
+            b"GNU.sparse.realsize" => {
+                real_size = extension.value().ok().and_then(|v| v.parse().ok());
+            }
+            _ => {}
+        }
+    }
+
+    match (major.as_deref(), name, real_size) {
+        (Some("1"), Some(name), Some(real_size)) => Ok(Some(PaxSparse { name, real_size })),
+        _ => Ok(None),
+    }
+}
+
+/// Writes the data of a PAX 1.0 sparse member into `file`, leaving holes.
+pub fn unpack_pax<R: Read>(data: &mut R, real_size: u64, file: &mut File) -> io::Result<()> {
+    for (offset, len) in read_pax_map(data)? {
+        file.seek(io::SeekFrom::Start(offset))?;
+        let copied = io::copy(&mut data.take(len), file)?;
+        if copied != len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "sparse member is truncated",
+            ));
+        }
+    }
+    file.set_len(real_size)
+}
+
+/// Writes the full contents of a PAX 1.0 sparse member, holes as zeros.
+pub fn expand_pax<R: Read, W: Write>(data: &mut R, real_size: u64, out: &mut W) -> io::Result<()> {
+    let mut position = 0;
+    for (offset, len) in read_pax_map(data)? {
+        io::copy(
+            &mut io::repeat(0).take(offset.saturating_sub(position)),
+            out,
+        )?;
+        io::copy(&mut data.take(len), out)?;
+        position = offset.max(position) + len;
+    }
+    io::copy(
+        &mut io::repeat(0).take(real_size.saturating_sub(position)),
+        out,
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pax_map_round_trip() {
+        let regions = vec![(0, 4096), (1 << 20, 512), (1 << 30, 7)];
+        let mut encoded = pax_map(&regions, (1 << 30) + 7);
+        assert_eq!(encoded.len() % BLOCK_SIZE, 0);
+
+        encoded.extend_from_slice(b"data");
+        let mut reader = encoded.as_slice();
+        assert_eq!(read_pax_map(&mut reader).unwrap(), regions);
+        assert_eq!(reader, b"data");
+    }
+
+    #[test]
+    fn test_expand_pax_fills_holes() {
+        let mut member = pax_map(&[(2, 3)], 8);
+        member.extend_from_slice(b"abc");
+
+        let mut out = Vec::new();
+        expand_pax(&mut member.as_slice(), 8, &mut out).unwrap();
+        assert_eq!(out, b"\0\0abc\0\0\0");
+    }
+
+    #[test]
+    fn test_gnu_map_marks_trailing_hole() {
+        assert_eq!(gnu_map(&[(0, 512)], 4096), vec![(0, 512), (4096, 0)]);
+        assert_eq!(gnu_map(&[(512, 512)], 1024), vec![(512, 512)]);
+        assert_eq!(gnu_map(&[], 4096), vec![(4096, 0)]);
+    }
+
+    #[test]
+    fn test_gnu_extension_blocks() {
+        let regions: Vec<Region> = (0..30).map(|i| (i * 8192, 4096)).collect();
+        let mut header = tar::Header::new_gnu();
+        let blocks = gnu_sparse_header(&mut header, &regions, 30 * 8192).unwrap();
+
+        // 4 regions in the header, 26 more (+1 trailing hole) in two blocks
+        assert_eq!(blocks.len(), 2 * BLOCK_SIZE);
+        assert!(header.as_gnu().unwrap().is_extended());
+        assert_eq!(header.as_gnu().unwrap().real_size().unwrap(), 30 * 8192);
+    }
+}
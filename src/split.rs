@@ -0,0 +1,272 @@
+//! Splitting archive output into fixed-size volumes.
+//!
+//! With `--split-size`, the archive stream is cut into parts named
+//! `<output>.000`, `<output>.001`, ... Each part is a plain byte range of the
+//! archive, so `cat name.tar.zst.* > name.tar.zst` rebuilds the original.
+//! There are at most 1000 parts: a four-digit suffix would sort before the
+//! three-digit ones and the shell would join the parts out of order.
+//! A TOML manifest next to the parts records the size and SHA-256 of every
+//! part and of the whole archive, all hashed while the parts are written.
+
+use crate::checksum::to_hex;
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// One volume of a split archive.
+#[derive(Debug, Serialize)]
+pub struct Part {
+    /// File name of the part, relative to the manifest
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// Describes a split archive: the parts in order and the archive they form.
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+    /// File name of the archive the parts concatenate to
+    pub archive: String,
+    pub size: u64,
+    pub sha256: String,
+    #[serde(rename = "part")]
+    pub parts: Vec<Part>,
+}
+
+/// Most parts an archive may be split into, so every suffix has 3 digits.
+pub const MAX_PARTS: usize = 1000;
+
+/// The part currently being written.
+struct OpenPart {
+    writer: BufWriter<File>,
+    hasher: Sha256,
+    written: u64,
+}
+
+/// A writer that spreads its output over numbered part files.
+///
+/// Parts are created on demand, so no empty trailing part is left behind.
+/// Call [`SplitWriter::finish`] to close the last part and write the manifest.
+pub struct SplitWriter {
+    base: PathBuf,
+    part_size: u64,
+    current: Option<OpenPart>,
+    hasher: Sha256,
+    parts: Vec<Part>,
+}
+
+/// Returns the path of part `index` of the archive at `base`.
+pub fn part_path(base: &Path, index: usize) -> PathBuf {
+    let mut name = OsString::from(base.as_os_str());
+    name.push(format!(".{index:03}"));
+    PathBuf::from(name)
+}
+
+/// Returns the path of the manifest for the archive at `base`.
+pub fn manifest_path(base: &Path) -> PathBuf {
+    let mut name = OsString::from(base.as_os_str());
+    name.push(".parts.toml");
+    PathBuf::from(name)
+}
+
+/// Returns true if `path` is a part or the manifest of the archive at `base`.
+pub fn belongs_to(path: &Path, base: &Path) -> bool {
+    if path == manifest_path(base) {
+        return true;
+    }
+    if path.parent() != base.parent() {
+        return false;
+    }
+    let (Some(name), Some(base_name)) = (path.file_name(), base.file_name()) else {
+        return false;
+    };
+    let (name, base_name) = (name.to_string_lossy(), base_name.to_string_lossy());
+    name.strip_prefix(base_name.as_ref())
+        .and_then(|rest| rest.strip_prefix('.'))
+        .is_some_and(|index| index.len() >= 3 && index.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Returns the file name of `path` as a string for the manifest.
+fn display_name(path: &Path) -> String {
+    path.file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().to_string())
+}
+
+impl SplitWriter {
+    /// Creates a writer producing parts of at most `part_size` bytes.
+    pub fn new(base: &Path, part_size: u64) -> Self {
+        assert!(part_size > 0, "part size must be positive");
+        Self {
+            base: base.to_path_buf(),
+            part_size,
+            current: None,
+            hasher: Sha256::new(),
+            parts: Vec::new(),
+        }
+    }
+
+    /// Flushes and records the part being written, if any.
+    fn close_part(&mut self) -> io::Result<()> {
+        if let Some(mut part) = self.current.take() {
+            part.writer.flush()?;
+            let path = part_path(&self.base, self.parts.len());
+            self.parts.push(Part {
+                name: display_name(&path),
+                size: part.written,
+                sha256: to_hex(&part.hasher.finalize()),
+            });
+        }
+        Ok(())
+    }
+
+    /// Closes the last part and writes the manifest next to the parts.
+    pub fn finish(mut self) -> io::Result<Manifest> {
+        self.close_part()?;
+        let manifest = Manifest {
+            archive: display_name(&self.base),
+            size: self.parts.iter().map(|p| p.size).sum(),
+            sha256: to_hex(&self.hasher.finalize()),
+            parts: self.parts,
+        };
+        let toml = toml::to_string(&manifest).map_err(io::Error::other)?;
+        std::fs::write(manifest_path(&self.base), toml)?;
+        Ok(manifest)
+    }
+}
+
+impl Write for SplitWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self
+            .current
+            .as_ref()
+            .is_some_and(|part| part.written == self.part_size)
+        {
+            self.close_part()?;
+        }
+        if self.current.is_none() {
+            if self.parts.len() == MAX_PARTS {
+                return Err(io::Error::other(format!(
+                    "--split-size would need more than {MAX_PARTS} parts; use a larger size"
+                )));
+            }
+            let file = File::create(part_path(&self.base, self.parts.len()))?;
+            self.current = Some(OpenPart {
+                writer: BufWriter::new(file),
+                hasher: Sha256::new(),
+                written: 0,
+            });
+        }
+
+        let part = self.current.as_mut().expect("part was just opened");
+        let room = usize::try_from(self.part_size - part.written).unwrap_or(usize::MAX);
+        let len = part.writer.write(&buf[..buf.len().min(room)])?;
+        part.hasher.update(&buf[..len]);
+        part.written += len as u64;
+        self.hasher.update(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.current
+            .as_mut()
+            .map_or(Ok(()), |part| part.writer.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..=250u8).cycle().take(len).collect()
+    }
+
+    #[test]
+    fn test_parts_concatenate_to_original() {
+        let tmp = TempDir::new().unwrap();
+        let base = tmp.path().join("out.tar");
+        let data = sample(2500);
+
+        let mut writer = SplitWriter::new(&base, 1000);
+        for chunk in data.chunks(333) {
+            writer.write_all(chunk).unwrap();
+        }
+        let manifest = writer.finish().unwrap();
+
+        let sizes: Vec<u64> = manifest.parts.iter().map(|p| p.size).collect();
+        assert_eq!(sizes, [1000, 1000, 500]);
+        assert_eq!(manifest.size, 2500);
+
+        let mut joined = Vec::new();
+        for index in 0..3 {
+            joined.extend(std::fs::read(part_path(&base, index)).unwrap());
+        }
+        assert_eq!(joined, data);
+        assert!(!part_path(&base, 3).exists());
+        assert_eq!(manifest.sha256, to_hex(&Sha256::digest(&data)));
+    }
+
+    #[test]
+    fn test_exact_multiple_leaves_no_empty_part() {
+        let tmp = TempDir::new().unwrap();
+        let base = tmp.path().join("out.tar");
+
+        let mut writer = SplitWriter::new(&base, 100);
+        writer.write_all(&sample(200)).unwrap();
+        let manifest = writer.finish().unwrap();
+
+        assert_eq!(manifest.parts.len(), 2);
+        assert!(!part_path(&base, 2).exists());
+    }
+
+    #[test]
+    fn test_refuses_more_than_max_parts() {
+        let tmp = TempDir::new().unwrap();
+        let base = tmp.path().join("out.tar");
+
+        let mut writer = SplitWriter::new(&base, 1);
+        writer.write_all(&sample(MAX_PARTS)).unwrap();
+        assert_eq!(writer.finish().unwrap().parts.len(), MAX_PARTS);
+        assert!(part_path(&base, MAX_PARTS - 1).ends_with("out.tar.999"));
+
+        let mut writer = SplitWriter::new(&base, 1);
+        let err = writer.write_all(&sample(MAX_PARTS + 1)).unwrap_err();
+        assert!(err.to_string().contains("more than 1000 parts"));
+        assert!(!part_path(&base, MAX_PARTS).exists());
+    }
+
+    #[test]
+    fn test_manifest_lists_part_checksums() {
+        let tmp = TempDir::new().unwrap();
+        let base = tmp.path().join("out.tar");
+        let data = sample(150);
+
+        let mut writer = SplitWriter::new(&base, 100);
+        writer.write_all(&data).unwrap();
+        writer.finish().unwrap();
+
+        let manifest = std::fs::read_to_string(manifest_path(&base)).unwrap();
+        assert!(manifest.contains("archive = \"out.tar\""));
+        assert!(manifest.contains("name = \"out.tar.001\""));
+        assert!(manifest.contains(&to_hex(&Sha256::digest(&data[100..]))));
+    }
+
+    #[test]
+    fn test_belongs_to() {
+        let base = Path::new("/tmp/x/out.tar.zst");
+        assert!(belongs_to(Path::new("/tmp/x/out.tar.zst.000"), base));
+        assert!(belongs_to(Path::new("/tmp/x/out.tar.zst.1234"), base));
+        assert!(belongs_to(Path::new("/tmp/x/out.tar.zst.parts.toml"), base));
+        assert!(!belongs_to(Path::new("/tmp/x/out.tar.zst.bak"), base));
+        assert!(!belongs_to(Path::new("/tmp/x/out.tar.zst.00"), base));
+        assert!(!belongs_to(Path::new("/tmp/y/out.tar.zst.000"), base));
+    }
+}
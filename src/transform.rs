@@ -0,0 +1,316 @@
+//! Sed-like renaming of archive members (`--transform`).
+//!
+//! Each rule has the form `s<d>REGEX<d>REPLACEMENT<d>FLAGS`, where `<d>` is
+//! any delimiter character, as in `sed` and GNU tar. Rules from the config
+//! file run first, then those given on the command line, each one applied to
+//! the result of the previous. The regex uses Rust syntax (extended, like
+//! `sed -E`); the replacement understands `&` and `\1`..`\9`.
+//!
+//! Flags: `g` replaces every match instead of the first, `i` ignores case,
+//! and `S` leaves symlink targets alone (by default they are renamed too).
+
+use crate::walk::{EntryType, FileEntry};
+
+use anyhow::{Context, Result};
+use regex::bytes::{Regex, RegexBuilder};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+
+/// One parsed `s,REGEX,REPLACEMENT,FLAGS` rule.
+#[derive(Debug)]
+pub struct Transform {
+    regex: Regex,
+    /// Replacement in `regex` syntax (`${1}`, `$$`)
+    replacement: Vec<u8>,
+    global: bool,
+    /// Also rename symlink targets
+    symlinks: bool,
+}
+
+/// Splits `s` at unescaped occurrences of `delim`, turning `\<delim>` into
+/// a regex escape of the delimiter.
+fn split_fields(s: &str, delim: char) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        let field = fields.last_mut().expect("fields is never empty");
+        match c {
+            '\\' => match chars.next() {
+                Some(next) if next == delim => field.push_str(&regex::escape(&next.to_string())),
+                Some(next) => {
+                    field.push('\\');
+                    field.push(next);
+                }
+                None => field.push('\\'),
+            },
+            c if c == delim => fields.push(String::new()),
+            c => field.push(c),
+        }
+    }
+    fields
+}
+
+/// Converts a sed replacement (`&`, `\1`) into `regex` replacement syntax.
+fn convert_replacement(sed: &str) -> Vec<u8> {
+    let mut out = String::new();
+    let mut chars = sed.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => out.push_str("${0}"),
+            '$' => out.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(d @ '0'..='9') => {
+                    out.push_str("${");
+                    out.push(d);
+                    out.push('}');
+                }
+                Some('$') => out.push_str("$$"),
+                Some(other) => out.push(other),
+                None => out.push('\\'),
+            },
+            c => out.push(c),
+        }
+    }
+    out.into_bytes()
+}
+
+impl Transform {
+    /// Parses a rule such as `s,^dist/,,` or `s|\.prod\.|.|g`.
+    pub fn parse(expr: &str) -> Result<Self> {
+        let invalid = |reason: &str| anyhow::anyhow!("Invalid transform {expr:?}: {reason}");
+
+        let mut chars = expr.chars();
+        if chars.next() != Some('s') {
+            return Err(invalid("expected s<delim>REGEX<delim>REPLACEMENT<delim>"));
+        }
+        let delim = chars
+            .next()
+            .filter(|c| !c.is_alphanumeric() && *c != '\\')
+            .ok_or_else(|| invalid("missing delimiter after s"))?;
+
+        let fields = split_fields(chars.as_str(), delim);
+        let [pattern, replacement, flags] = fields.as_slice() else {
+            return Err(invalid(&format!("expected 3 fields separated by {delim}")));
+        };
+
+        let mut rule_flags = (false, false, true);
+        for flag in flags.chars() {
+            match flag {
+                'g' => rule_flags.0 = true,
+                'i' => rule_flags.1 = true,
+                'S' => rule_flags.2 = false,
+                's' => rule_flags.2 = true,
+                other => return Err(invalid(&format!("unknown flag {other}"))),
+            }
+        }
+        let (global, case_insensitive, symlinks) = rule_flags;
+
+        // Escaped delimiters were turned into regex escapes; undo that in
+        // the replacement, where they must be literal
+        let replacement =
+            replacement.replace(&regex::escape(&delim.to_string()), &delim.to_string());
+
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(case_insensitive)
+            .build()
+            .with_context(|| format!("Invalid regex in transform {expr:?}"))?;
+
+        Ok(Self {
+            regex,
+            replacement: convert_replacement(&replacement),
+            global,
+            symlinks,
+        })
+    }
+
+    /// Applies the rule to a name.
+    fn apply(&self, name: &[u8]) -> Vec<u8> {
+        let replacement = self.replacement.as_slice();
+        if self.global {
+            self.regex.replace_all(name, replacement).into_owned()
+        } else {
+            self.regex.replace(name, replacement).into_owned()
+        }
+    }
+}
+
+/// Parses every rule, reporting the first invalid one.
+pub fn parse_all<'a>(exprs: impl IntoIterator<Item = &'a str>) -> Result<Vec<Transform>> {
+    exprs.into_iter().map(Transform::parse).collect()
+}
+
+/// Runs `path` through the rules (only those with `symlinks` set when
+/// `for_symlink` is true).
+fn rename(rules: &[Transform], path: &Path, for_symlink: bool) -> PathBuf {
+    let mut name = path.as_os_str().as_bytes().to_vec();
+    for rule in rules.iter().filter(|r| r.symlinks || !for_symlink) {
+        name = rule.apply(&name);
+    }
+    PathBuf::from(OsStr::from_bytes(&name))
+}
+
+/// Cleans up a renamed member name: drops leading `/`, `.` components and
+/// empty components. Names that climb out with `..` are rejected.
+fn normalize(renamed: &Path, original: &Path) -> Result<PathBuf> {
+    let mut clean = PathBuf::new();
+    for component in renamed.components() {
+        match component {
+            Component::Normal(part) => clean.push(part),
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => anyhow::bail!(
+                "Transform renames {} to {}, which leaves the archive root",
+                original.display(),
+                renamed.display()
+            ),
+        }
+    }
+    Ok(clean)
+}
+
+/// Renames entries and symlink targets with the given rules.
+///
+/// Entries renamed to an empty name are dropped. Directories that end up
+/// with the same name are merged; any other collision is an error listing
+/// every clash.
+pub fn apply_transforms(
+    entries: &mut Vec<FileEntry>,
+    rules: &[Transform],
+    verbose: bool,
+) -> Result<()> {
+    if rules.is_empty() {
+        return Ok(());
+    }
+
+    let mut renamed = Vec::with_capacity(entries.len());
+    let mut seen: HashMap<PathBuf, (PathBuf, EntryType)> = HashMap::new();
+    let mut collisions = Vec::new();
+
+    for mut entry in entries.drain(..) {
+        let original = entry.relative_path.clone();
+        let name = normalize(&rename(rules, &original, false), &original)?;
+        if name.as_os_str().is_empty() {
+            if verbose {
+                eprintln!("Transform drops {} (empty name)", original.display());
+            }
+            continue;
+        }
+
+        match seen.entry(name.clone()) {
+            Entry::Occupied(first) => {
+                let (first_path, first_type) = first.get();
+                if *first_type == EntryType::Directory && entry.entry_type == EntryType::Directory {
+                    continue;
+                }
+                collisions.push(format!(
+                    "  {} and {} both become {}",
+                    first_path.display(),
+                    original.display(),
+                    name.display()
+                ));
+                continue;
+            }
+            Entry::Vacant(slot) => {
+                slot.insert((original, entry.entry_type));
+            }
+        }
+
+        if let Some(ref target) = entry.link_target {
+            if entry.entry_type == EntryType::Symlink {
+                entry.link_target = Some(rename(rules, target, true));
+            }
+        }
+        entry.relative_path = name;
+        renamed.push(entry);
+    }
+
+    if !collisions.is_empty() {
+        anyhow::bail!(
+            "Transform rules map several entries to the same name:\n{}",
+            collisions.join("\n")
+        );
+    }
+
+    *entries = renamed;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(expr: &str, name: &str) -> String {
+        let rules = [Transform::parse(expr).unwrap()];
+        rename(&rules, Path::new(name), false)
+            .to_string_lossy()
+            .to_string()
+    }
+
+    #[test]
+    fn test_strip_leading_directory() {
+        assert_eq!(apply("s,^dist/,,", "dist/app.js"), "app.js");
+        assert_eq!(apply("s,^dist/,,", "src/dist/app.js"), "src/dist/app.js");
+    }
+
+    #[test]
+    fn test_rename_file() {
+        assert_eq!(
+            apply(r"s,^config\.prod\.toml$,config.toml,", "config.prod.toml"),
+            "config.toml"
+        );
+    }
+
+    #[test]
+    fn test_global_and_case_flags() {
+        assert_eq!(apply("s/a/b/", "aaa"), "baa");
+        assert_eq!(apply("s/a/b/g", "aaa"), "bbb");
+        assert_eq!(apply("s/A/b/gi", "aAa"), "bbb");
+    }
+
+    #[test]
+    fn test_backreferences_and_ampersand() {
+        assert_eq!(apply(r"s,(.*)\.txt,\1.md,", "notes.txt"), "notes.md");
+        assert_eq!(apply("s,^,old-&,", "x"), "old-x");
+        assert_eq!(apply("s,x,&&,", "x"), "xx");
+        assert_eq!(apply("s,x,$1,", "x"), "$1");
+    }
+
+    #[test]
+    fn test_escaped_delimiter() {
+        assert_eq!(apply(r"s,a\,b,c\,d,", "a,b"), "c,d");
+        assert_eq!(apply(r"s|^lib/|vendor/|", "lib/x"), "vendor/x");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Transform::parse("y,a,b,").is_err());
+        assert!(Transform::parse("s,a,b").is_err());
+        assert!(Transform::parse("s,a,b,q").is_err());
+        assert!(Transform::parse("s,(,b,").is_err());
+    }
+
+    #[test]
+    fn test_normalize_rejects_parent() {
+        let original = Path::new("a");
+        assert_eq!(
+            normalize(Path::new("/x//./y"), original).unwrap(),
+            PathBuf::from("x/y")
+        );
+        assert!(normalize(Path::new("../x"), original).is_err());
+    }
+
+    #[test]
+    fn test_symlink_flag() {
+        let rules = [Transform::parse("s,^dist/,,S").unwrap()];
+        assert_eq!(
+            rename(&rules, Path::new("dist/a"), true),
+            PathBuf::from("dist/a")
+        );
+        assert_eq!(
+            rename(&rules, Path::new("dist/a"), false),
+            PathBuf::from("a")
+        );
+    }
+}
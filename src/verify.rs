@@ -0,0 +1,472 @@
+//! Checking an archive against the tree it was built from (`raptar verify`).
+//!
+//! The tree is scanned with the same rules as when archiving, and every
+//! entry is compared with the member raptar would have written for it: its
+//! type, mode, mtime, owner (tar), link target and contents. File contents
+//! are compared by BLAKE3 digest, so nothing is held in memory.
+
+use crate::archive::{self, normalized_mode};
+use crate::checksum::Hasher;
+use crate::encrypt::Keys;
+use crate::incremental;
+use crate::list::{self, normalize};
+use crate::owner::Ownership;
+use crate::reader::{self, ArchiveReader};
+use crate::sparse;
+use crate::walk::{EntryType, FileEntry};
+use crate::{ChecksumAlgorithm, Format, TarFormat, ZipSymlinks};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// How the archive was written, as far as the comparison needs to know.
+pub struct VerifyOptions {
+    pub normalize_modes: bool,
+    /// Owners stored in tar headers
+    pub owners: Ownership,
+    pub tar_format: TarFormat,
+    pub zip_symlinks: ZipSymlinks,
+}
+
+/// One way a member differs from the tree.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Difference {
+    /// In the tree but not in the archive
+    Missing,
+    /// In the archive but not in the tree
+    Extra,
+    /// File data or symlink target differs
+    Content,
+    /// Type, mode, mtime, owner or hard link differs
+    Metadata(Vec<String>),
+}
+
+impl Difference {
+    pub const fn label(&self) -> &'static str {
+        match self {
+            Self::Missing => "missing",
+            Self::Extra => "extra",
+            Self::Content => "content",
+            Self::Metadata(_) => "metadata",
+        }
+    }
+}
+
+/// The outcome of a verification.
+pub struct Report {
+    /// Members compared
+    pub checked: usize,
+    /// Differences, sorted by path
+    pub differences: Vec<(PathBuf, Difference)>,
+}
+
+/// Hashes and counts the bytes written to it.
+struct DigestWriter {
+    hasher: Hasher,
+    len: u64,
+}
+
+impl DigestWriter {
+    fn new() -> Self {
+        Self {
+            hasher: Hasher::new(ChecksumAlgorithm::Blake3),
+            len: 0,
+        }
+    }
+
+    fn finish(self) -> (String, u64) {
+        (self.hasher.finish(), self.len)
+    }
+}
+
+impl Write for DigestWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.hasher.update(buf);
+        self.len += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns the digest and length of everything `data` yields.
+fn digest(mut data: impl Read) -> io::Result<(String, u64)> {
+    let mut writer = DigestWriter::new();
+    io::copy(&mut data, &mut writer)?;
+    Ok(writer.finish())
+}
+
+/// A member as read from the archive.
+struct Member {
+    entry: FileEntry,
+    /// Name of the member a hard link points to
+    hard_link: Option<PathBuf>,
+    /// Digest and length of a regular file's data
+    data: Option<(String, u64)>,
+}
+
+/// Reads every member of a tar archive, hashing file data.
+fn read_tar(mut archive: reader::TarReader) -> Result<HashMap<PathBuf, Member>> {
+    let mut members = HashMap::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let kind = entry.header().entry_type();
+        let hard_link = if kind == tar::EntryType::Link {
+            entry.link_name()?.map(|name| normalize(&name))
+        } else {
+            None
+        };
+        let sparse = sparse::pax_sparse(&mut entry)?;
+        let info = list::tar_entry(&mut entry)?;
+
+        let data = if hard_link.is_some() || info.entry_type != EntryType::File {
+            None
+        } else if let Some(sparse) = sparse {
+            let mut writer = DigestWriter::new();
+            sparse::expand_pax(&mut entry, sparse.real_size, &mut writer)?;
+            Some(writer.finish())
+        } else {
+            Some(digest(&mut entry)?)
+        };
+
+        members.insert(
+            info.relative_path.clone(),
+            Member {
+                entry: info,
+                hard_link,
+                data,
+            },
+        );
+    }
+    Ok(members)
+}
+
+/// Reads every member of a zip archive, hashing file data.
+fn read_zip(mut archive: reader::ZipReader, keys: &Keys) -> Result<HashMap<PathBuf, Member>> {
+    let mut members = HashMap::new();
+    for i in 0..archive.len() {
+        let mut file = reader::zip_member(&mut archive, i, keys)?;
+        let info = list::zip_entry(&mut file)?;
+        let data = if info.entry_type == EntryType::File {
+            Some(digest(&mut file)?)
+        } else {
+            None
+        };
+        members.insert(
+            info.relative_path.clone(),
+            Member {
+                entry: info,
+                hard_link: None,
+                data,
+            },
+        );
+    }
+    Ok(members)
+}
+
+/// The member raptar writes for a tree entry.
+struct Expected<'a> {
+    entry: &'a FileEntry,
+    entry_type: EntryType,
+    /// Permission bits
+    mode: u32,
+    /// File holding the member's data
+    source: Option<&'a Path>,
+    hard_link: Option<PathBuf>,
+    /// uid and gid (tar only)
+    owner: Option<(u32, u32)>,
+}
+
+/// Works out the tar member for each entry; `None` for entries tar skips.
+fn expect_tar<'a>(entries: &'a [FileEntry], options: &VerifyOptions) -> Vec<Option<Expected<'a>>> {
+    let mut first_links: HashMap<(u64, u64), PathBuf> = HashMap::new();
+    entries
+        .iter()
+        .map(|entry| {
+            let skipped = match entry.entry_type {
+                EntryType::Socket => true,
+                EntryType::Fifo | EntryType::CharDevice | EntryType::BlockDevice => {
+                    options.tar_format == TarFormat::V7
+                }
+                EntryType::Symlink => entry.link_target.is_none(),
+                _ => false,
+            };
+            if skipped {
+                return None;
+            }
+
+            let name = normalize(&entry.relative_path);
+            let hard_link = match entry.hard_link_key {
+                Some(key) if entry.entry_type == EntryType::File => match first_links.entry(key) {
+                    Entry::Occupied(first) => Some(first.get().clone()),
+                    Entry::Vacant(slot) => {
+                        slot.insert(name);
+                        None
+                    }
+                },
+                _ => None,
+            };
+            let owner = options.owners.resolve(entry.uid, entry.gid);
+            let mode = if options.normalize_modes {
+                normalized_mode(entry.entry_type, entry.mode)
+            } else {
+                entry.mode & 0o7777
+            };
+            Some(Expected {
+                entry,
+                entry_type: entry.entry_type,
+                mode,
+                source: (entry.entry_type == EntryType::File && hard_link.is_none())
+                    .then_some(entry.path.as_path()),
+                hard_link,
+                owner: Some((owner.uid, owner.gid)),
+            })
+        })
+        .collect()
+}
+
+/// Works out the zip member for each entry; `None` for entries zip skips.
+fn expect_zip<'a>(entries: &'a [FileEntry], options: &VerifyOptions) -> Vec<Option<Expected<'a>>> {
+    let permissions = |entry_type: EntryType, mode: u32| {
+        if options.normalize_modes {
+            normalized_mode(entry_type, mode)
+        } else {
+            mode & 0o7777
+        }
+    };
+    entries
+        .iter()
+        .map(|entry| {
+            let (entry_type, mode) = match entry.entry_type {
+                EntryType::File | EntryType::Directory => {
+                    (entry.entry_type, permissions(entry.entry_type, entry.mode))
+                }
+                EntryType::Symlink => match options.zip_symlinks {
+                    ZipSymlinks::Link if entry.link_target.is_some() => (EntryType::Symlink, 0o777),
+                    ZipSymlinks::Follow => match fs::metadata(&entry.path) {
+                        Ok(meta) if meta.is_file() => {
+                            (EntryType::File, permissions(EntryType::File, meta.mode()))
+                        }
+                        _ => return None,
+                    },
+                    _ => return None,
+                },
+                _ => return None,
+            };
+            Some(Expected {
+                entry,
+                entry_type,
+                mode,
+                source: (entry_type == EntryType::File).then_some(entry.path.as_path()),
+                hard_link: None,
+                owner: None,
+            })
+        })
+        .collect()
+}
+
+/// Rounds an mtime the way zip stores it.
+fn zip_mtime(mtime: u64) -> u64 {
+    reader::zip_timestamp(archive::zip_datetime(mtime))
+        .and_then(|t| u64::try_from(t).ok())
+        .unwrap_or(0)
+}
+
+/// Compares one member with its expected form.
+fn compare(expected: &Expected<'_>, member: &Member, zip: bool) -> Result<Option<Difference>> {
+    let (want, got) = (expected.entry, &member.entry);
+    let mut metadata = Vec::new();
+
+    if expected.hard_link.is_some() || member.hard_link.is_some() {
+        if expected.hard_link != member.hard_link {
+            let show = |link: Option<&PathBuf>| {
+                link.map_or_else(|| "none".to_string(), |l| l.display().to_string())
+            };
+            metadata.push(format!(
+                "hard link to {}, expected {}",
+                show(member.hard_link.as_ref()),
+                show(expected.hard_link.as_ref())
+            ));
+        }
+    } else if got.entry_type != expected.entry_type {
+        return Ok(Some(Difference::Metadata(vec![format!(
+            "{} in archive, {} in tree",
+            got.entry_type.name(),
+            expected.entry_type.name()
+        )])));
+    }
+
+    if got.mode & 0o7777 != expected.mode {
+        metadata.push(format!(
+            "mode {:04o}, expected {:04o}",
+            got.mode & 0o7777,
+            expected.mode
+        ));
+    }
+    let mtime = if zip {
+        zip_mtime(want.mtime)
+    } else {
+        want.mtime
+    };
+    if got.mtime != mtime {
+        metadata.push(format!("mtime {}, expected {mtime}", got.mtime));
+    }
+    if let Some((uid, gid)) = expected.owner {
+        if (got.uid, got.gid) != (uid, gid) {
+            metadata.push(format!(
+                "owner {}:{}, expected {uid}:{gid}",
+                got.uid, got.gid
+            ));
+        }
+    }
+    if !zip && got.device != want.device {
+        metadata.push("device numbers differ".to_string());
+    }
+
+    let content_differs = match expected.source {
+        Some(path) => {
+            let file =
+                File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+            let on_disk =
+                digest(file).with_context(|| format!("Failed to read {}", path.display()))?;
+            member.data.as_ref() != Some(&on_disk)
+        }
+        None => expected.entry_type == EntryType::Symlink && got.link_target != want.link_target,
+    };
+
+    Ok(if content_differs {
+        Some(Difference::Content)
+    } else if metadata.is_empty() {
+        None
+    } else {
+        Some(Difference::Metadata(metadata))
+    })
+}
+
+/// Compares an archive with the entries collected from the tree.
+pub fn verify_archive(
+    path: &Path,
+    format: Option<Format>,
+    keys: &Keys,
+    entries: &[FileEntry],
+    options: &VerifyOptions,
+) -> Result<Report> {
+    let (mut members, expected, zip) = match reader::open(path, format, keys)? {
+        ArchiveReader::Tar(archive) => (read_tar(archive)?, expect_tar(entries, options), false),
+        ArchiveReader::Zip(archive) => {
+            (read_zip(archive, keys)?, expect_zip(entries, options), true)
+        }
+    };
+
+    let mut report = Report {
+        checked: 0,
+        differences: Vec::new(),
+    };
+    for expected in expected.iter().flatten() {
+        let name = normalize(&expected.entry.relative_path);
+        let Some(member) = members.remove(&name) else {
+            report.differences.push((name, Difference::Missing));
+            continue;
+        };
+        report.checked += 1;
+        if let Some(difference) = compare(expected, &member, zip)? {
+            report.differences.push((name, difference));
+        }
+    }
+
+    // The sums member of --embed-checksums and the deletion list of an
+    // increment have no counterpart in the tree
+    let generated: Vec<PathBuf> = ChecksumAlgorithm::value_variants()
+        .iter()
+        .map(|alg| PathBuf::from(alg.sums_name()))
+        .chain([PathBuf::from(incremental::DELETED_NAME)])
+        .collect();
+    report.differences.extend(
+        members
+            .into_keys()
+            .filter(|name| !generated.contains(name))
+            .map(|name| (name, Difference::Extra)),
+    );
+    report.differences.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, entry_type: EntryType, hard_link_key: Option<(u64, u64)>) -> FileEntry {
+        FileEntry {
+            path: PathBuf::from("/src").join(name),
+            relative_path: PathBuf::from(name),
+            size: 0,
+            entry_type,
+            link_target: None,
+            mode: 0o100_664,
+            uid: 1000,
+            gid: 1000,
+            uname: None,
+            gname: None,
+            mtime: 1_700_000_001,
+            mtime_nsec: 0,
+            hard_link_key,
+            xattrs: Vec::new(),
+            device: None,
+            sparse: None,
+            disk: None,
+        }
+    }
+
+    fn options(tar_format: TarFormat) -> VerifyOptions {
+        VerifyOptions {
+            normalize_modes: false,
+            owners: Ownership::default(),
+            tar_format,
+            zip_symlinks: ZipSymlinks::Link,
+        }
+    }
+
+    #[test]
+    fn test_expect_tar_links_later_names_to_first() {
+        let entries = [
+            entry("a", EntryType::File, Some((1, 7))),
+            entry("b", EntryType::File, Some((1, 7))),
+            entry("sock", EntryType::Socket, None),
+        ];
+        let expected = expect_tar(&entries, &options(TarFormat::Gnu));
+
+        let first = expected[0].as_ref().unwrap();
+        assert_eq!(first.hard_link, None);
+        assert_eq!(first.source, Some(Path::new("/src/a")));
+        assert_eq!(first.mode, 0o664);
+        assert_eq!(first.owner, Some((0, 0)));
+
+        let second = expected[1].as_ref().unwrap();
+        assert_eq!(second.hard_link, Some(PathBuf::from("a")));
+        assert_eq!(second.source, None);
+        assert!(expected[2].is_none());
+    }
+
+    #[test]
+    fn test_expect_v7_skips_special_files() {
+        let entries = [entry("fifo", EntryType::Fifo, None)];
+        assert!(expect_tar(&entries, &options(TarFormat::V7))[0].is_none());
+        assert!(expect_tar(&entries, &options(TarFormat::Gnu))[0].is_some());
+        assert!(expect_zip(&entries, &options(TarFormat::Gnu))[0].is_none());
+    }
+
+    #[test]
+    fn test_zip_mtime_rounds_to_two_seconds() {
+        let mtime = zip_mtime(1_700_000_001);
+        assert_eq!(mtime % 2, 0);
+        assert!(1_700_000_001 - mtime < 2);
+    }
+}
@@ -1,15 +1,22 @@
 //! Directory walking with rule-based filtering using indexed lookups.
 
 use crate::config::{find_ignore_files, Config};
+use crate::mtime::{self, MtimePolicy};
 use crate::rules::{parse_ignore_file, print_rules_verbose, Action, RuleIndex, RuleOrigin};
+use crate::sparse::{self, Region};
+use crate::transform;
 use crate::Args;
 
 use anyhow::{Context, Result};
 use colored::Colorize;
+use std::collections::HashSet;
+use std::fmt::Write as _;
 use std::fs::{self, Metadata};
-use std::os::unix::fs::{MetadataExt, PermissionsExt};
-use std::path::{Path, PathBuf};
+use std::io;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
 use std::time::UNIX_EPOCH;
+use time::OffsetDateTime;
 
 /// A file entry to be archived.
 #[derive(Debug, Clone)]
@@ -18,11 +25,62 @@ pub struct FileEntry {
     pub relative_path: PathBuf,
     pub size: u64,
     pub entry_type: EntryType,
+    /// Target of a symlink, or the member a listed hard link points to
     pub link_target: Option<PathBuf>,
     pub mode: u32,
     pub uid: u32,
     pub gid: u32,
+    /// Owner names stored in a listed tar header
+    pub uname: Option<String>,
+    pub gname: Option<String>,
     pub mtime: u64,
+    /// Nanoseconds past `mtime`, stored by the pax format
+    pub mtime_nsec: u32,
+    /// `(dev, ino)` for regular files with more than one link
+    pub hard_link_key: Option<(u64, u64)>,
+    /// Extended attributes (name, value), read only with `--xattrs`
+    pub xattrs: Vec<(String, Vec<u8>)>,
+    /// `(major, minor)` for character and block devices
+    pub device: Option<(u32, u32)>,
+    /// Data regions of a file with holes, mapped only with `--sparse`
+    pub sparse: Option<Vec<Region>>,
+    /// State on disk; `None` for members that were not read from a tree
+    pub disk: Option<DiskState>,
+}
+
+/// A file's state on disk, before `--mtime` or reproducible mode rewrite its
+/// timestamp. This is what `--listed-incremental` compares between runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskState {
+    pub size: u64,
+    pub mtime: i64,
+    pub mtime_nsec: i64,
+    /// Status change time, which moves when a file is rewritten even if its
+    /// mtime is put back afterwards
+    pub ctime: i64,
+    pub ctime_nsec: i64,
+    pub inode: u64,
+}
+
+impl DiskState {
+    pub fn of(metadata: &Metadata) -> Self {
+        Self {
+            size: metadata.len(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+            ctime: metadata.ctime(),
+            ctime_nsec: metadata.ctime_nsec(),
+            inode: metadata.ino(),
+        }
+    }
+}
+
+impl FileEntry {
+    /// Returns the bytes of content actually stored: the data regions of a
+    /// sparse file, or the full size otherwise.
+    pub fn stored_size(&self) -> u64 {
+        self.sparse.as_deref().map_or(self.size, sparse::data_size)
+    }
 }
 
 /// Type of file entry.
@@ -31,6 +89,33 @@ pub enum EntryType {
     File,
     Directory,
     Symlink,
+    Fifo,
+    CharDevice,
+    BlockDevice,
+    Socket,
+}
+
+impl EntryType {
+    /// Returns true for FIFOs, devices and sockets, which have no content.
+    pub const fn is_special(self) -> bool {
+        matches!(
+            self,
+            Self::Fifo | Self::CharDevice | Self::BlockDevice | Self::Socket
+        )
+    }
+
+    /// Returns a short name for messages.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::File => "file",
+            Self::Directory => "directory",
+            Self::Symlink => "symlink",
+            Self::Fifo => "FIFO",
+            Self::CharDevice => "character device",
+            Self::BlockDevice => "block device",
+            Self::Socket => "socket",
+        }
+    }
 }
 
 /// A file that was excluded from the archive.
@@ -182,43 +267,141 @@ pub fn build_rule_index(args: &Args, config: &Config, root: &Path) -> RuleIndex
     index
 }
 
+/// Reads the extended attributes of `path`, following a final symlink only
+/// when `follow` is set (i.e., for dereferenced entries).
+///
+/// Filesystems without xattr support yield an empty list. Names that are not
+/// valid UTF-8 cannot be stored in PAX records and are skipped with a warning.
+pub fn read_xattrs(path: &Path, follow: bool) -> Result<Vec<(String, Vec<u8>)>> {
+    let names = if follow {
+        xattr::list_deref(path)
+    } else {
+        xattr::list(path)
+    };
+    let names = match names {
+        Ok(names) => names,
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to list xattrs: {}", path.display()));
+        }
+    };
+
+    let mut xattrs = Vec::new();
+    for name in names {
+        let Some(key) = name.to_str() else {
+            eprintln!(
+                "{} Skipping non-UTF-8 xattr {} on {}",
+                "⚠".yellow(),
+                name.to_string_lossy(),
+                path.display()
+            );
+            continue;
+        };
+        let value = if follow {
+            xattr::get_deref(path, &name)
+        } else {
+            xattr::get(path, &name)
+        };
+        if let Some(value) =
+            value.with_context(|| format!("Failed to read xattr {key}: {}", path.display()))?
+        {
+            xattrs.push((key.to_string(), value));
+        }
+    }
+    xattrs.sort();
+    Ok(xattrs)
+}
+
+/// Splits a device number into its major and minor numbers.
+#[cfg(target_os = "macos")]
+#[allow(clippy::cast_possible_truncation)]
+const fn device_numbers(rdev: u64) -> (u32, u32) {
+    (((rdev >> 24) & 0xff) as u32, (rdev & 0xff_ffff) as u32)
+}
+
+/// Splits a device number into its major and minor numbers (glibc encoding).
+#[cfg(not(target_os = "macos"))]
+#[allow(clippy::cast_possible_truncation)]
+const fn device_numbers(rdev: u64) -> (u32, u32) {
+    let major = ((rdev >> 8) & 0xfff) | ((rdev >> 32) & !0xfff);
+    let minor = (rdev & 0xff) | ((rdev >> 12) & !0xff);
+    (major as u32, minor as u32)
+}
+
+/// Per-walk settings that affect how entries are read.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WalkOptions {
+    /// Follow symlinks
+    pub dereference: bool,
+    /// Rewrites modification times (`--mtime`, reproducible mode)
+    pub mtime: Option<MtimePolicy>,
+    /// Read extended attributes
+    pub xattrs: bool,
+    /// Map the holes of sparse files
+    pub sparse: bool,
+}
+
 /// Creates a file entry from path and metadata.
 pub fn create_file_entry(
     path: &Path,
     relative_path: PathBuf,
     metadata: &Metadata,
-    reproducible: bool,
+    options: WalkOptions,
 ) -> Result<FileEntry> {
-    let entry_type = if metadata.file_type().is_symlink() {
+    let file_type = metadata.file_type();
+    let entry_type = if file_type.is_symlink() {
         EntryType::Symlink
-    } else if metadata.is_dir() {
+    } else if file_type.is_dir() {
         EntryType::Directory
+    } else if file_type.is_fifo() {
+        EntryType::Fifo
+    } else if file_type.is_char_device() {
+        EntryType::CharDevice
+    } else if file_type.is_block_device() {
+        EntryType::BlockDevice
+    } else if file_type.is_socket() {
+        EntryType::Socket
     } else {
         EntryType::File
     };
 
+    let device = matches!(entry_type, EntryType::CharDevice | EntryType::BlockDevice)
+        .then(|| device_numbers(metadata.rdev()));
+
     let link_target = if entry_type == EntryType::Symlink {
         Some(fs::read_link(path).context("Failed to read symlink target")?)
     } else {
         None
     };
 
-    let size = if entry_type == EntryType::Symlink {
-        0 // Symlinks don't have content size
-    } else {
+    let size = if entry_type == EntryType::File {
         metadata.len()
+    } else {
+        0 // Symlinks, directories and special files don't have content size
     };
 
-    let mtime = if reproducible {
-        0
+    let hard_link_key = if entry_type == EntryType::File && metadata.nlink() > 1 {
+        Some((metadata.dev(), metadata.ino()))
     } else {
-        metadata
-            .modified()
-            .ok()
-            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
-            .map_or(0, |d| d.as_secs())
+        None
+    };
+
+    let sparse = if options.sparse && entry_type == EntryType::File {
+        sparse::data_regions(path, metadata)
+            .with_context(|| format!("Failed to map holes: {}", path.display()))?
+    } else {
+        None
     };
 
+    let (mtime, mtime_nsec) = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or((0, 0), |d| (d.as_secs(), d.subsec_nanos()));
+    let (mtime, mtime_nsec) = options.mtime.map_or((mtime, mtime_nsec), |policy| {
+        policy.apply(mtime, mtime_nsec)
+    });
+
     Ok(FileEntry {
         path: path.to_path_buf(),
         relative_path,
@@ -228,19 +411,64 @@ pub fn create_file_entry(
         mode: metadata.permissions().mode(),
         uid: metadata.uid(),
         gid: metadata.gid(),
+        uname: None,
+        gname: None,
         mtime,
+        mtime_nsec,
+        hard_link_key,
+        xattrs: if options.xattrs {
+            read_xattrs(path, entry_type != EntryType::Symlink)?
+        } else {
+            Vec::new()
+        },
+        device,
+        sparse,
+        disk: Some(DiskState::of(metadata)),
     })
 }
 
+/// Removes the directory entry at `dir_index` if the directory had children
+/// on disk but every one of them was excluded (e.g., `target/` or `.git/**`).
+///
+/// Directories that are empty on disk are kept.
+fn drop_if_emptied(results: &mut WalkResults, dir_index: usize, children: usize) {
+    if children > 0 && results.entries.len() == dir_index + 1 {
+        results.entries.pop();
+    }
+}
+
+/// Adds `path` to the results, recursing into it if it is a directory.
+fn push_entry(
+    path: &Path,
+    relative: &Path,
+    metadata: &Metadata,
+    root: &Path,
+    index: &mut RuleIndex,
+    options: WalkOptions,
+    results: &mut WalkResults,
+) -> Result<()> {
+    let entry = create_file_entry(path, relative.to_path_buf(), metadata, options)?;
+    let is_dir = entry.entry_type == EntryType::Directory;
+    let dir_index = results.entries.len();
+    results.entries.push(entry);
+
+    if is_dir {
+        let children = walk_directory(path, root, index, options, results)?;
+        drop_if_emptied(results, dir_index, children);
+    }
+    Ok(())
+}
+
 /// Recursively walks a directory, using indexed rule lookups.
+///
+/// Returns the number of children found on disk, archived or not.
 fn walk_directory(
     dir: &Path,
     root: &Path,
     index: &mut RuleIndex,
-    dereference: bool,
-    reproducible: bool,
+    options: WalkOptions,
     results: &mut WalkResults,
-) -> Result<()> {
+) -> Result<usize> {
     let mut entries: Vec<_> = fs::read_dir(dir)
         .with_context(|| format!("Failed to read directory: {}", dir.display()))?
         .filter_map(Result::ok)
@@ -248,6 +476,7 @@ fn walk_directory(
 
     // Sort for deterministic ordering
     entries.sort_by_key(std::fs::DirEntry::path);
+    let children = entries.len();
 
     for entry in entries {
         let path = entry.path();
@@ -270,7 +499,7 @@ fn walk_directory(
             }
         }
 
-        let metadata = if dereference {
+        let metadata = if options.dereference {
             path.metadata()
         } else {
             path.symlink_metadata()
@@ -280,60 +509,142 @@ fn walk_directory(
         let is_dir = metadata.is_dir() && !metadata.file_type().is_symlink();
 
         // Check rules using the index (uses absolute path)
-        if let Some((action, origin)) = index.find_match(&path) {
-            match action {
-                Action::Exclude => {
-                    results.excluded.push(ExcludedFile {
-                        path: relative.to_path_buf(),
-                        origin,
-                    });
-
-                    // But check if we need to recurse anyway for nested includes
-                    if is_dir && index.has_include_rules(&path) {
-                        walk_directory(&path, root, index, dereference, reproducible, results)?;
-                    }
-                    continue;
-                }
-                Action::Include => {
-                    // Explicitly included
-                    if is_dir {
-                        walk_directory(&path, root, index, dereference, reproducible, results)?;
-                    } else {
-                        let file_entry = create_file_entry(
-                            &path,
-                            relative.to_path_buf(),
-                            &metadata,
-                            reproducible,
-                        )?;
-                        results.entries.push(file_entry);
-                    }
-                    continue;
-                }
+        if let Some((Action::Exclude, origin)) = index.find_match(&path) {
+            results.excluded.push(ExcludedFile {
+                path: relative.to_path_buf(),
+                origin,
+            });
+
+            // But check if we need to recurse anyway for nested includes
+            if is_dir && index.has_include_rules(&path) {
+                walk_directory(&path, root, index, options, results)?;
             }
+            continue;
         }
 
-        // No rule matched - include by default
-        if is_dir {
-            walk_directory(&path, root, index, dereference, reproducible, results)?;
-        } else {
-            let file_entry =
-                create_file_entry(&path, relative.to_path_buf(), &metadata, reproducible)?;
-            results.entries.push(file_entry);
+        // Explicitly included, or no rule matched - include by default
+        push_entry(&path, relative, &metadata, root, index, options, results)?;
+    }
+
+    Ok(children)
+}
+
+/// Drops directory entries that have no files or symlinks beneath them.
+pub fn remove_empty_dirs(entries: &mut Vec<FileEntry>) {
+    let non_empty: HashSet<PathBuf> = entries
+        .iter()
+        .filter(|e| e.entry_type != EntryType::Directory)
+        .flat_map(|e| e.relative_path.ancestors().skip(1))
+        .map(Path::to_path_buf)
+        .collect();
+
+    entries
+        .retain(|e| e.entry_type != EntryType::Directory || non_empty.contains(&e.relative_path));
+}
+
+/// Expands the placeholders in a `--prefix` template and checks the result.
+///
+/// `{dirname}` is the name of the archived directory and `{date}` the UTC
+/// date (YYYY-MM-DD) of the mtime policy's time, so that reproducible and
+/// incremental runs keep their names; today's date without a policy.
+/// Returns `None` for an empty prefix.
+pub fn expand_prefix(
+    template: &str,
+    root: &Path,
+    mtime: Option<MtimePolicy>,
+) -> Result<Option<PathBuf>> {
+    let mut expanded = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .with_context(|| format!("Unclosed placeholder in prefix: {template}"))?;
+        match &rest[start + 1..start + end] {
+            "dirname" => expanded.push_str(
+                &root
+                    .file_name()
+                    .map_or_else(|| "archive".into(), |n| n.to_string_lossy()),
+            ),
+            "date" => {
+                let date = mtime
+                    .and_then(|policy| i64::try_from(policy.time()).ok())
+                    .and_then(|time| OffsetDateTime::from_unix_timestamp(time).ok())
+                    .unwrap_or_else(OffsetDateTime::now_utc)
+                    .date();
+                let _ = write!(
+                    expanded,
+                    "{:04}-{:02}-{:02}",
+                    date.year(),
+                    u8::from(date.month()),
+                    date.day()
+                );
+            }
+            other => anyhow::bail!(
+                "Unknown placeholder {{{other}}} in prefix (expected {{dirname}} or {{date}})"
+            ),
         }
+        rest = &rest[start + end + 1..];
     }
+    expanded.push_str(rest);
 
+    let prefix = PathBuf::from(expanded.trim_end_matches('/'));
+    if prefix.as_os_str().is_empty() {
+        return Ok(None);
+    }
+    if !prefix
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+    {
+        anyhow::bail!(
+            "Prefix must be a relative path without . or .. components: {}",
+            prefix.display()
+        );
+    }
+    Ok(Some(prefix))
+}
+
+/// Moves every entry under `prefix`, adding directory entries for the
+/// prefix itself that take their metadata from the archived directory.
+fn apply_prefix(
+    entries: &mut Vec<FileEntry>,
+    prefix: &Path,
+    root: &Path,
+    options: WalkOptions,
+) -> Result<()> {
+    for entry in entries.iter_mut() {
+        entry.relative_path = prefix.join(&entry.relative_path);
+    }
+
+    let metadata = root
+        .metadata()
+        .with_context(|| format!("Failed to read metadata: {}", root.display()))?;
+    let mut dirs: Vec<&Path> = prefix.ancestors().filter(|a| *a != Path::new("")).collect();
+    dirs.reverse();
+    let dirs = dirs
+        .into_iter()
+        .map(|dir| create_file_entry(root, dir.to_path_buf(), &metadata, options))
+        .collect::<Result<Vec<_>>>()?;
+    entries.splice(0..0, dirs);
     Ok(())
 }
 
 /// Collects all files to be archived based on ignore rules.
 pub fn collect_files(args: &Args, config: &Config) -> Result<(Vec<FileEntry>, Vec<ExcludedFile>)> {
     let root = args.path.canonicalize().context("Failed to resolve path")?;
+    let transforms = transform::parse_all(
+        config
+            .transform
+            .iter()
+            .map(|rule| rule.expr.as_str())
+            .chain(args.transform.iter().map(String::as_str)),
+    )?;
 
     // Build the rule index
     let mut index = build_rule_index(args, config, &root);
 
     // Verbose output
-    print_rules_verbose(&index, args.verbose);
+    print_rules_verbose(&mut *args.human_output(), &index, args.verbose)?;
 
     // Walk the tree using indexed lookups
     let mut results = WalkResults {
@@ -341,14 +652,30 @@ pub fn collect_files(args: &Args, config: &Config) -> Result<(Vec<FileEntry>, Ve
         excluded: Vec::new(),
     };
 
-    walk_directory(
-        &root,
-        &root,
-        &mut index,
-        args.dereference,
-        args.reproducible,
-        &mut results,
-    )?;
+    let options = WalkOptions {
+        dereference: args.dereference,
+        mtime: mtime::resolve(args, &root)?,
+        xattrs: args.xattrs,
+        sparse: args.sparse,
+    };
+    walk_directory(&root, &root, &mut index, options, &mut results)?;
+
+    if args.no_empty_dirs {
+        remove_empty_dirs(&mut results.entries);
+    }
+
+    transform::apply_transforms(&mut results.entries, &transforms, args.verbose)?;
+
+    // With nothing to archive there is nothing to place under the prefix
+    if let Some(template) = args
+        .prefix
+        .as_deref()
+        .filter(|_| !results.entries.is_empty())
+    {
+        if let Some(prefix) = expand_prefix(template, &root, options.mtime)? {
+            apply_prefix(&mut results.entries, &prefix, &root, options)?;
+        }
+    }
 
     // Sort for reproducibility
     if args.reproducible {
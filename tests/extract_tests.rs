@@ -0,0 +1,915 @@
+//! Extraction tests.
+//!
+//! These tests cover:
+//! - Round-tripping archives created by raptar in every format
+//! - Restoring modes, mtimes and symlinks
+//! - --strip-components
+//! - Storing and restoring extended attributes
+//! - Storing files with holes as sparse entries
+//! - Refusing path traversal, escaping symlinks and hard links
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::fs;
+use std::os::unix::fs::{symlink, MetadataExt, PermissionsExt};
+use std::path::Path;
+use std::process::Command as StdCommand;
+use tempfile::TempDir;
+
+fn raptar() -> Command {
+    let cmd = StdCommand::new(env!("CARGO_BIN_EXE_raptar"));
+    Command::from_std(cmd)
+}
+
+/// Helper to create a file and its parent directories
+fn create_file(base: &Path, path: &str, content: &str) {
+    let full = base.join(path);
+    if let Some(parent) = full.parent() {
+        fs::create_dir_all(parent).unwrap();
+    }
+    fs::write(full, content).unwrap();
+}
+
+/// Helper to build a small source tree with an executable and a symlink
+fn create_source(base: &Path) {
+    create_file(base, "readme.txt", "hello");
+    create_file(base, "bin/run.sh", "#!/bin/sh\n");
+    fs::set_permissions(base.join("bin/run.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+    symlink("readme.txt", base.join("link.txt")).unwrap();
+}
+
+/// Helper to archive `src` into `output` with the given format
+fn create_archive(src: &Path, output: &Path, format: &str) {
+    raptar()
+        .arg(src)
+        .arg("-f")
+        .arg(format)
+        .arg("-o")
+        .arg(output)
+        .arg("-q")
+        .assert()
+        .success();
+}
+
+/// Helper to write a tar containing a single member with a raw (unchecked) name
+fn write_raw_tar(output: &Path, name: &str, entry_type: tar::EntryType, link: Option<&str>) {
+    write_raw_members(output, &[(name, entry_type, link)]);
+}
+
+/// Helper to write a tar of members with raw (unchecked) names and link names
+fn write_raw_members(output: &Path, members: &[(&str, tar::EntryType, Option<&str>)]) {
+    let mut builder = tar::Builder::new(fs::File::create(output).unwrap());
+    for &(name, entry_type, link) in members {
+        let mut header = tar::Header::new_gnu();
+        let bytes = name.as_bytes();
+        header.as_old_mut().name[..bytes.len()].copy_from_slice(bytes);
+        if let Some(link) = link {
+            let link = link.as_bytes();
+            header.as_old_mut().linkname[..link.len()].copy_from_slice(link);
+        }
+        let data: &[u8] = if entry_type == tar::EntryType::Regular {
+            b"evil"
+        } else {
+            b""
+        };
+        header.set_entry_type(entry_type);
+        header.set_mode(0o644);
+        header.set_size(data.len() as u64);
+        header.set_cksum();
+        builder.append(&header, data).unwrap();
+    }
+    builder.finish().unwrap();
+}
+
+// ============================================================================
+// ROUND TRIP
+// ============================================================================
+
+mod round_trip {
+    use super::*;
+
+    fn assert_round_trip(format: &str) {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("src");
+        create_source(&src);
+        let output = tmp.path().join(format!("out.{format}"));
+        create_archive(&src, &output, format);
+
+        let dest = tmp.path().join("dest");
+        raptar()
+            .arg("extract")
+            .arg(&output)
+            .arg("-C")
+            .arg(&dest)
+            .arg("-q")
+            .assert()
+            .success();
+
+        assert_eq!(
+            fs::read_to_string(dest.join("readme.txt")).unwrap(),
+            "hello"
+        );
+        let mode = fs::metadata(dest.join("bin/run.sh")).unwrap().mode();
+        assert_eq!(mode & 0o777, 0o755);
+        assert_eq!(
+            fs::read_link(dest.join("link.txt")).unwrap(),
+            Path::new("readme.txt")
+        );
+    }
+
+    #[test]
+    fn tar() {
+        assert_round_trip("tar");
+    }
+
+    #[test]
+    fn tar_gz() {
+        assert_round_trip("tar.gz");
+    }
+
+    #[test]
+    fn tar_bz2() {
+        assert_round_trip("tar.bz2");
+    }
+
+    #[test]
+    fn tar_zst() {
+        assert_round_trip("tar.zst");
+    }
+
+    #[test]
+    fn tar_xz() {
+        assert_round_trip("tar.xz");
+    }
+
+    #[test]
+    fn tar_lz4() {
+        assert_round_trip("tar.lz4");
+    }
+
+    #[test]
+    fn tar_br() {
+        assert_round_trip("tar.br");
+    }
+
+    #[test]
+    fn zip() {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("src");
+        create_file(&src, "readme.txt", "hello");
+        create_file(&src, "bin/run.sh", "#!/bin/sh\n");
+        fs::set_permissions(src.join("bin/run.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+        symlink("readme.txt", src.join("link.txt")).unwrap();
+        let output = tmp.path().join("out.zip");
+        create_archive(&src, &output, "zip");
+
+        let dest = tmp.path().join("dest");
+        raptar()
+            .arg("extract")
+            .arg(&output)
+            .arg("-C")
+            .arg(&dest)
+            .arg("-q")
+            .assert()
+            .success();
+
+        assert_eq!(
+            fs::read_to_string(dest.join("readme.txt")).unwrap(),
+            "hello"
+        );
+        let mode = fs::metadata(dest.join("bin/run.sh")).unwrap().mode();
+        assert_eq!(mode & 0o777, 0o755);
+        assert_eq!(
+            fs::read_link(dest.join("link.txt")).unwrap(),
+            Path::new("readme.txt")
+        );
+    }
+
+    #[test]
+    fn empty_dirs_preserved() {
+        for format in ["tar.gz", "zip"] {
+            let tmp = TempDir::new().unwrap();
+            let src = tmp.path().join("src");
+            create_file(&src, "readme.txt", "hello");
+            fs::create_dir_all(src.join("logs")).unwrap();
+            fs::create_dir_all(src.join("tmp/cache")).unwrap();
+            fs::set_permissions(src.join("logs"), fs::Permissions::from_mode(0o750)).unwrap();
+            let output = tmp.path().join(format!("out.{format}"));
+            create_archive(&src, &output, format);
+
+            let dest = tmp.path().join("dest");
+            raptar()
+                .arg("extract")
+                .arg(&output)
+                .arg("-C")
+                .arg(&dest)
+                .arg("-q")
+                .assert()
+                .success();
+
+            assert!(dest.join("tmp/cache").is_dir(), "{format}");
+            let mode = fs::metadata(dest.join("logs")).unwrap().mode();
+            assert_eq!(mode & 0o777, 0o750, "{format}");
+        }
+    }
+
+    #[test]
+    fn threaded_multi_block() {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("src");
+        // Large enough to span several parallel gzip and bzip2 blocks
+        let data: Vec<u8> = (0..=250u8).cycle().take(2_000_000).collect();
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("big.bin"), &data).unwrap();
+
+        for format in ["tar.gz", "tar.bz2", "tar.zst"] {
+            let output = tmp.path().join(format!("out.{format}"));
+            raptar()
+                .arg(&src)
+                .arg("-f")
+                .arg(format)
+                .arg("--threads")
+                .arg("3")
+                .arg("-o")
+                .arg(&output)
+                .arg("-q")
+                .assert()
+                .success();
+
+            let dest = tmp.path().join(format!("dest-{format}"));
+            raptar()
+                .arg("extract")
+                .arg(&output)
+                .arg("-C")
+                .arg(&dest)
+                .arg("-q")
+                .assert()
+                .success();
+
+            assert_eq!(fs::read(dest.join("big.bin")).unwrap(), data, "{format}");
+        }
+    }
+
+    #[test]
+    fn mtime_restored() {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("src");
+        create_file(&src, "old.txt", "old");
+        let old = filetime::FileTime::from_unix_time(1_000_000_000, 0);
+        filetime::set_file_mtime(src.join("old.txt"), old).unwrap();
+
+        let output = tmp.path().join("out.tar");
+        create_archive(&src, &output, "tar");
+
+        let dest = tmp.path().join("dest");
+        raptar()
+            .arg("extract")
+            .arg(&output)
+            .arg("-C")
+            .arg(&dest)
+            .arg("-q")
+            .assert()
+            .success();
+
+        let mtime = fs::metadata(dest.join("old.txt")).unwrap().mtime();
+        assert_eq!(mtime, 1_000_000_000);
+    }
+
+    #[test]
+    fn pax_subsecond_mtime_restored() {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("src");
+        create_file(&src, "precise.txt", "precise");
+        let precise = filetime::FileTime::from_unix_time(1_700_000_000, 123_456_789);
+        filetime::set_file_mtime(src.join("precise.txt"), precise).unwrap();
+
+        let output = tmp.path().join("out.tar");
+        raptar()
+            .arg(&src)
+            .arg("-f")
+            .arg("tar")
+            .arg("--tar-format")
+            .arg("pax")
+            .arg("-o")
+            .arg(&output)
+            .arg("-q")
+            .assert()
+            .success();
+
+        let dest = tmp.path().join("dest");
+        raptar()
+            .arg("extract")
+            .arg(&output)
+            .arg("-C")
+            .arg(&dest)
+            .arg("-q")
+            .assert()
+            .success();
+
+        let meta = fs::metadata(dest.join("precise.txt")).unwrap();
+        assert_eq!(meta.mtime(), 1_700_000_000);
+        assert_eq!(meta.mtime_nsec(), 123_456_789);
+    }
+
+    #[test]
+    fn format_detected_without_extension() {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("src");
+        create_file(&src, "a.txt", "a");
+        let output = tmp.path().join("archive.bin");
+        create_archive(&src, &output, "tar.zst");
+
+        let dest = tmp.path().join("dest");
+        raptar()
+            .arg("extract")
+            .arg(&output)
+            .arg("-C")
+            .arg(&dest)
+            .arg("-q")
+            .assert()
+            .success();
+
+        assert!(dest.join("a.txt").exists());
+    }
+}
+
+// ============================================================================
+// HARD LINKS
+// ============================================================================
+
+mod hard_links {
+    use super::*;
+
+    /// Creates `src/a.txt` and `src/b.txt` sharing one inode.
+    fn create_linked_source(src: &Path) {
+        create_file(src, "a.txt", &"shared content\n".repeat(4096));
+        fs::hard_link(src.join("a.txt"), src.join("b.txt")).unwrap();
+    }
+
+    fn read_members(archive: &Path) -> Vec<(String, tar::EntryType)> {
+        let mut archive = tar::Archive::new(fs::File::open(archive).unwrap());
+        archive
+            .entries()
+            .unwrap()
+            .map(|e| {
+                let e = e.unwrap();
+                (
+                    e.path().unwrap().to_string_lossy().to_string(),
+                    e.header().entry_type(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stored_once() {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("src");
+        create_linked_source(&src);
+        let output = tmp.path().join("out.tar");
+        create_archive(&src, &output, "tar");
+
+        let members = read_members(&output);
+        assert_eq!(
+            members,
+            vec![
+                ("a.txt".to_string(), tar::EntryType::Regular),
+                ("b.txt".to_string(), tar::EntryType::Link),
+            ]
+        );
+        // Content is written once
+        assert!(fs::metadata(&output).unwrap().len() < 2 * 4096 * 15);
+    }
+
+    #[test]
+    fn restored_as_links() {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("src");
+        create_linked_source(&src);
+        let output = tmp.path().join("out.tar.gz");
+        create_archive(&src, &output, "tar.gz");
+
+        let dest = tmp.path().join("dest");
+        raptar()
+            .arg("extract")
+            .arg(&output)
+            .arg("-C")
+            .arg(&dest)
+            .arg("-q")
+            .assert()
+            .success();
+
+        let a = fs::metadata(dest.join("a.txt")).unwrap();
+        let b = fs::metadata(dest.join("b.txt")).unwrap();
+        assert_eq!(a.ino(), b.ino());
+        assert_eq!(fs::read(dest.join("b.txt")).unwrap().len(), 15 * 4096);
+    }
+
+    #[test]
+    fn first_link_excluded() {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("src");
+        create_linked_source(&src);
+        let output = tmp.path().join("out.tar");
+        raptar()
+            .arg(&src)
+            .arg("-f")
+            .arg("tar")
+            .arg("--with-exclude")
+            .arg("a.txt")
+            .arg("-o")
+            .arg(&output)
+            .arg("-q")
+            .assert()
+            .success();
+
+        // The surviving path carries the content
+        let members = read_members(&output);
+        assert_eq!(
+            members,
+            vec![("b.txt".to_string(), tar::EntryType::Regular)]
+        );
+    }
+}
+
+// ============================================================================
+// EXTENDED ATTRIBUTES
+// ============================================================================
+
+mod xattrs {
+    use super::*;
+
+    /// Creates `src/tagged.txt` with a `user.raptar` xattr, or returns false
+    /// if the filesystem does not support user xattrs.
+    fn create_tagged_source(src: &Path) -> bool {
+        create_file(src, "tagged.txt", "content");
+        xattr::set(src.join("tagged.txt"), "user.raptar", b"hello").is_ok()
+    }
+
+    #[test]
+    fn stored_as_pax_records() {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("src");
+        if !create_tagged_source(&src) {
+            return;
+        }
+        let output = tmp.path().join("out.tar");
+        raptar()
+            .arg(&src)
+            .arg("-f")
+            .arg("tar")
+            .arg("--xattrs")
+            .arg("-o")
+            .arg(&output)
+            .arg("-q")
+            .assert()
+            .success();
+
+        let mut archive = tar::Archive::new(fs::File::open(&output).unwrap());
+        let mut entry = archive.entries().unwrap().next().unwrap().unwrap();
+        let found = entry.pax_extensions().unwrap().unwrap().any(|r| {
+            let r = r.unwrap();
+            r.key().unwrap() == "SCHILY.xattr.user.raptar" && r.value_bytes() == b"hello"
+        });
+        assert!(found);
+    }
+
+    #[test]
+    fn not_stored_by_default() {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("src");
+        if !create_tagged_source(&src) {
+            return;
+        }
+        let output = tmp.path().join("out.tar");
+        create_archive(&src, &output, "tar");
+
+        let mut archive = tar::Archive::new(fs::File::open(&output).unwrap());
+        let mut entry = archive.entries().unwrap().next().unwrap().unwrap();
+        assert!(entry.pax_extensions().unwrap().is_none());
+    }
+
+    #[test]
+    fn restored_with_xattrs() {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("src");
+        if !create_tagged_source(&src) {
+            return;
+        }
+        let output = tmp.path().join("out.tar.zst");
+        raptar()
+            .arg(&src)
+            .arg("-f")
+            .arg("tar.zst")
+            .arg("--xattrs")
+            .arg("-o")
+            .arg(&output)
+            .arg("-q")
+            .assert()
+            .success();
+
+        let plain = tmp.path().join("plain");
+        raptar()
+            .arg("extract")
+            .arg(&output)
+            .arg("-C")
+            .arg(&plain)
+            .arg("-q")
+            .assert()
+            .success();
+        assert_eq!(
+            xattr::get(plain.join("tagged.txt"), "user.raptar").unwrap(),
+            None
+        );
+
+        let dest = tmp.path().join("dest");
+        raptar()
+            .arg("extract")
+            .arg(&output)
+            .arg("-C")
+            .arg(&dest)
+            .arg("--xattrs")
+            .arg("-q")
+            .assert()
+            .success();
+        assert_eq!(
+            xattr::get(dest.join("tagged.txt"), "user.raptar").unwrap(),
+            Some(b"hello".to_vec())
+        );
+    }
+
+    #[test]
+    fn rejected_for_zip() {
+        let tmp = TempDir::new().unwrap();
+        create_file(tmp.path(), "a.txt", "content");
+        raptar()
+            .arg(tmp.path())
+            .arg("-f")
+            .arg("zip")
+            .arg("--xattrs")
+            .arg("-o")
+            .arg(tmp.path().join("out.zip"))
+            .arg("-q")
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("--xattrs needs a tar format"));
+    }
+}
+
+// ============================================================================
+// SPARSE FILES
+// ============================================================================
+
+mod sparse {
+    use super::*;
+    use std::io::{Seek, SeekFrom, Write};
+
+    const SPARSE_SIZE: u64 = 16 * 1024 * 1024;
+
+    /// Creates `src/holes.img` with data at the start and in the middle, or
+    /// returns false if the filesystem does not create holes.
+    fn create_sparse_source(src: &Path) -> bool {
+        fs::create_dir_all(src).unwrap();
+        let path = src.join("holes.img");
+        let mut file = fs::File::create(&path).unwrap();
+        file.write_all(b"start").unwrap();
+        file.seek(SeekFrom::Start(SPARSE_SIZE / 2)).unwrap();
+        file.write_all(b"middle").unwrap();
+        file.set_len(SPARSE_SIZE).unwrap();
+        drop(file);
+        fs::metadata(&path).unwrap().blocks() * 512 < SPARSE_SIZE
+    }
+
+    fn round_trip(tar_format: &str) {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("src");
+        if !create_sparse_source(&src) {
+            return;
+        }
+        let output = tmp.path().join("out.tar");
+        raptar()
+            .arg(&src)
+            .arg("-f")
+            .arg("tar")
+            .arg("--sparse")
+            .arg("--tar-format")
+            .arg(tar_format)
+            .arg("-o")
+            .arg(&output)
+            .arg("-q")
+            .assert()
+            .success();
+        assert!(fs::metadata(&output).unwrap().len() < 64 * 1024);
+
+        let dest = tmp.path().join("dest");
+        raptar()
+            .arg("extract")
+            .arg(&output)
+            .arg("-C")
+            .arg(&dest)
+            .arg("-q")
+            .assert()
+            .success();
+
+        let extracted = dest.join("holes.img");
+        assert_eq!(
+            fs::read(&extracted).unwrap(),
+            fs::read(src.join("holes.img")).unwrap()
+        );
+        assert!(fs::metadata(&extracted).unwrap().blocks() * 512 < SPARSE_SIZE);
+    }
+
+    #[test]
+    fn gnu_round_trip_keeps_holes() {
+        round_trip("gnu");
+    }
+
+    #[test]
+    fn pax_round_trip_keeps_holes() {
+        round_trip("pax");
+    }
+
+    #[test]
+    fn stored_densely_by_default() {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("src");
+        if !create_sparse_source(&src) {
+            return;
+        }
+        let output = tmp.path().join("out.tar");
+        raptar()
+            .arg(&src)
+            .arg("-f")
+            .arg("tar")
+            .arg("-o")
+            .arg(&output)
+            .arg("-q")
+            .assert()
+            .success();
+        assert!(fs::metadata(&output).unwrap().len() > SPARSE_SIZE);
+    }
+
+    #[test]
+    fn rejected_for_zip() {
+        let tmp = TempDir::new().unwrap();
+        create_file(tmp.path(), "a.txt", "content");
+        raptar()
+            .arg(tmp.path())
+            .arg("-f")
+            .arg("zip")
+            .arg("--sparse")
+            .arg("-o")
+            .arg(tmp.path().join("out.zip"))
+            .arg("-q")
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("--sparse needs a tar format"));
+    }
+}
+
+// ============================================================================
+// STRIP COMPONENTS
+// ============================================================================
+
+mod strip_components {
+    use super::*;
+
+    #[test]
+    fn strips_leading_directory() {
+        let tmp = TempDir::new().unwrap();
+        let src = tmp.path().join("src");
+        create_file(&src, "proj/lib/a.rs", "a");
+        create_file(&src, "proj/top.txt", "top");
+        let output = tmp.path().join("out.tar.gz");
+        create_archive(&src, &output, "tar.gz");
+
+        let dest = tmp.path().join("dest");
+        raptar()
+            .arg("extract")
+            .arg(&output)
+            .arg("-C")
+            .arg(&dest)
+            .arg("--strip-components")
+            .arg("1")
+            .arg("-q")
+            .assert()
+            .success();
+
+        assert!(dest.join("lib/a.rs").exists());
+        assert!(dest.join("top.txt").exists());
+        assert!(!dest.join("proj").exists());
+    }
+}
+
+// ============================================================================
+// PATH TRAVERSAL
+// ============================================================================
+
+mod traversal {
+    use super::*;
+
+    #[test]
+    fn refuses_parent_dir_member() {
+        let tmp = TempDir::new().unwrap();
+        let archive = tmp.path().join("evil.tar");
+        write_raw_tar(&archive, "../escaped.txt", tar::EntryType::Regular, None);
+
+        let dest = tmp.path().join("dest");
+        raptar()
+            .arg("extract")
+            .arg(&archive)
+            .arg("-C")
+            .arg(&dest)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("Refusing to extract"));
+
+        assert!(!tmp.path().join("escaped.txt").exists());
+    }
+
+    #[test]
+    fn refuses_absolute_member() {
+        let tmp = TempDir::new().unwrap();
+        let archive = tmp.path().join("evil.tar");
+        let target = tmp.path().join("absolute.txt");
+        write_raw_tar(
+            &archive,
+            target.to_str().unwrap(),
+            tar::EntryType::Regular,
+            None,
+        );
+
+        raptar()
+            .arg("extract")
+            .arg(&archive)
+            .arg("-C")
+            .arg(tmp.path().join("dest"))
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("absolute path"));
+
+        assert!(!target.exists());
+    }
+
+    #[test]
+    fn refuses_escaping_symlink() {
+        let tmp = TempDir::new().unwrap();
+        let archive = tmp.path().join("evil.tar");
+        write_raw_tar(
+            &archive,
+            "sub/link",
+            tar::EntryType::Symlink,
+            Some("../../outside"),
+        );
+
+        let dest = tmp.path().join("dest");
+        raptar()
+            .arg("extract")
+            .arg(&archive)
+            .arg("-C")
+            .arg(&dest)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("escapes destination"));
+
+        assert!(dest.join("sub/link").symlink_metadata().is_err());
+    }
+
+    #[test]
+    fn refuses_write_through_existing_symlink() {
+        let tmp = TempDir::new().unwrap();
+        let outside = tmp.path().join("outside");
+        fs::create_dir(&outside).unwrap();
+        let dest = tmp.path().join("dest");
+        fs::create_dir(&dest).unwrap();
+        symlink(&outside, dest.join("sub")).unwrap();
+
+        let archive = tmp.path().join("evil.tar");
+        write_raw_tar(&archive, "sub/file.txt", tar::EntryType::Regular, None);
+
+        raptar()
+            .arg("extract")
+            .arg(&archive)
+            .arg("-C")
+            .arg(&dest)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("outside destination"));
+
+        assert!(!outside.join("file.txt").exists());
+    }
+
+    #[test]
+    fn refuses_chained_symlinks_escaping_destination() {
+        let tmp = TempDir::new().unwrap();
+        fs::write(tmp.path().join("secret"), "secret").unwrap();
+        let dest = tmp.path().join("dest");
+
+        // Each target stays inside as text; together l1 is dest's parent
+        let archive = tmp.path().join("evil.tar");
+        write_raw_members(
+            &archive,
+            &[
+                ("l2", tar::EntryType::Symlink, Some(".")),
+                ("l1", tar::EntryType::Symlink, Some("l2/..")),
+                ("stolen", tar::EntryType::Link, Some("l1/secret")),
+            ],
+        );
+
+        raptar()
+            .arg("extract")
+            .arg(&archive)
+            .arg("-C")
+            .arg(&dest)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "symlink target resolves outside destination",
+            ));
+
+        assert!(dest.join("l1").symlink_metadata().is_err());
+        assert!(dest.join("stolen").symlink_metadata().is_err());
+        assert_eq!(fs::metadata(tmp.path().join("secret")).unwrap().nlink(), 1);
+    }
+
+    #[test]
+    fn refuses_symlink_going_up_from_missing_path() {
+        let tmp = TempDir::new().unwrap();
+        let dest = tmp.path().join("dest");
+
+        // l2 does not exist yet, so l1 could only be judged by its text
+        let archive = tmp.path().join("evil.tar");
+        write_raw_members(
+            &archive,
+            &[
+                ("l1", tar::EntryType::Symlink, Some("l2/..")),
+                ("l2", tar::EntryType::Symlink, Some(".")),
+            ],
+        );
+
+        raptar()
+            .arg("extract")
+            .arg(&archive)
+            .arg("-C")
+            .arg(&dest)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("not a directory"));
+
+        assert!(dest.join("l1").symlink_metadata().is_err());
+    }
+
+    #[test]
+    fn refuses_hard_link_through_existing_symlink() {
+        let tmp = TempDir::new().unwrap();
+        let outside = tmp.path().join("outside");
+        fs::create_dir(&outside).unwrap();
+        fs::write(outside.join("secret"), "secret").unwrap();
+        let dest = tmp.path().join("dest");
+        fs::create_dir(&dest).unwrap();
+        symlink(&outside, dest.join("l1")).unwrap();
+
+        let archive = tmp.path().join("evil.tar");
+        write_raw_tar(&archive, "stolen", tar::EntryType::Link, Some("l1/secret"));
+
+        raptar()
+            .arg("extract")
+            .arg(&archive)
+            .arg("-C")
+            .arg(&dest)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "hard link target resolves outside destination",
+            ));
+
+        assert!(dest.join("stolen").symlink_metadata().is_err());
+        assert_eq!(fs::metadata(outside.join("secret")).unwrap().nlink(), 1);
+    }
+
+    #[test]
+    fn refuses_hard_link_to_symlink() {
+        let tmp = TempDir::new().unwrap();
+        let dest = tmp.path().join("dest");
+
+        let archive = tmp.path().join("evil.tar");
+        write_raw_members(
+            &archive,
+            &[
+                ("link", tar::EntryType::Symlink, Some("file")),
+                ("hard", tar::EntryType::Link, Some("link")),
+            ],
+        );
+
+        raptar()
+            .arg("extract")
+            .arg(&archive)
+            .arg("-C")
+            .arg(&dest)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("hard link target is a symlink"));
+
+        assert!(dest.join("hard").symlink_metadata().is_err());
+    }
+}
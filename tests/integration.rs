@@ -1,5 +1,6 @@
 use assert_cmd::Command;
 use predicates::prelude::*;
+use std::fmt::Write as _;
 use std::fs;
 use std::process::Command as StdCommand;
 use tempfile::TempDir;
@@ -282,6 +283,183 @@ fn test_cli_tzst_alias() {
     assert!(output.exists());
 }
 
+#[test]
+fn test_cli_creates_tar_xz() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+
+    let output = tmp.path().join("output.tar.xz");
+
+    raptar()
+        .arg(tmp.path())
+        .arg("-f")
+        .arg("tar.xz")
+        .arg("-o")
+        .arg(&output)
+        .arg("-q")
+        .assert()
+        .success();
+
+    // Verify xz magic bytes (FD 37 7A 58 5A 00)
+    let bytes = fs::read(&output).unwrap();
+    assert_eq!(&bytes[..6], &[0xfd, b'7', b'z', b'X', b'Z', 0x00]);
+}
+
+#[test]
+fn test_cli_creates_tar_lz4() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+
+    let output = tmp.path().join("output.tar.lz4");
+
+    raptar()
+        .arg(tmp.path())
+        .arg("-f")
+        .arg("tar.lz4")
+        .arg("-o")
+        .arg(&output)
+        .arg("-q")
+        .assert()
+        .success();
+
+    // Verify lz4 frame magic bytes (0x184D2204)
+    let bytes = fs::read(&output).unwrap();
+    assert_eq!(&bytes[..4], &[0x04, 0x22, 0x4d, 0x18]);
+}
+
+#[test]
+fn test_cli_creates_tar_br() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+
+    let output = tmp.path().join("output.tar.br");
+
+    raptar()
+        .arg(tmp.path())
+        .arg("-f")
+        .arg("tar.br")
+        .arg("-o")
+        .arg(&output)
+        .arg("-q")
+        .assert()
+        .success();
+
+    // Brotli has no magic number; verify it lists back
+    raptar()
+        .arg("list")
+        .arg(&output)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("main.rs"));
+}
+
+#[test]
+fn test_cli_txz_alias() {
+    let tmp = TempDir::new().unwrap();
+    fs::write(tmp.path().join("test.txt"), "content").unwrap();
+
+    let output = tmp.path().join("out.txz");
+
+    raptar()
+        .arg(tmp.path())
+        .arg("-f")
+        .arg("txz")
+        .arg("-o")
+        .arg(&output)
+        .arg("-q")
+        .assert()
+        .success();
+
+    assert!(output.exists());
+}
+
+#[test]
+fn test_cli_tlz4_alias() {
+    let tmp = TempDir::new().unwrap();
+    fs::write(tmp.path().join("test.txt"), "content").unwrap();
+
+    let output = tmp.path().join("out.tlz4");
+
+    raptar()
+        .arg(tmp.path())
+        .arg("-f")
+        .arg("tlz4")
+        .arg("-o")
+        .arg(&output)
+        .arg("-q")
+        .assert()
+        .success();
+
+    assert!(output.exists());
+}
+
+#[test]
+fn test_cli_level_changes_output_size() {
+    let tmp = TempDir::new().unwrap();
+    let src = tmp.path().join("src");
+    fs::create_dir_all(&src).unwrap();
+    let text = (0..20_000).fold(String::new(), |mut acc, i| {
+        let _ = writeln!(acc, "line {} of text", i % 97);
+        acc
+    });
+    fs::write(src.join("data.txt"), text).unwrap();
+
+    let fast = tmp.path().join("fast.tar.gz");
+    let best = tmp.path().join("best.tar.gz");
+    for (output, level) in [(&fast, "1"), (&best, "9")] {
+        raptar()
+            .arg(&src)
+            .arg("--level")
+            .arg(level)
+            .arg("-o")
+            .arg(output)
+            .arg("-q")
+            .assert()
+            .success();
+    }
+
+    let fast_size = fs::metadata(&fast).unwrap().len();
+    let best_size = fs::metadata(&best).unwrap().len();
+    assert!(best_size < fast_size, "{best_size} >= {fast_size}");
+}
+
+#[test]
+fn test_cli_level_negative_zstd() {
+    let tmp = TempDir::new().unwrap();
+    fs::write(tmp.path().join("test.txt"), "content").unwrap();
+
+    raptar()
+        .arg(tmp.path())
+        .arg("-f")
+        .arg("tar.zst")
+        .arg("--level")
+        .arg("-5")
+        .arg("-o")
+        .arg(tmp.path().join("out.tar.zst"))
+        .arg("-q")
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_cli_level_out_of_range() {
+    let tmp = TempDir::new().unwrap();
+    fs::write(tmp.path().join("test.txt"), "content").unwrap();
+
+    raptar()
+        .arg(tmp.path())
+        .arg("-f")
+        .arg("tar.gz")
+        .arg("--level")
+        .arg("12")
+        .arg("-o")
+        .arg(tmp.path().join("out.tar.gz"))
+        .arg("-q")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("expected 0 to 9"));
+}
+
 // ============================================================
 // Ignore behavior tests
 // ============================================================
@@ -322,6 +500,69 @@ fn test_cli_dotfiles_included_by_default() {
 // Reproducible archive tests
 // ============================================================
 
+/// Builds two reproducible archives of the same tree and returns their bytes.
+fn reproducible_pair(format: &str, extra_args: &[&str]) -> (Vec<u8>, Vec<u8>) {
+    let tmp = TempDir::new().unwrap();
+    let src_dir = tmp.path().join("src");
+    fs::create_dir(&src_dir).unwrap();
+    fs::write(src_dir.join("a.txt"), "aaa").unwrap();
+    fs::write(src_dir.join("b.txt"), "bbb").unwrap();
+
+    let output1 = tmp.path().join(format!("out1.{format}"));
+    let output2 = tmp.path().join(format!("out2.{format}"));
+
+    for output in [&output1, &output2] {
+        raptar()
+            .arg(&src_dir)
+            .arg("-f")
+            .arg(format)
+            .arg("-r")
+            .args(extra_args)
+            .arg("-o")
+            .arg(output)
+            .arg("-q")
+            .assert()
+            .success();
+    }
+
+    (fs::read(&output1).unwrap(), fs::read(&output2).unwrap())
+}
+
+#[test]
+fn test_cli_reproducible_new_formats() {
+    for format in ["tar.xz", "tar.lz4", "tar.br"] {
+        let (bytes1, bytes2) = reproducible_pair(format, &[]);
+        assert_eq!(bytes1, bytes2, "{format} output differs between runs");
+    }
+}
+
+#[test]
+fn test_cli_reproducible_threads() {
+    for format in ["tar.gz", "tar.bz2", "tar.zst"] {
+        let (bytes1, bytes2) = reproducible_pair(format, &["--threads", "3"]);
+        assert_eq!(bytes1, bytes2, "{format} output differs between runs");
+    }
+}
+
+#[test]
+fn test_cli_threads_unsupported_format() {
+    let tmp = TempDir::new().unwrap();
+    fs::write(tmp.path().join("test.txt"), "content").unwrap();
+
+    raptar()
+        .arg(tmp.path())
+        .arg("-f")
+        .arg("tar.xz")
+        .arg("--threads")
+        .arg("2")
+        .arg("-o")
+        .arg(tmp.path().join("out.tar.xz"))
+        .arg("-q")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("does not support --threads"));
+}
+
 #[test]
 fn test_cli_reproducible_flag() {
     let tmp = TempDir::new().unwrap();
@@ -363,140 +604,518 @@ fn test_cli_reproducible_flag() {
     assert_eq!(bytes1, bytes2);
 }
 
-// ============================================================
-// Verbose/quiet mode tests
-// ============================================================
+/// Returns (name, mtime) for each member of a plain tar archive.
+fn tar_mtimes(path: &std::path::Path) -> Vec<(String, u64)> {
+    let mut archive = tar::Archive::new(fs::File::open(path).unwrap());
+    archive
+        .entries()
+        .unwrap()
+        .map(|e| {
+            let e = e.unwrap();
+            (
+                e.path().unwrap().to_string_lossy().to_string(),
+                e.header().mtime().unwrap(),
+            )
+        })
+        .collect()
+}
+
+/// Creates a tree with an old file (2001) and a fresh one.
+fn old_and_new_files(dir: &std::path::Path) {
+    fs::create_dir_all(dir).unwrap();
+    fs::write(dir.join("old.txt"), "old").unwrap();
+    fs::write(dir.join("new.txt"), "new").unwrap();
+    let old = filetime::FileTime::from_unix_time(1_000_000_000, 0);
+    filetime::set_file_mtime(dir.join("old.txt"), old).unwrap();
+}
 
 #[test]
-fn test_cli_quiet_mode() {
+fn test_cli_reproducible_clamps_to_source_date_epoch() {
     let tmp = TempDir::new().unwrap();
-    fs::write(tmp.path().join("test.txt"), "content").unwrap();
-
-    let output = tmp.path().join("out.tar.gz");
+    let src = tmp.path().join("src");
+    old_and_new_files(&src);
+    let output = tmp.path().join("out.tar");
 
     raptar()
-        .arg(tmp.path())
+        .env("SOURCE_DATE_EPOCH", "1500000000")
+        .arg(&src)
+        .arg("-f")
+        .arg("tar")
+        .arg("-r")
         .arg("-o")
         .arg(&output)
         .arg("-q")
         .assert()
-        .success()
-        .stdout(predicate::str::is_empty());
+        .success();
+
+    let mtimes = tar_mtimes(&output);
+    assert!(mtimes.contains(&("old.txt".to_string(), 1_000_000_000)));
+    assert!(mtimes.contains(&("new.txt".to_string(), 1_500_000_000)));
 }
 
 #[test]
-fn test_cli_verbose_mode() {
+fn test_cli_mtime_set_and_clamp() {
     let tmp = TempDir::new().unwrap();
-    fs::write(tmp.path().join("test.txt"), "content").unwrap();
-    fs::write(tmp.path().join("remove.bak"), "bak").unwrap();
+    let src = tmp.path().join("src");
+    old_and_new_files(&src);
+    let set = tmp.path().join("set.tar");
+    let clamp = tmp.path().join("clamp.tar");
 
-    // Verbose mode shows excluded files with reasons
     raptar()
-        .arg(tmp.path())
-        .arg("--preview")
-        .arg("-v")
-        .arg("--with-exclude")
-        .arg("*.bak")
+        .arg(&src)
+        .args(["-f", "tar", "--mtime", "2020-01-01", "-q", "-o"])
+        .arg(&set)
         .assert()
-        .success()
-        .stdout(predicate::str::contains("test.txt"))
-        .stdout(predicate::str::contains("Files excluded"))
-        .stdout(predicate::str::contains("remove.bak"));
-}
+        .success();
+    raptar()
+        .arg(&src)
+        .args([
+            "-f",
+            "tar",
+            "--mtime",
+            "@1577836800",
+            "--clamp-mtime",
+            "-q",
+            "-o",
+        ])
+        .arg(&clamp)
+        .assert()
+        .success();
 
-// ============================================================
-// Empty directory test
-// ============================================================
+    assert!(tar_mtimes(&set).iter().all(|(_, m)| *m == 1_577_836_800));
+    let clamped = tar_mtimes(&clamp);
+    assert!(clamped.contains(&("old.txt".to_string(), 1_000_000_000)));
+    assert!(clamped.contains(&("new.txt".to_string(), 1_577_836_800)));
+}
 
 #[test]
-fn test_cli_empty_directory() {
+fn test_cli_mtime_git_uses_last_commit() {
     let tmp = TempDir::new().unwrap();
-    fs::create_dir_all(tmp.path().join("empty")).unwrap();
+    let src = tmp.path().join("src");
+    old_and_new_files(&src);
+    let git = |args: &[&str]| {
+        let status = StdCommand::new("git")
+            .current_dir(&src)
+            .args(args)
+            .env("GIT_COMMITTER_DATE", "@1600000000 +0000")
+            .env("GIT_AUTHOR_DATE", "@1600000000 +0000")
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+    git(&["init", "-q"]);
+    git(&["add", "."]);
+    git(&[
+        "-c",
+        "user.name=t",
+        "-c",
+        "user.email=t@example.com",
+        "commit",
+        "-qm",
+        "init",
+    ]);
+    let output = tmp.path().join("out.tar");
 
     raptar()
-        .arg(tmp.path())
-        .arg("--preview")
+        .arg(&src)
+        .args(["-f", "tar", "--mtime", "git", "-q", "-o"])
+        .arg(&output)
         .assert()
-        .success()
-        .stdout(predicate::str::contains("No files to archive"));
-}
+        .success();
 
-// ============================================================
-// Format alias test
-// ============================================================
+    let mtimes = tar_mtimes(&output);
+    assert!(mtimes.contains(&("new.txt".to_string(), 1_600_000_000)));
+}
 
 #[test]
-fn test_cli_tgz_alias() {
+fn test_cli_mtime_rejects_bad_value() {
     let tmp = TempDir::new().unwrap();
-    fs::write(tmp.path().join("test.txt"), "content").unwrap();
-
-    let output = tmp.path().join("out.tgz");
+    fs::write(tmp.path().join("a.txt"), "a").unwrap();
 
     raptar()
         .arg(tmp.path())
-        .arg("-f")
-        .arg("tgz")
-        .arg("-o")
-        .arg(&output)
-        .arg("-q")
+        .args(["--preview", "--mtime", "last tuesday"])
         .assert()
-        .success();
+        .failure()
+        .stderr(predicate::str::contains("Invalid --mtime"));
+}
 
-    assert!(output.exists());
+/// Creates a tree with unusual permissions: group-writable, setuid and a
+/// private directory.
+fn odd_modes_tree(dir: &std::path::Path) {
+    use std::os::unix::fs::PermissionsExt;
+    fs::create_dir_all(dir.join("private")).unwrap();
+    fs::write(dir.join("shared.txt"), "shared").unwrap();
+    fs::write(dir.join("tool.sh"), "#!/bin/sh").unwrap();
+    fs::write(dir.join("private/key"), "key").unwrap();
+    let chmod = |path: &str, mode| {
+        fs::set_permissions(dir.join(path), fs::Permissions::from_mode(mode)).unwrap();
+    };
+    chmod("shared.txt", 0o664);
+    chmod("tool.sh", 0o4750);
+    chmod("private/key", 0o600);
+    chmod("private", 0o700);
 }
 
-// ============================================================
-// Symlink tests
-// ============================================================
+/// Returns (name, mode) for each member of a plain tar archive.
+fn tar_modes(path: &std::path::Path) -> Vec<(String, u32)> {
+    let mut archive = tar::Archive::new(fs::File::open(path).unwrap());
+    archive
+        .entries()
+        .unwrap()
+        .map(|e| {
+            let e = e.unwrap();
+            (
+                e.path().unwrap().to_string_lossy().to_string(),
+                e.header().mode().unwrap() & 0o7777,
+            )
+        })
+        .collect()
+}
 
 #[test]
-fn test_cli_symlink_in_archive() {
-    use std::os::unix::fs::symlink;
-
+fn test_cli_reproducible_normalizes_modes() {
     let tmp = TempDir::new().unwrap();
-    fs::write(tmp.path().join("target.txt"), "content").unwrap();
-    symlink("target.txt", tmp.path().join("link.txt")).unwrap();
-
+    let src = tmp.path().join("src");
+    odd_modes_tree(&src);
     let output = tmp.path().join("out.tar");
 
     raptar()
-        .arg(tmp.path())
-        .arg("-f")
-        .arg("tar")
-        .arg("-o")
+        .arg(&src)
+        .args(["-f", "tar", "-r", "-q", "-o"])
         .arg(&output)
-        .arg("-q")
         .assert()
         .success();
 
-    // Verify symlink is in archive
-    let file = fs::File::open(&output).unwrap();
-    let mut archive = tar::Archive::new(file);
-
-    let mut found_symlink = false;
-    for entry in archive.entries().unwrap() {
-        let entry = entry.unwrap();
-        if entry.path().unwrap().to_string_lossy() == "link.txt" {
-            assert_eq!(entry.header().entry_type(), tar::EntryType::Symlink);
-            found_symlink = true;
-        }
-    }
-    assert!(found_symlink, "Symlink not found in archive");
+    let modes = tar_modes(&output);
+    assert!(modes.contains(&("shared.txt".to_string(), 0o644)));
+    assert!(modes.contains(&("tool.sh".to_string(), 0o755)));
+    assert!(modes.contains(&("private/".to_string(), 0o755)));
+    assert!(modes.contains(&("private/key".to_string(), 0o644)));
 }
 
 #[test]
-fn test_cli_dereference_follows_symlinks() {
-    use std::os::unix::fs::symlink;
-
+fn test_cli_no_normalize_modes_keeps_disk_modes() {
     let tmp = TempDir::new().unwrap();
-    fs::write(tmp.path().join("target.txt"), "actual content").unwrap();
-    symlink("target.txt", tmp.path().join("link.txt")).unwrap();
-
+    let src = tmp.path().join("src");
+    odd_modes_tree(&src);
     let output = tmp.path().join("out.tar");
 
     raptar()
-        .arg(tmp.path())
-        .arg("-f")
+        .arg(&src)
+        .args(["-f", "tar", "-r", "--no-normalize-modes", "-q", "-o"])
+        .arg(&output)
+        .assert()
+        .success();
+
+    let modes = tar_modes(&output);
+    assert!(modes.contains(&("tool.sh".to_string(), 0o4750)));
+    assert!(modes.contains(&("private/key".to_string(), 0o600)));
+}
+
+#[test]
+fn test_cli_normalize_modes_in_zip() {
+    let tmp = TempDir::new().unwrap();
+    let src = tmp.path().join("src");
+    odd_modes_tree(&src);
+    let output = tmp.path().join("out.zip");
+
+    raptar()
+        .arg(&src)
+        .args(["-f", "zip", "--normalize-modes", "-q", "-o"])
+        .arg(&output)
+        .assert()
+        .success();
+
+    let mut archive = zip::ZipArchive::new(fs::File::open(&output).unwrap()).unwrap();
+    let mode = |archive: &mut zip::ZipArchive<fs::File>, name: &str| {
+        archive.by_name(name).unwrap().unix_mode().unwrap() & 0o7777
+    };
+    assert_eq!(mode(&mut archive, "shared.txt"), 0o644);
+    assert_eq!(mode(&mut archive, "tool.sh"), 0o755);
+    assert_eq!(mode(&mut archive, "private/key"), 0o644);
+}
+
+/// Returns (name, uid, username, gid, groupname) for each member of a plain
+/// tar archive.
+fn tar_owners(path: &std::path::Path) -> Vec<(String, u64, String, u64, String)> {
+    let mut archive = tar::Archive::new(fs::File::open(path).unwrap());
+    archive
+        .entries()
+        .unwrap()
+        .map(|e| {
+            let e = e.unwrap();
+            let header = e.header();
+            (
+                e.path().unwrap().to_string_lossy().to_string(),
+                header.uid().unwrap(),
+                header.username().unwrap().unwrap_or_default().to_string(),
+                header.gid().unwrap(),
+                header.groupname().unwrap().unwrap_or_default().to_string(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_cli_forced_owner_and_group() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+    let output = tmp.path().join("out.tar");
+
+    raptar()
+        .arg(tmp.path())
+        .args([
+            "-f", "tar", "--owner", "app:1500", "--group", "www:33", "-q",
+        ])
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+
+    let owners = tar_owners(&output);
+    assert!(!owners.is_empty());
+    for (name, uid, uname, gid, gname) in owners {
+        assert_eq!((uid, uname.as_str()), (1500, "app"), "{name}");
+        assert_eq!((gid, gname.as_str()), (33, "www"), "{name}");
+    }
+}
+
+#[test]
+fn test_cli_owner_map_rewrites_local_user() {
+    use std::os::unix::fs::MetadataExt;
+    let tmp = TempDir::new().unwrap();
+    let src = tmp.path().join("src");
+    fs::create_dir(&src).unwrap();
+    fs::write(src.join("app.bin"), "binary").unwrap();
+    let uid = fs::metadata(src.join("app.bin")).unwrap().uid();
+    let map = tmp.path().join("owners.map");
+    fs::write(
+        &map,
+        format!("# build user -> service account\n+{uid} deploy:2000\n"),
+    )
+    .unwrap();
+    let output = tmp.path().join("out.tar");
+
+    raptar()
+        .arg(&src)
+        .args(["-f", "tar", "-q", "--owner-map"])
+        .arg(&map)
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+
+    let owners = tar_owners(&output);
+    let (_, uid, uname, _, _) = owners.iter().find(|o| o.0 == "app.bin").unwrap();
+    assert_eq!((*uid, uname.as_str()), (2000, "deploy"));
+}
+
+#[test]
+fn test_cli_owner_map_needs_preserve_owner_when_reproducible() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+    let map = tmp.path().join("owners.map");
+    fs::write(&map, "+0 app:1500\n").unwrap();
+    let output = tmp.path().join("out.tar");
+
+    raptar()
+        .arg(tmp.path())
+        .args(["-f", "tar", "-r", "-q", "--owner-map"])
+        .arg(&map)
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("add --preserve-owner"));
+    raptar()
+        .arg(tmp.path())
+        .args(["-f", "tar", "-r", "-q", "--preserve-owner", "--owner-map"])
+        .arg(&map)
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_cli_numeric_owner_leaves_names_empty() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+    let output = tmp.path().join("out.tar");
+
+    raptar()
+        .arg(tmp.path())
+        .args(["-f", "tar", "--owner", "app:1500", "--numeric-owner", "-q"])
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+
+    for (_, uid, uname, _, gname) in tar_owners(&output) {
+        assert_eq!(uid, 1500);
+        assert!(uname.is_empty() && gname.is_empty());
+    }
+}
+
+#[test]
+fn test_cli_owner_rejected_for_zip() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+
+    raptar()
+        .arg(tmp.path())
+        .args(["-f", "zip", "--owner", "app:1500", "-q"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("need a tar format"));
+}
+
+// ============================================================
+// Verbose/quiet mode tests
+// ============================================================
+
+#[test]
+fn test_cli_quiet_mode() {
+    let tmp = TempDir::new().unwrap();
+    fs::write(tmp.path().join("test.txt"), "content").unwrap();
+
+    let output = tmp.path().join("out.tar.gz");
+
+    raptar()
+        .arg(tmp.path())
+        .arg("-o")
+        .arg(&output)
+        .arg("-q")
+        .assert()
+        .success()
+        .stdout(predicate::str::is_empty());
+}
+
+#[test]
+fn test_cli_verbose_mode() {
+    let tmp = TempDir::new().unwrap();
+    fs::write(tmp.path().join("test.txt"), "content").unwrap();
+    fs::write(tmp.path().join("remove.bak"), "bak").unwrap();
+
+    // Verbose mode shows excluded files with reasons
+    raptar()
+        .arg(tmp.path())
+        .arg("--preview")
+        .arg("-v")
+        .arg("--with-exclude")
+        .arg("*.bak")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("test.txt"))
+        .stdout(predicate::str::contains("Files excluded"))
+        .stdout(predicate::str::contains("remove.bak"));
+}
+
+// ============================================================
+// Empty directory test
+// ============================================================
+
+#[test]
+fn test_cli_empty_directory() {
+    let tmp = TempDir::new().unwrap();
+    fs::create_dir_all(tmp.path().join("empty")).unwrap();
+    let out = TempDir::new().unwrap();
+    let output = out.path().join("out.tar.gz");
+
+    // A tree of empty placeholders is still archived
+    raptar()
+        .arg(tmp.path())
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+    assert_eq!(tar_gz_names(&output), ["empty/"]);
+
+    raptar()
+        .arg(tmp.path())
+        .args(["--no-empty-dirs", "--preview"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No files to archive"));
+}
+
+// ============================================================
+// Format alias test
+// ============================================================
+
+#[test]
+fn test_cli_tgz_alias() {
+    let tmp = TempDir::new().unwrap();
+    fs::write(tmp.path().join("test.txt"), "content").unwrap();
+
+    let output = tmp.path().join("out.tgz");
+
+    raptar()
+        .arg(tmp.path())
+        .arg("-f")
+        .arg("tgz")
+        .arg("-o")
+        .arg(&output)
+        .arg("-q")
+        .assert()
+        .success();
+
+    assert!(output.exists());
+}
+
+// ============================================================
+// Symlink tests
+// ============================================================
+
+#[test]
+fn test_cli_symlink_in_archive() {
+    use std::os::unix::fs::symlink;
+
+    let tmp = TempDir::new().unwrap();
+    fs::write(tmp.path().join("target.txt"), "content").unwrap();
+    symlink("target.txt", tmp.path().join("link.txt")).unwrap();
+
+    let output = tmp.path().join("out.tar");
+
+    raptar()
+        .arg(tmp.path())
+        .arg("-f")
+        .arg("tar")
+        .arg("-o")
+        .arg(&output)
+        .arg("-q")
+        .assert()
+        .success();
+
+    // Verify symlink is in archive
+    let file = fs::File::open(&output).unwrap();
+    let mut archive = tar::Archive::new(file);
+
+    let mut found_symlink = false;
+    for entry in archive.entries().unwrap() {
+        let entry = entry.unwrap();
+        if entry.path().unwrap().to_string_lossy() == "link.txt" {
+            assert_eq!(entry.header().entry_type(), tar::EntryType::Symlink);
+            found_symlink = true;
+        }
+    }
+    assert!(found_symlink, "Symlink not found in archive");
+}
+
+#[test]
+fn test_cli_dereference_follows_symlinks() {
+    use std::os::unix::fs::symlink;
+
+    let tmp = TempDir::new().unwrap();
+    fs::write(tmp.path().join("target.txt"), "actual content").unwrap();
+    symlink("target.txt", tmp.path().join("link.txt")).unwrap();
+
+    let output = tmp.path().join("out.tar");
+
+    raptar()
+        .arg(tmp.path())
+        .arg("-f")
         .arg("tar")
         .arg("--dereference")
         .arg("-o")
@@ -741,3 +1360,1590 @@ fn test_cli_multiple_sibling_directories() {
         .stdout(predicate::str::contains("examples/ex.rs"))
         .stdout(predicate::str::contains(".git").not());
 }
+
+#[test]
+fn test_cli_zip_symlink_policies() {
+    let tmp = TempDir::new().unwrap();
+    let src = tmp.path().join("src");
+    fs::create_dir_all(&src).unwrap();
+    fs::write(src.join("target.txt"), "content").unwrap();
+    std::os::unix::fs::symlink("target.txt", src.join("link.txt")).unwrap();
+
+    let list = |policy: &str| {
+        let output = tmp.path().join(format!("{policy}.zip"));
+        raptar()
+            .arg(&src)
+            .arg("-f")
+            .arg("zip")
+            .arg("--zip-symlinks")
+            .arg(policy)
+            .arg("-o")
+            .arg(&output)
+            .arg("-q")
+            .assert()
+            .success();
+        let out = raptar().arg("list").arg(&output).assert().success();
+        String::from_utf8(out.get_output().stdout.clone()).unwrap()
+    };
+
+    assert!(list("link").contains("link.txt -> target.txt"));
+
+    let follow = list("follow");
+    assert!(follow.contains("link.txt"));
+    assert!(!follow.contains("->"));
+
+    assert!(!list("skip").contains("link.txt"));
+}
+
+/// Returns `(name, compression method)` for each member of a zip file.
+fn zip_methods(path: &std::path::Path) -> Vec<(String, zip::CompressionMethod)> {
+    let mut archive = zip::ZipArchive::new(fs::File::open(path).unwrap()).unwrap();
+    (0..archive.len())
+        .map(|i| {
+            let file = archive.by_index(i).unwrap();
+            (file.name().to_string(), file.compression())
+        })
+        .collect()
+}
+
+#[test]
+fn test_cli_zip_stores_precompressed_extensions() {
+    let tmp = TempDir::new().unwrap();
+    let src = tmp.path().join("src");
+    fs::create_dir_all(&src).unwrap();
+    let text = "compressible text\n".repeat(1000);
+    fs::write(src.join("notes.txt"), &text).unwrap();
+    fs::write(src.join("image.png"), &text).unwrap();
+
+    let output = tmp.path().join("out.zip");
+    raptar()
+        .arg(&src)
+        .arg("-f")
+        .arg("zip")
+        .arg("-o")
+        .arg(&output)
+        .arg("-q")
+        .assert()
+        .success();
+
+    let methods = zip_methods(&output);
+    assert!(methods.contains(&("notes.txt".to_string(), zip::CompressionMethod::Deflated)));
+    assert!(methods.contains(&("image.png".to_string(), zip::CompressionMethod::Stored)));
+}
+
+#[test]
+fn test_cli_zip_stores_incompressible_data() {
+    let tmp = TempDir::new().unwrap();
+    let src = tmp.path().join("src");
+    fs::create_dir_all(&src).unwrap();
+    // A simple LCG gives bytes deflate cannot shrink
+    let mut state = 12345u32;
+    let noise: Vec<u8> = (0..50_000)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            state.to_be_bytes()[0]
+        })
+        .collect();
+    fs::write(src.join("noise.bin"), noise).unwrap();
+    let home = tmp.path().join("home");
+    let config_dir = home.join(".config/raptar");
+    fs::create_dir_all(&config_dir).unwrap();
+    let output = tmp.path().join("out.zip");
+    let create = || {
+        raptar()
+            .env("HOME", &home)
+            .arg(&src)
+            .args(["-f", "zip", "-q", "-o"])
+            .arg(&output)
+            .assert()
+            .success();
+        zip_methods(&output)
+    };
+
+    // Sampling is opt-in
+    assert_eq!(
+        create(),
+        vec![("noise.bin".to_string(), zip::CompressionMethod::Deflated)]
+    );
+
+    fs::write(
+        config_dir.join("config.toml"),
+        "[zip]\nstore_if_larger = true\n",
+    )
+    .unwrap();
+    assert_eq!(
+        create(),
+        vec![("noise.bin".to_string(), zip::CompressionMethod::Stored)]
+    );
+}
+
+#[test]
+fn test_cli_zip_method() {
+    let tmp = TempDir::new().unwrap();
+    let src = tmp.path().join("src");
+    fs::create_dir_all(&src).unwrap();
+    fs::write(src.join("notes.txt"), "compressible text\n".repeat(1000)).unwrap();
+
+    for (method, expected) in [
+        ("store", zip::CompressionMethod::Stored),
+        ("deflate", zip::CompressionMethod::Deflated),
+        ("bzip2", zip::CompressionMethod::Bzip2),
+        ("zstd", zip::CompressionMethod::Zstd),
+    ] {
+        let output = tmp.path().join(format!("{method}.zip"));
+        raptar()
+            .arg(&src)
+            .arg("-f")
+            .arg("zip")
+            .arg("--zip-method")
+            .arg(method)
+            .arg("-o")
+            .arg(&output)
+            .arg("-q")
+            .assert()
+            .success();
+
+        assert_eq!(
+            zip_methods(&output),
+            vec![("notes.txt".to_string(), expected)],
+            "{method}"
+        );
+    }
+}
+
+// ============================================================
+// Tar header format tests
+// ============================================================
+
+/// Creates a file nested under two 120-byte directory names.
+fn create_long_path(dir: &std::path::Path) -> String {
+    let long = "d".repeat(120);
+    let relative = format!("{long}/{long}/file.txt");
+    fs::create_dir_all(dir.join(format!("{long}/{long}"))).unwrap();
+    fs::write(dir.join(&relative), "content").unwrap();
+    relative
+}
+
+fn create_with_tar_format(src: &std::path::Path, output: &std::path::Path, format: &str) {
+    raptar()
+        .arg(src)
+        .arg("-f")
+        .arg("tar")
+        .arg("--tar-format")
+        .arg(format)
+        .arg("-o")
+        .arg(output)
+        .arg("-q")
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_cli_tar_format_pax_long_paths() {
+    let tmp = TempDir::new().unwrap();
+    let src = tmp.path().join("src");
+    let relative = create_long_path(&src);
+    fs::write(src.join("naïve.txt"), "utf-8").unwrap();
+
+    let output = tmp.path().join("out.tar");
+    create_with_tar_format(&src, &output, "pax");
+
+    // POSIX headers only: a pax extended header first, no GNU long names
+    let bytes = fs::read(&output).unwrap();
+    assert_eq!(bytes[156], b'x');
+    assert_eq!(&bytes[257..265], b"ustar\x0000");
+    assert!(!bytes.chunks(512).any(|block| block[257..263] == *b"ustar "));
+
+    let mut archive = tar::Archive::new(fs::File::open(&output).unwrap());
+    let names: Vec<String> = archive
+        .entries()
+        .unwrap()
+        .map(|e| e.unwrap().path().unwrap().to_string_lossy().to_string())
+        .collect();
+    assert!(names.contains(&relative));
+    assert!(names.contains(&"naïve.txt".to_string()));
+}
+
+#[test]
+fn test_cli_tar_format_ustar_reports_long_names() {
+    let tmp = TempDir::new().unwrap();
+    let src = tmp.path().join("src");
+    let relative = create_long_path(&src);
+
+    let output = tmp.path().join("out.tar");
+    raptar()
+        .arg(&src)
+        .arg("-f")
+        .arg("tar")
+        .arg("--tar-format")
+        .arg("ustar")
+        .arg("-o")
+        .arg(&output)
+        .arg("-q")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be stored in ustar format"))
+        .stderr(predicate::str::contains(format!(
+            "{relative}: name is 250 bytes"
+        )));
+    assert!(!output.exists());
+}
+
+#[test]
+fn test_cli_tar_format_ustar_splits_names() {
+    let tmp = TempDir::new().unwrap();
+    let src = tmp.path().join("src");
+    let relative = format!("{}/{}/file.txt", "a".repeat(60), "b".repeat(60));
+    fs::create_dir_all(src.join(&relative).parent().unwrap()).unwrap();
+    fs::write(src.join(&relative), "content").unwrap();
+
+    let output = tmp.path().join("out.tar");
+    create_with_tar_format(&src, &output, "ustar");
+
+    let mut archive = tar::Archive::new(fs::File::open(&output).unwrap());
+    let names: Vec<String> = archive
+        .entries()
+        .unwrap()
+        .map(|e| e.unwrap().path().unwrap().to_string_lossy().to_string())
+        .collect();
+    assert!(names.contains(&relative));
+}
+
+#[test]
+fn test_cli_tar_format_v7() {
+    let tmp = TempDir::new().unwrap();
+    fs::write(tmp.path().join("file.txt"), "content").unwrap();
+
+    let output = tmp.path().join("out.tar");
+    create_with_tar_format(tmp.path(), &output, "v7");
+
+    // v7 headers have no magic
+    let bytes = fs::read(&output).unwrap();
+    assert_eq!(&bytes[..8], b"file.txt");
+    assert_eq!(&bytes[257..265], &[0; 8]);
+}
+
+#[test]
+fn test_cli_tar_format_xattrs_need_pax() {
+    let tmp = TempDir::new().unwrap();
+    fs::write(tmp.path().join("file.txt"), "content").unwrap();
+
+    raptar()
+        .arg(tmp.path())
+        .arg("-f")
+        .arg("tar")
+        .arg("--tar-format")
+        .arg("ustar")
+        .arg("--xattrs")
+        .arg("-o")
+        .arg(tmp.path().join("out.tar"))
+        .arg("-q")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--xattrs needs PAX headers"));
+}
+
+// ============================================================
+// Special file tests
+// ============================================================
+
+/// Creates a regular file, a FIFO and a Unix socket; returns the listener so
+/// the socket stays bound.
+fn create_special_files(dir: &std::path::Path) -> std::os::unix::net::UnixListener {
+    fs::write(dir.join("a.txt"), "content").unwrap();
+    let status = StdCommand::new("mkfifo")
+        .arg(dir.join("pipe"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+    std::os::unix::net::UnixListener::bind(dir.join("sock")).unwrap()
+}
+
+#[test]
+fn test_cli_tar_stores_fifo_and_skips_socket() {
+    let tmp = TempDir::new().unwrap();
+    let src = tmp.path().join("src");
+    fs::create_dir_all(&src).unwrap();
+    let _listener = create_special_files(&src);
+
+    let output = tmp.path().join("out.tar");
+    raptar()
+        .arg(&src)
+        .arg("-f")
+        .arg("tar")
+        .arg("-o")
+        .arg(&output)
+        .timeout(std::time::Duration::from_secs(30))
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "Skipping socket sock: tar cannot store sockets",
+        ));
+
+    let mut archive = tar::Archive::new(fs::File::open(&output).unwrap());
+    let members: Vec<(String, tar::EntryType)> = archive
+        .entries()
+        .unwrap()
+        .map(|e| {
+            let e = e.unwrap();
+            (
+                e.path().unwrap().to_string_lossy().to_string(),
+                e.header().entry_type(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        members,
+        vec![
+            ("a.txt".to_string(), tar::EntryType::Regular),
+            ("pipe".to_string(), tar::EntryType::Fifo),
+        ]
+    );
+}
+
+#[test]
+fn test_cli_zip_skips_special_files() {
+    let tmp = TempDir::new().unwrap();
+    let src = tmp.path().join("src");
+    fs::create_dir_all(&src).unwrap();
+    let _listener = create_special_files(&src);
+
+    let output = tmp.path().join("out.zip");
+    raptar()
+        .arg(&src)
+        .arg("-f")
+        .arg("zip")
+        .arg("-o")
+        .arg(&output)
+        .timeout(std::time::Duration::from_secs(30))
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "Skipping FIFO pipe: zip cannot store special files",
+        ));
+
+    assert_eq!(
+        zip_methods(&output)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>(),
+        vec!["a.txt".to_string()]
+    );
+}
+
+#[test]
+fn test_cli_preview_shows_special_files() {
+    let tmp = TempDir::new().unwrap();
+    let _listener = create_special_files(tmp.path());
+
+    raptar()
+        .arg(tmp.path())
+        .arg("--preview")
+        .arg("--size")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("fifo pipe"))
+        .stdout(predicate::str::contains("socket sock"));
+}
+
+// ============================================================
+// Stdout streaming tests
+// ============================================================
+
+#[test]
+fn test_cli_stream_tar_gz_to_stdout() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+
+    let output = raptar()
+        .current_dir(tmp.path())
+        .arg(".")
+        .arg("-o")
+        .arg("-")
+        .arg("--size")
+        .arg("-v")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    // Only archive bytes on stdout; the listing and rules went to stderr
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Files to be archived"));
+    assert!(stderr.contains("*.log"));
+    assert!(stderr.contains("streamed to stdout"));
+
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(output.stdout.as_slice()));
+    let names: Vec<String> = archive
+        .entries()
+        .unwrap()
+        .map(|e| e.unwrap().path().unwrap().to_string_lossy().to_string())
+        .collect();
+    assert!(names.contains(&"main.rs".to_string()));
+    assert!(!tmp.path().join("-").exists());
+}
+
+#[test]
+fn test_cli_stream_zip_to_stdout() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+
+    let output = raptar()
+        .arg(tmp.path())
+        .arg("-f")
+        .arg("zip")
+        .arg("-o")
+        .arg("-")
+        .arg("-q")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let archive_path = tmp.path().join("streamed.zip");
+    fs::write(&archive_path, &output.stdout).unwrap();
+    assert!(zip_methods(&archive_path)
+        .iter()
+        .any(|(name, _)| name == "main.rs"));
+}
+
+// ============================================================
+// Prefix tests
+// ============================================================
+
+fn tar_gz_names(path: &std::path::Path) -> Vec<String> {
+    let file = fs::File::open(path).unwrap();
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+    archive
+        .entries()
+        .unwrap()
+        .map(|e| e.unwrap().path().unwrap().to_string_lossy().to_string())
+        .collect()
+}
+
+#[test]
+fn test_cli_prefix_places_members_under_directory() {
+    let tmp = TempDir::new().unwrap();
+    let project = tmp.path().join("proj");
+    create_test_project(&project);
+    let output = tmp.path().join("out.tar.gz");
+
+    raptar()
+        .arg(&project)
+        .arg("-o")
+        .arg(&output)
+        .arg("--prefix")
+        .arg("{dirname}-1.2.0/")
+        .arg("-q")
+        .assert()
+        .success();
+
+    let names = tar_gz_names(&output);
+    assert_eq!(names[0], "proj-1.2.0/");
+    assert!(names.contains(&"proj-1.2.0/main.rs".to_string()));
+    assert!(names.contains(&"proj-1.2.0/src/util.rs".to_string()));
+    assert!(names.iter().all(|n| n.starts_with("proj-1.2.0/")));
+}
+
+#[test]
+fn test_cli_prefix_applies_to_zip() {
+    let tmp = TempDir::new().unwrap();
+    let project = tmp.path().join("proj");
+    create_test_project(&project);
+    let output = tmp.path().join("out.zip");
+
+    raptar()
+        .arg(&project)
+        .arg("-f")
+        .arg("zip")
+        .arg("-o")
+        .arg(&output)
+        .arg("--prefix")
+        .arg("release/{dirname}")
+        .arg("-q")
+        .assert()
+        .success();
+
+    let names: Vec<String> = zip_methods(&output)
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    assert!(names.contains(&"release/proj/main.rs".to_string()));
+    assert!(names.iter().all(|n| n.starts_with("release/")));
+}
+
+#[test]
+fn test_cli_prefix_date_follows_mtime() {
+    let tmp = TempDir::new().unwrap();
+    let project = tmp.path().join("proj");
+    create_test_project(&project);
+    let output = tmp.path().join("out.tar.gz");
+
+    raptar()
+        .arg(&project)
+        .args(["-r", "--prefix", "{dirname}-{date}", "-q", "-o"])
+        .arg(&output)
+        .env("SOURCE_DATE_EPOCH", "1709640000")
+        .assert()
+        .success();
+    assert_eq!(tar_gz_names(&output)[0], "proj-2024-03-05/");
+
+    raptar()
+        .arg(&project)
+        .args(["--mtime", "2020-01-01", "--prefix", "{date}", "--preview"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2020-01-01/main.rs"));
+}
+
+#[test]
+fn test_cli_prefix_shown_in_preview() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+
+    raptar()
+        .arg(tmp.path())
+        .arg("--preview")
+        .arg("--prefix")
+        .arg("pkg")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("pkg/main.rs"));
+}
+
+#[test]
+fn test_cli_prefix_rejects_parent_components() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+
+    raptar()
+        .arg(tmp.path())
+        .arg("--preview")
+        .arg("--prefix")
+        .arg("../escape")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Prefix must be a relative path"));
+}
+
+#[test]
+fn test_cli_prefix_rejects_unknown_placeholder() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+
+    raptar()
+        .arg(tmp.path())
+        .arg("--preview")
+        .arg("--prefix")
+        .arg("{version}")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Unknown placeholder {version}"));
+}
+
+// ============================================================
+// Transform tests
+// ============================================================
+
+#[test]
+fn test_cli_transform_renames_members() {
+    let tmp = TempDir::new().unwrap();
+    let src = tmp.path().join("src");
+    fs::create_dir_all(src.join("dist/js")).unwrap();
+    fs::write(src.join("dist/index.html"), "<html>").unwrap();
+    fs::write(src.join("dist/js/app.js"), "app").unwrap();
+    fs::write(src.join("config.prod.toml"), "prod = true").unwrap();
+    let output = tmp.path().join("out.tar.gz");
+
+    raptar()
+        .arg(&src)
+        .arg("-o")
+        .arg(&output)
+        .arg("--transform")
+        .arg("s,^dist/,,")
+        .arg("--transform")
+        .arg(r"s,^config\.prod\.toml$,config.toml,")
+        .arg("-q")
+        .assert()
+        .success();
+
+    let names = tar_gz_names(&output);
+    assert!(names.contains(&"index.html".to_string()));
+    assert!(names.contains(&"js/app.js".to_string()));
+    assert!(names.contains(&"config.toml".to_string()));
+    assert!(!names.iter().any(|n| n.starts_with("dist/") && n != "dist/"));
+}
+
+#[test]
+fn test_cli_transform_shown_in_preview() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+
+    raptar()
+        .arg(tmp.path())
+        .arg("--preview")
+        .arg("--transform")
+        .arg("s,^src/,lib/,")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("lib/util.rs"))
+        .stdout(predicate::str::contains("src/util.rs").not());
+}
+
+#[test]
+fn test_cli_transform_detects_collisions() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+
+    raptar()
+        .arg(tmp.path())
+        .arg("--preview")
+        .arg("--transform")
+        .arg(r"s,\.rs$,.txt,")
+        .arg("--transform")
+        .arg("s,^lib,main,")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("both become main.txt"));
+}
+
+#[test]
+fn test_cli_transform_applies_to_symlink_targets() {
+    let tmp = TempDir::new().unwrap();
+    let src = tmp.path().join("src");
+    fs::create_dir_all(src.join("dist")).unwrap();
+    fs::write(src.join("dist/real.txt"), "data").unwrap();
+    std::os::unix::fs::symlink("dist/real.txt", src.join("link")).unwrap();
+    let output = tmp.path().join("out.tar.gz");
+
+    raptar()
+        .arg(&src)
+        .arg("-o")
+        .arg(&output)
+        .arg("--transform")
+        .arg("s,^dist/,,")
+        .arg("-q")
+        .assert()
+        .success();
+
+    let file = fs::File::open(&output).unwrap();
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+    let link = archive
+        .entries()
+        .unwrap()
+        .map(Result::unwrap)
+        .find(|e| e.path().unwrap().to_string_lossy() == "link")
+        .unwrap();
+    assert_eq!(
+        link.link_name().unwrap().unwrap().to_string_lossy(),
+        "real.txt"
+    );
+}
+
+#[test]
+fn test_cli_transform_rejects_invalid_expression() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+
+    raptar()
+        .arg(tmp.path())
+        .arg("--preview")
+        .arg("--transform")
+        .arg("s,a,b")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid transform"));
+}
+
+// ============================================================
+// Split archive tests
+// ============================================================
+
+#[test]
+fn test_cli_split_size_parts_rebuild_archive() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+    let noise: Vec<u8> = (0..20_000u32).flat_map(u32::to_le_bytes).collect();
+    fs::write(tmp.path().join("data.bin"), noise).unwrap();
+    let out_dir = TempDir::new().unwrap();
+    let output = out_dir.path().join("out.tar");
+
+    raptar()
+        .arg(tmp.path())
+        .arg("-f")
+        .arg("tar")
+        .arg("-o")
+        .arg(&output)
+        .arg("--split-size")
+        .arg("10K")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("parts"));
+
+    assert!(!output.exists());
+    let mut joined = Vec::new();
+    let mut index = 0;
+    while let Ok(part) = fs::read(out_dir.path().join(format!("out.tar.{index:03}"))) {
+        assert!(part.len() <= 10_000);
+        joined.extend(part);
+        index += 1;
+    }
+    assert!(index > 1);
+
+    let mut archive = tar::Archive::new(joined.as_slice());
+    let names: Vec<String> = archive
+        .entries()
+        .unwrap()
+        .map(|e| e.unwrap().path().unwrap().to_string_lossy().to_string())
+        .collect();
+    assert!(names.contains(&"data.bin".to_string()));
+
+    let manifest = fs::read_to_string(out_dir.path().join("out.tar.parts.toml")).unwrap();
+    assert!(manifest.contains("name = \"out.tar.000\""));
+    assert!(manifest.contains(&format!("size = {}", joined.len())));
+}
+
+#[test]
+fn test_cli_split_size_rejects_zip() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+
+    raptar()
+        .arg(tmp.path())
+        .arg("-f")
+        .arg("zip")
+        .arg("-o")
+        .arg(tmp.path().join("out.zip"))
+        .arg("--split-size")
+        .arg("1M")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("spanned"));
+}
+
+// ============================================================
+// Checksum tests
+// ============================================================
+
+fn sha256_hex(data: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    Sha256::digest(data)
+        .iter()
+        .fold(String::new(), |mut hex, b| {
+            let _ = write!(hex, "{b:02x}");
+            hex
+        })
+}
+
+#[test]
+fn test_cli_checksums_sidecar_matches_archive() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+    let out_dir = TempDir::new().unwrap();
+    let output = out_dir.path().join("out.tar.gz");
+
+    raptar()
+        .arg(tmp.path())
+        .args(["--checksums", "sha256", "-q", "-o"])
+        .arg(&output)
+        .assert()
+        .success();
+
+    let sidecar = fs::read_to_string(out_dir.path().join("out.tar.gz.sha256")).unwrap();
+    let expected = format!("{}  out.tar.gz\n", sha256_hex(&fs::read(&output).unwrap()));
+    assert_eq!(sidecar, expected);
+}
+
+#[test]
+fn test_cli_embed_checksums_in_tar() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+    let out_dir = TempDir::new().unwrap();
+    let output = out_dir.path().join("out.tar");
+
+    raptar()
+        .arg(tmp.path())
+        .args([
+            "-f",
+            "tar",
+            "--checksums",
+            "sha256",
+            "--embed-checksums",
+            "-q",
+            "-o",
+        ])
+        .arg(&output)
+        .assert()
+        .success();
+
+    let mut archive = tar::Archive::new(fs::File::open(&output).unwrap());
+    let mut sums = String::new();
+    for entry in archive.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        if entry.path().unwrap().to_str() == Some("SHA256SUMS") {
+            std::io::Read::read_to_string(&mut entry, &mut sums).unwrap();
+        }
+    }
+    let line = format!("{}  src/util.rs\n", sha256_hex(b"pub fn util() {}"));
+    assert!(sums.contains(&line), "{sums}");
+    assert!(!sums.contains("SHA256SUMS"));
+}
+
+#[test]
+fn test_cli_embed_checksums_in_zip() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+    let out_dir = TempDir::new().unwrap();
+    let output = out_dir.path().join("out.zip");
+
+    raptar()
+        .arg(tmp.path())
+        .args([
+            "-f",
+            "zip",
+            "--checksums",
+            "blake3",
+            "--embed-checksums",
+            "-q",
+            "-o",
+        ])
+        .arg(&output)
+        .assert()
+        .success();
+
+    assert!(out_dir.path().join("out.zip.b3").exists());
+    let mut archive = zip::ZipArchive::new(fs::File::open(&output).unwrap()).unwrap();
+    let mut sums = String::new();
+    std::io::Read::read_to_string(&mut archive.by_name("B3SUMS").unwrap(), &mut sums).unwrap();
+    assert!(sums.contains("  README.md\n"));
+    assert_eq!(
+        sums.lines()
+            .next()
+            .unwrap()
+            .split("  ")
+            .next()
+            .unwrap()
+            .len(),
+        64
+    );
+}
+
+#[test]
+fn test_cli_embed_checksums_requires_algorithm() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+
+    raptar()
+        .arg(tmp.path())
+        .arg("--embed-checksums")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--checksums"));
+}
+
+// ============================================================
+// Signature tests
+// ============================================================
+
+/// Writes an unencrypted minisign key pair and returns (secret, public).
+fn minisign_keys(dir: &std::path::Path) -> (std::path::PathBuf, std::path::PathBuf) {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use blake2::digest::consts::U32;
+    use blake2::{Blake2b, Digest};
+
+    let key = ed25519_dalek::SigningKey::from_bytes(&[42; 32]);
+    let id = [1u8, 2, 3, 4, 5, 6, 7, 8];
+    let keypair = key.to_keypair_bytes();
+    let checksum = Blake2b::<U32>::new()
+        .chain_update(b"Ed")
+        .chain_update(id)
+        .chain_update(keypair)
+        .finalize();
+    let mut secret = b"Ed\0\0B2".to_vec();
+    secret.extend([0u8; 48]);
+    secret.extend(id);
+    secret.extend(keypair);
+    secret.extend(checksum);
+    let public = [&b"Ed"[..], &id, key.verifying_key().as_bytes()].concat();
+
+    let (secret_path, public_path) = (dir.join("raptar.key"), dir.join("raptar.pub"));
+    let file =
+        |kind: &str, data: &[u8]| format!("untrusted comment: {kind}\n{}\n", STANDARD.encode(data));
+    fs::write(&secret_path, file("secret key", &secret)).unwrap();
+    fs::write(&public_path, file("public key", &public)).unwrap();
+    (secret_path, public_path)
+}
+
+#[test]
+fn test_cli_sign_and_verify_signature() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+    let out_dir = TempDir::new().unwrap();
+    // Keys inside the tree: the secret key must not end up in the archive
+    let (secret, public) = minisign_keys(tmp.path());
+    let output = out_dir.path().join("out.tar.gz");
+
+    raptar()
+        .arg(tmp.path())
+        .arg("--sign")
+        .arg(&secret)
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("out.tar.gz.minisig"));
+
+    let signature = fs::read_to_string(out_dir.path().join("out.tar.gz.minisig")).unwrap();
+    assert!(signature.contains("file:out.tar.gz\thashed"));
+    let names = tar_gz_names(&output);
+    assert!(names.contains(&"raptar.pub".to_string()));
+    assert!(!names.contains(&"raptar.key".to_string()));
+
+    raptar()
+        .arg("verify-signature")
+        .arg(&output)
+        .arg("-p")
+        .arg(&public)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("verified"));
+
+    let mut data = fs::read(&output).unwrap();
+    data.push(0);
+    fs::write(&output, data).unwrap();
+    raptar()
+        .arg("verify-signature")
+        .arg(&output)
+        .arg("-p")
+        .arg(&public)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("does not match"));
+}
+
+#[test]
+fn test_cli_sign_from_config_default() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+    let home = TempDir::new().unwrap();
+    let (secret, public) = minisign_keys(home.path());
+    let config_dir = home.path().join(".config/raptar");
+    fs::create_dir_all(&config_dir).unwrap();
+    fs::write(
+        config_dir.join("config.toml"),
+        "[defaults]\nsign = \"~/raptar.key\"\n",
+    )
+    .unwrap();
+    assert!(secret.exists());
+    let output = home.path().join("out.zip");
+
+    raptar()
+        .env("HOME", home.path())
+        .arg(tmp.path())
+        .args(["-f", "zip", "-q", "-o"])
+        .arg(&output)
+        .assert()
+        .success();
+
+    raptar()
+        .arg("verify-signature")
+        .arg(&output)
+        .arg("-p")
+        .arg(&public)
+        .assert()
+        .success();
+
+    // --no-sign overrides the config
+    fs::remove_file(home.path().join("out.zip.minisig")).unwrap();
+    raptar()
+        .env("HOME", home.path())
+        .arg(tmp.path())
+        .args(["-f", "zip", "-q", "--no-sign", "-o"])
+        .arg(&output)
+        .assert()
+        .success();
+    assert!(!home.path().join("out.zip.minisig").exists());
+}
+
+#[test]
+fn test_cli_sign_rejects_stdout() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+    let (secret, _) = minisign_keys(tmp.path());
+
+    raptar()
+        .arg(tmp.path())
+        .arg("--sign")
+        .arg(&secret)
+        .args(["-o", "-"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with -o -"));
+}
+
+// ============================================================
+// Encryption tests
+// ============================================================
+
+/// Writes a new age identity file and returns (identity file, recipient).
+fn age_identity(dir: &std::path::Path) -> (std::path::PathBuf, String) {
+    use age::secrecy::ExposeSecret;
+
+    let identity = age::x25519::Identity::generate();
+    let path = dir.join("identity.txt");
+    fs::write(&path, format!("{}\n", identity.to_string().expose_secret())).unwrap();
+    (path, identity.to_public().to_string())
+}
+
+#[test]
+fn test_cli_encrypt_to_age_recipient() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+    let keys = TempDir::new().unwrap();
+    let (identity, recipient) = age_identity(keys.path());
+    let out_dir = TempDir::new().unwrap();
+
+    // Without -o the age extension is added to the default name
+    raptar()
+        .current_dir(out_dir.path())
+        .arg(tmp.path())
+        .args(["-f", "tar.zst", "--encrypt-to", &recipient])
+        .assert()
+        .success();
+    let name = tmp.path().file_name().unwrap().to_string_lossy();
+    let output = out_dir.path().join(format!("{name}.tar.zst.age"));
+    assert!(fs::read(&output)
+        .unwrap()
+        .starts_with(b"age-encryption.org/v1\n"));
+
+    raptar()
+        .arg("list")
+        .arg(&output)
+        .arg("-i")
+        .arg(&identity)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("src/util.rs"));
+    raptar()
+        .arg("list")
+        .arg(&output)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("pass an identity file with -i"));
+
+    let dest = TempDir::new().unwrap();
+    raptar()
+        .arg("extract")
+        .arg(&output)
+        .arg("-C")
+        .arg(dest.path())
+        .arg("-i")
+        .arg(&identity)
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(dest.path().join("main.rs")).unwrap(),
+        "fn main() {}"
+    );
+}
+
+#[test]
+fn test_cli_encrypt_with_passphrase() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+    let out_dir = TempDir::new().unwrap();
+    let output = out_dir.path().join("out.tar.gz.age");
+
+    raptar()
+        .env("RAPTAR_PASSPHRASE", "correct horse")
+        .arg(tmp.path())
+        .arg("--passphrase")
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+
+    raptar()
+        .env("RAPTAR_PASSPHRASE", "correct horse")
+        .arg("list")
+        .arg(&output)
+        .arg("--cat")
+        .arg("README.md")
+        .assert()
+        .success()
+        .stdout("# Test Project");
+    raptar()
+        .env("RAPTAR_PASSPHRASE", "wrong")
+        .arg("list")
+        .arg(&output)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Failed to decrypt"));
+}
+
+#[test]
+fn test_cli_passphrase_rejects_empty_environment_value() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+    let out_dir = TempDir::new().unwrap();
+    let output = out_dir.path().join("out.tar.gz.age");
+
+    raptar()
+        .env("RAPTAR_PASSPHRASE", "")
+        .arg(tmp.path())
+        .arg("--passphrase")
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("RAPTAR_PASSPHRASE is empty"));
+    assert!(!output.exists());
+}
+
+#[test]
+fn test_cli_zip_passphrase_encrypts_members() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+    let out_dir = TempDir::new().unwrap();
+
+    let output = out_dir.path().join("secret.zip");
+    raptar()
+        .env("RAPTAR_PASSPHRASE", "zip secret")
+        .arg(tmp.path())
+        .args(["-f", "zip", "--passphrase", "-o"])
+        .arg(&output)
+        .assert()
+        .success();
+
+    let mut archive = zip::ZipArchive::new(fs::File::open(&output).unwrap()).unwrap();
+    let index = archive.index_for_name("main.rs").unwrap();
+    assert!(archive.by_index_raw(index).unwrap().encrypted());
+    assert!(archive.by_index(index).is_err());
+
+    let dest = TempDir::new().unwrap();
+    raptar()
+        .env("RAPTAR_PASSPHRASE", "zip secret")
+        .arg("extract")
+        .arg(&output)
+        .arg("-C")
+        .arg(dest.path())
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(dest.path().join("src/util.rs")).unwrap(),
+        "pub fn util() {}"
+    );
+    raptar()
+        .env("RAPTAR_PASSPHRASE", "not it")
+        .arg("list")
+        .arg(&output)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("wrong passphrase"));
+}
+
+#[test]
+fn test_cli_zip_passphrase_rejects_checksums_and_sign() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+    let keys = TempDir::new().unwrap();
+    let (secret, _) = minisign_keys(keys.path());
+
+    for extra in [
+        vec!["--checksums".into(), "sha256".into()],
+        vec!["--sign".into(), secret.display().to_string()],
+    ] {
+        raptar()
+            .env("RAPTAR_PASSPHRASE", "zip secret")
+            .arg(tmp.path())
+            .args(["-f", "zip", "--passphrase", "-o"])
+            .arg(keys.path().join("out.zip"))
+            .args(&extra)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "AES-encrypted members cannot be streamed",
+            ));
+    }
+    assert!(!keys.path().join("out.zip").exists());
+}
+
+#[test]
+fn test_cli_zip_rejects_encrypt_to() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+    let keys = TempDir::new().unwrap();
+    let (_, recipient) = age_identity(keys.path());
+
+    raptar()
+        .arg(tmp.path())
+        .args(["-f", "zip", "--encrypt-to", &recipient, "--preview"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("use --passphrase"));
+}
+
+// ============================================================
+// Verify tests
+// ============================================================
+
+#[test]
+fn test_cli_verify_matching_archive() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+    let out_dir = TempDir::new().unwrap();
+    let output = out_dir.path().join("out.tar.zst");
+
+    raptar()
+        .arg(tmp.path())
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+
+    raptar()
+        .arg("verify")
+        .arg(&output)
+        .arg(tmp.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Archive matches the tree"));
+}
+
+#[test]
+fn test_cli_verify_reports_differences() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+    let out_dir = TempDir::new().unwrap();
+    let output = out_dir.path().join("out.tar.gz");
+
+    raptar()
+        .arg(tmp.path())
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+
+    fs::write(tmp.path().join("main.rs"), "fn main() { changed(); }").unwrap();
+    fs::set_permissions(tmp.path().join("lib.rs"), fs::Permissions::from_mode(0o600)).unwrap();
+    fs::remove_file(tmp.path().join("README.md")).unwrap();
+    fs::write(tmp.path().join("new.rs"), "").unwrap();
+
+    raptar()
+        .arg("verify")
+        .arg(&output)
+        .arg(tmp.path())
+        .assert()
+        .failure()
+        .stdout(predicate::str::is_match(r"content\s+main\.rs").unwrap())
+        .stdout(predicate::str::is_match(r"metadata\s+lib\.rs: mode 0644, expected 0600").unwrap())
+        .stdout(predicate::str::is_match(r"extra\s+README\.md").unwrap())
+        .stdout(predicate::str::is_match(r"missing\s+new\.rs").unwrap())
+        .stderr(predicate::str::contains("4 difference(s)"));
+}
+
+#[test]
+fn test_cli_verify_uses_archiving_options() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+    let out_dir = TempDir::new().unwrap();
+    let output = out_dir.path().join("out.zip");
+
+    raptar()
+        .arg(tmp.path())
+        .args(["-r", "--prefix", "proj", "-o"])
+        .arg(&output)
+        .assert()
+        .success();
+
+    // The same options reproduce the member names, modes and mtimes
+    raptar()
+        .args(["-r", "--prefix", "proj", "verify"])
+        .arg(&output)
+        .arg(tmp.path())
+        .assert()
+        .success();
+    raptar()
+        .arg("verify")
+        .arg(&output)
+        .arg(tmp.path())
+        .assert()
+        .failure()
+        .stdout(predicate::str::is_match(r"missing\s+main\.rs").unwrap());
+}
+
+// ============================================================
+// Incremental tests
+// ============================================================
+
+/// Archives `src` as `out/<name>` with `--listed-incremental out/snapshot`.
+fn create_increment(
+    src: &std::path::Path,
+    out: &std::path::Path,
+    name: &str,
+) -> std::path::PathBuf {
+    let output = out.join(name);
+    raptar()
+        .arg(src)
+        .arg("-o")
+        .arg(&output)
+        .arg("--listed-incremental")
+        .arg(out.join("snapshot"))
+        .assert()
+        .success();
+    output
+}
+
+#[test]
+fn test_cli_listed_incremental_archives_changes() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+    let out = TempDir::new().unwrap();
+
+    let level0 = create_increment(tmp.path(), out.path(), "level0.tar.gz");
+    let names = tar_gz_names(&level0);
+    assert!(names.contains(&"main.rs".to_string()));
+    assert!(!names.contains(&".raptar-deleted".to_string()));
+    let snapshot = fs::read_to_string(out.path().join("snapshot")).unwrap();
+    assert!(snapshot.starts_with("raptar-snapshot 2\n"));
+    assert!(snapshot.contains(" src/util.rs\n"));
+
+    fs::write(tmp.path().join("main.rs"), "fn main() { changed(); }").unwrap();
+    fs::write(tmp.path().join("new.rs"), "").unwrap();
+    fs::remove_file(tmp.path().join("lib.rs")).unwrap();
+
+    let level1 = create_increment(tmp.path(), out.path(), "level1.tar.gz");
+    let mut names = tar_gz_names(&level1);
+    assert_eq!(names[0], ".raptar-deleted");
+    names.sort();
+    assert_eq!(names, [".raptar-deleted", "main.rs", "new.rs"]);
+
+    raptar()
+        .arg("list")
+        .arg(&level1)
+        .args(["--cat", ".raptar-deleted"])
+        .assert()
+        .success()
+        .stdout("lib.rs\n");
+}
+
+#[test]
+fn test_cli_restore_applies_increments() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+    let out = TempDir::new().unwrap();
+
+    let level0 = create_increment(tmp.path(), out.path(), "level0.tar.gz");
+    fs::write(
+        tmp.path().join("src/util.rs"),
+        "pub fn util() { changed() }",
+    )
+    .unwrap();
+    fs::remove_file(tmp.path().join("README.md")).unwrap();
+    let level1 = create_increment(tmp.path(), out.path(), "level1.tar.gz");
+    fs::remove_dir_all(tmp.path().join("src")).unwrap();
+    fs::write(tmp.path().join("new.rs"), "new").unwrap();
+    let level2 = create_increment(tmp.path(), out.path(), "level2.tar.gz");
+
+    let dest = TempDir::new().unwrap();
+    raptar()
+        .arg("restore")
+        .args([&level0, &level1, &level2])
+        .arg("-C")
+        .arg(dest.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Restored 3 archive(s)"));
+
+    assert_eq!(
+        fs::read_to_string(dest.path().join("new.rs")).unwrap(),
+        "new"
+    );
+    assert!(dest.path().join("main.rs").exists());
+    assert!(!dest.path().join("README.md").exists());
+    assert!(!dest.path().join("src").exists());
+    assert!(!dest.path().join(".raptar-deleted").exists());
+}
+
+#[test]
+fn test_cli_restore_replaces_directory_with_file() {
+    for format in ["tar.gz", "zip"] {
+        let tmp = TempDir::new().unwrap();
+        create_test_project(tmp.path());
+        let out = TempDir::new().unwrap();
+
+        let level0 = create_increment(tmp.path(), out.path(), &format!("level0.{format}"));
+        fs::remove_dir_all(tmp.path().join("src")).unwrap();
+        fs::write(tmp.path().join("src"), "now a file").unwrap();
+        let level1 = create_increment(tmp.path(), out.path(), &format!("level1.{format}"));
+
+        let dest = TempDir::new().unwrap();
+        raptar()
+            .arg("restore")
+            .args([&level0, &level1])
+            .arg("-C")
+            .arg(dest.path())
+            .assert()
+            .success();
+        assert_eq!(
+            fs::read_to_string(dest.path().join("src")).unwrap(),
+            "now a file",
+            "{format}"
+        );
+    }
+}
+
+#[test]
+fn test_cli_listed_incremental_preview_keeps_snapshot() {
+    let tmp = TempDir::new().unwrap();
+    create_test_project(tmp.path());
+    let out = TempDir::new().unwrap();
+    let snapshot = out.path().join("snapshot");
+
+    raptar()
+        .arg(tmp.path())
+        .arg("--preview")
+        .arg("--listed-incremental")
+        .arg(&snapshot)
+        .assert()
+        .success();
+    assert!(!snapshot.exists());
+}
+
+// ============================================================
+// List mode tests
+// ============================================================
+
+#[test]
+fn test_cli_list_shows_members() {
+    use std::os::unix::fs::symlink;
+
+    let tmp = TempDir::new().unwrap();
+    let src = tmp.path().join("src");
+    fs::create_dir_all(src.join("sub")).unwrap();
+    fs::write(src.join("a.txt"), "aaa").unwrap();
+    fs::write(src.join("sub/b.txt"), "bbb").unwrap();
+    symlink("a.txt", src.join("link.txt")).unwrap();
+
+    let output = tmp.path().join("out.tar.zst");
+    raptar()
+        .arg(&src)
+        .arg("-f")
+        .arg("tar.zst")
+        .arg("-o")
+        .arg(&output)
+        .arg("-q")
+        .assert()
+        .success();
+
+    raptar()
+        .arg("list")
+        .arg(&output)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("a.txt"))
+        .stdout(predicate::str::contains("sub/b.txt"))
+        .stdout(predicate::str::contains("link.txt -> a.txt"))
+        .stdout(predicate::str::contains("3 files (1 symlinks)"));
+}
+
+#[test]
+fn test_cli_list_long_format() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = TempDir::new().unwrap();
+    let src = tmp.path().join("src");
+    fs::create_dir_all(&src).unwrap();
+    fs::write(src.join("run.sh"), "#!/bin/sh").unwrap();
+    fs::set_permissions(src.join("run.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+
+    let output = tmp.path().join("out.zip");
+    raptar()
+        .arg(&src)
+        .arg("-f")
+        .arg("zip")
+        .arg("-o")
+        .arg(&output)
+        .arg("-q")
+        .assert()
+        .success();
+
+    raptar()
+        .arg("list")
+        .arg(&output)
+        .arg("--long")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("-rwxr-xr-x"))
+        .stdout(predicate::str::contains("run.sh"));
+}
+
+#[test]
+fn test_cli_list_shows_hard_links_and_owner_names() {
+    let tmp = TempDir::new().unwrap();
+    let src = tmp.path().join("src");
+    fs::create_dir_all(&src).unwrap();
+    fs::write(src.join("a.txt"), "shared").unwrap();
+    fs::hard_link(src.join("a.txt"), src.join("b.txt")).unwrap();
+
+    let output = tmp.path().join("out.tar");
+    raptar()
+        .arg(&src)
+        .args(["-f", "tar", "--owner", "app:1500", "-q", "-o"])
+        .arg(&output)
+        .assert()
+        .success();
+
+    raptar()
+        .arg("list")
+        .arg(&output)
+        .arg("--long")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("b.txt link to a.txt"))
+        .stdout(predicate::str::contains(" app/"));
+    raptar()
+        .arg("list")
+        .arg(&output)
+        .args(["--cat", "b.txt"])
+        .assert()
+        .success()
+        .stdout("shared");
+}
+
+#[test]
+fn test_cli_list_cat_member() {
+    let tmp = TempDir::new().unwrap();
+    let src = tmp.path().join("src");
+    fs::create_dir_all(src.join("conf")).unwrap();
+    fs::write(src.join("conf/app.toml"), "port = 8080\n").unwrap();
+    fs::write(src.join("other.txt"), "other").unwrap();
+
+    let output = tmp.path().join("out.tar.gz");
+    raptar()
+        .arg(&src)
+        .arg("-o")
+        .arg(&output)
+        .arg("-q")
+        .assert()
+        .success();
+
+    raptar()
+        .arg("list")
+        .arg(&output)
+        .arg("--cat")
+        .arg("conf/app.toml")
+        .assert()
+        .success()
+        .stdout("port = 8080\n");
+
+    raptar()
+        .arg("list")
+        .arg(&output)
+        .arg("--cat")
+        .arg("missing.txt")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Member not found"));
+}
@@ -861,17 +861,53 @@ mod edge_cases {
     }
 
     #[test]
-    fn empty_directory_not_in_archive() {
+    fn empty_directory_in_archive() {
         let tmp = TempDir::new().unwrap();
         fs::create_dir_all(tmp.path().join("empty_dir")).unwrap();
         create_file(tmp.path(), "file.txt", "content");
 
         let preview = get_preview(&tmp);
-        // Empty directories shouldn't appear (tar only stores files)
+        // Empty directories are archived as directory entries
+        assert!(preview.contains("file.txt"));
+        assert!(preview.contains("empty_dir/"));
+    }
+
+    #[test]
+    fn empty_directory_dropped_with_no_empty_dirs() {
+        let tmp = TempDir::new().unwrap();
+        fs::create_dir_all(tmp.path().join("empty_dir/nested")).unwrap();
+        create_file(tmp.path(), "file.txt", "content");
+
+        let preview = get_preview_with_args(&tmp, &["--no-empty-dirs"]);
         assert!(preview.contains("file.txt"));
         assert!(!preview.contains("empty_dir"));
     }
 
+    #[test]
+    fn directory_with_all_contents_excluded_not_in_archive() {
+        let tmp = TempDir::new().unwrap();
+        create_file(tmp.path(), ".gitignore", "build/\n");
+        create_file(tmp.path(), "build/out.o", "obj");
+        create_file(tmp.path(), "file.txt", "content");
+
+        let preview = get_preview(&tmp);
+        assert!(preview.contains("file.txt"));
+        assert!(!preview.contains("build"));
+    }
+
+    #[test]
+    fn directory_emptied_by_exclusions_not_in_archive() {
+        let tmp = TempDir::new().unwrap();
+        create_file(tmp.path(), ".gitignore", "cache/**\n");
+        create_file(tmp.path(), "cache/blob", "data");
+        fs::create_dir_all(tmp.path().join("logs")).unwrap();
+
+        // The directory itself is not excluded, but nothing in it is archived
+        let preview = get_preview(&tmp);
+        assert!(!preview.contains("cache"));
+        assert!(preview.contains("logs/"));
+    }
+
     #[test]
     fn case_sensitivity() {
         let tmp = TempDir::new().unwrap();